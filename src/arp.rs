@@ -1,42 +1,189 @@
+use crate::budget::ProbeBudget;
+use crate::console::STATUS;
+use crate::discovery::DiscoveryMethod;
+use crate::events::{emit, EventSender, ScanEvent};
+use crate::timing::{Jitter, TimingProfile};
 use anyhow::Result;
+use ipnetwork::Ipv4Network;
+use log::{debug, info, warn};
 use pnet::datalink::{self, NetworkInterface, DataLinkSender, DataLinkReceiver};
 use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
 use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
 use pnet::packet::Packet;
 use pnet::util::MacAddr;
-use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr};
 use std::process::Command;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Reply wait after the last request for a small sweep; larger sweeps wait
+/// longer, up to `MAX_RESPONSE_WINDOW`
+const MIN_RESPONSE_WINDOW: Duration = Duration::from_millis(200);
+const RESPONSE_WINDOW_PER_TARGET: Duration = Duration::from_micros(500);
+const MAX_RESPONSE_WINDOW: Duration = Duration::from_secs(3);
+/// A scaled window ends early once no new host has answered for this long
+const QUIET_PERIOD: Duration = Duration::from_millis(200);
+/// How long one read on the capture socket blocks before the loop rechecks its deadlines
+const RECEIVE_POLL: Duration = Duration::from_millis(10);
+/// ARP round trips under this are never called slow, however fast the rest of the subnet is
+const SLOW_ARP_FLOOR: Duration = Duration::from_millis(10);
+/// How many times the subnet's median round trip an ARP reply may take before it counts as slow
+const SLOW_ARP_FACTOR: u32 = 5;
+
+/// How long to collect replies after sending requests to `targets` hosts:
+/// 200ms plus 0.5ms per target, capped at 3s (328ms for a /24)
+pub fn response_window(targets: usize) -> Duration {
+    let scaled = MIN_RESPONSE_WINDOW + RESPONSE_WINDOW_PER_TARGET * targets.min(u32::MAX as usize) as u32;
+    scaled.min(MAX_RESPONSE_WINDOW)
+}
+
+/// `IFF_PROMISC` from `<net/if.h>`, as reported in the interface flags on Linux
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const IFF_PROMISC: u32 = 0x100;
 
 pub struct ArpScanner {
     interface: NetworkInterface,
+    /// The interface's IPv4 addresses with their prefixes; a multi-homed
+    /// interface has several
+    addresses: Vec<Ipv4Network>,
+    /// Sender address for requests; `None` picks the address on the targets' subnet
+    source_ip: Option<Ipv4Addr>,
     sender: Box<dyn DataLinkSender>,
-    receiver: Box<dyn DataLinkReceiver>,
+    /// Shared with the thread that reads replies during a sweep
+    receiver: Arc<Mutex<Box<dyn DataLinkReceiver>>>,
+    budget: Arc<ProbeBudget>,
+    /// Pause after each request so the interface isn't flooded
+    burst_delay: Duration,
+    /// Fixed reply wait; `None` scales it with the target count
+    response_window: Option<Duration>,
+    jitter: Jitter,
+    /// Stops sending and stops waiting for replies when cancelled
+    cancel: CancellationToken,
+    events: Option<EventSender>,
 }
 
 impl ArpScanner {
-    pub fn new(interface_name: &str) -> Result<Self> {
+    /// Open a datalink channel on `interface_name`.
+    ///
+    /// The channel is a raw packet socket, so this needs root or
+    /// `CAP_NET_RAW` (e.g. `setcap cap_net_raw+ep` on the binary) on Linux,
+    /// and read access to `/dev/bpf*` on macOS and the BSDs. It fails with a
+    /// permission error otherwise.
+    ///
+    /// With `promiscuous` set the capture socket also sees frames not addressed
+    /// to us; the kernel drops that again when the socket closes, so the
+    /// interface is restored once the scanner is dropped.
+    pub fn new(interface_name: &str, promiscuous: bool) -> Result<Self> {
         let interfaces = datalink::interfaces();
         let interface = interfaces
             .into_iter()
             .find(|iface| iface.name == interface_name)
             .ok_or_else(|| anyhow::anyhow!("Interface {} not found", interface_name))?;
 
-        let (sender, receiver) = match datalink::channel(&interface, Default::default()) {
+        let config = datalink::Config {
+            promiscuous,
+            read_timeout: Some(RECEIVE_POLL),
+            ..Default::default()
+        };
+        let (sender, receiver) = match datalink::channel(&interface, config) {
             Ok(datalink::Channel::Ethernet(tx, rx)) => (tx, rx),
             Ok(_) => return Err(anyhow::anyhow!("Unsupported channel type")),
             Err(e) => return Err(anyhow::anyhow!("Failed to create channel: {}", e)),
         };
 
+        // ARP only resolves on-link addresses, so remember the interface's subnets
+        let addresses = interface
+            .ips
+            .iter()
+            .filter_map(|ip| match ip {
+                pnet::ipnetwork::IpNetwork::V4(net) => Ipv4Network::new(net.ip(), net.prefix()).ok(),
+                _ => None,
+            })
+            .collect();
+
         Ok(ArpScanner {
             interface,
+            addresses,
+            source_ip: None,
             sender,
-            receiver,
+            receiver: Arc::new(Mutex::new(receiver)),
+            budget: Arc::new(ProbeBudget::default()),
+            burst_delay: TimingProfile::default().arp_burst_delay,
+            response_window: None,
+            jitter: Jitter::default(),
+            cancel: CancellationToken::new(),
+            events: None,
         })
     }
 
-    fn create_arp_request(&self, target_ip: Ipv4Addr) -> Vec<u8> {
+    /// Send requests from `ip`, one of the interface's addresses, and only ARP
+    /// targets on its subnet
+    pub fn with_source_ip(mut self, ip: Ipv4Addr) -> Self {
+        self.source_ip = Some(ip);
+        self
+    }
+
+    /// Count every ARP request against the scan's probe budget
+    pub fn with_budget(mut self, budget: Arc<ProbeBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Wait this long after sending each ARP request
+    pub fn with_burst_delay(mut self, burst_delay: Duration) -> Self {
+        self.burst_delay = burst_delay;
+        self
+    }
+
+    /// Collect replies for exactly this long after the last request instead of
+    /// the scaled window, which also stops once replies dry up
+    pub fn with_response_window(mut self, window: Duration) -> Self {
+        self.response_window = Some(window);
+        self
+    }
+
+    /// Pause a random time of up to `jitter` before each request, on top of the pacing
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// End sweeps early once `cancel` is cancelled
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Send a `HostDiscovered` event for each host that answers
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Send at most `packets_per_second` ARP requests per second
+    pub fn with_rate(self, packets_per_second: u32) -> Self {
+        self.with_burst_delay(Duration::from_secs(1) / packets_per_second.max(1))
+    }
+
+    /// Whether the interface itself was in promiscuous mode when the scanner
+    /// opened it, or `None` where the platform doesn't report it
+    pub fn interface_promiscuous(&self) -> Option<bool> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            Some(self.interface.flags & IFF_PROMISC != 0)
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            None
+        }
+    }
+
+    fn create_arp_request(&self, source_ip: Option<Ipv4Addr>, target_ip: Ipv4Addr) -> Vec<u8> {
         let mut ethernet_buffer = [0u8; 42];
         let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap();
 
@@ -54,8 +201,7 @@ impl ArpScanner {
         arp_packet.set_operation(ArpOperations::Request);
         arp_packet.set_sender_hw_addr(self.interface.mac.unwrap());
         
-        if let Some(source_ip) = self.interface.ips.iter()
-            .find_map(|ip| if let pnet::ipnetwork::IpNetwork::V4(net) = ip { Some(net.ip()) } else { None }) {
+        if let Some(source_ip) = source_ip {
             arp_packet.set_sender_proto_addr(source_ip);
         }
         
@@ -66,15 +212,52 @@ impl ArpScanner {
         ethernet_packet.packet().to_vec()
     }
 
-
-    pub async fn sweep(&mut self, ip_addresses: Vec<Ipv4Addr>) -> Vec<(Ipv4Addr, MacAddr)> {
+    /// ARP each of `ip_addresses` on the interface's subnet and return the
+    /// hosts that replied, with the round trip from request to reply.
+    /// Addresses off that subnet can't answer ARP, so they are skipped with a
+    /// status line naming the first few. Cancellation stops
+    /// sending and waiting for replies within one receive poll, and returns
+    /// the hosts that had already replied.
+    pub async fn sweep(&mut self, ip_addresses: Vec<Ipv4Addr>) -> Vec<(Ipv4Addr, MacAddr, Duration)> {
         // Use the new fast batch scanning method
         self.fast_arp_sweep(ip_addresses).await
     }
 
-    pub async fn fast_arp_sweep(&mut self, ip_addresses: Vec<Ipv4Addr>) -> Vec<(Ipv4Addr, MacAddr)> {
-        use std::collections::HashMap;
-        use tokio::time::{sleep, Duration};
+    /// Send every request in paced batches, then collect the replies; cancels as `sweep` does.
+    ///
+    /// Replies are read on a separate thread from the first request on and
+    /// stamped as they arrive, so a round trip doesn't count the time spent
+    /// sending to the rest of the subnet.
+    pub async fn fast_arp_sweep(&mut self, ip_addresses: Vec<Ipv4Addr>) -> Vec<(Ipv4Addr, MacAddr, Duration)> {
+        use std::collections::hash_map::{Entry, HashMap};
+        use tokio::time::{sleep_until, timeout};
+
+        if ip_addresses.is_empty() {
+            return Vec::new();
+        }
+
+        let source = match self.source_ip {
+            Some(ip) => self.addresses.iter().copied().find(|address| address.ip() == ip),
+            None => source_address(&self.addresses, &ip_addresses),
+        };
+        let source_ip = self.source_ip.or(source.map(|address| address.ip()));
+        let network = source.and_then(|address| Ipv4Network::new(address.network(), address.prefix()).ok());
+        if self.addresses.len() > 1 {
+            debug!("ARP requests go out from {:?} for {:?}", source_ip, network);
+        }
+        let (ip_addresses, off_subnet) = split_off_subnet(network, ip_addresses);
+        if !off_subnet.is_empty() {
+            let examples: Vec<String> = off_subnet.iter().take(5).map(|ip| ip.to_string()).collect();
+            let more = if off_subnet.len() > 5 { ", ..." } else { "" };
+            info!(
+                target: STATUS,
+                "Skipping {} off-subnet ARP targets (not on {}): {}{}",
+                off_subnet.len(),
+                network.map(|net| net.to_string()).unwrap_or_default(),
+                examples.join(", "),
+                more
+            );
+        }
 
         if ip_addresses.is_empty() {
             return Vec::new();
@@ -85,57 +268,188 @@ impl ArpScanner {
 
         // Send all ARP requests rapidly in batches
         const BATCH_SIZE: usize = 100;
-        const BURST_DELAY: Duration = Duration::from_micros(100); // 100μs between packets
-        const RESPONSE_WINDOW: Duration = Duration::from_millis(200); // Total response collection time
+        let (response_window, quiet_period) = match self.response_window {
+            Some(window) => (window, window),
+            None => (response_window(total_targets), QUIET_PERIOD),
+        };
 
-        println!("Sending {} ARP requests...", total_targets);
+        info!(target: STATUS, "Sending {} ARP requests...", total_targets);
+        info!("ARP sweep on {}: {} targets, {:?} between requests", self.interface.name, total_targets, self.burst_delay);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (reply_sender, mut replies) = tokio::sync::mpsc::unbounded_channel();
+        let reader = std::thread::spawn({
+            let receiver = Arc::clone(&self.receiver);
+            let stop = Arc::clone(&stop);
+            let interface = self.interface.name.clone();
+            move || {
+                let mut receiver = receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                while !stop.load(Ordering::Relaxed) {
+                    match receiver.next() {
+                        Ok(frame) => {
+                            if let Some(reply) = parse_arp_reply(frame) {
+                                // Nobody left to collect replies
+                                if reply_sender.send((reply, Instant::now())).is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        // The read timeout: nothing arrived within RECEIVE_POLL
+                        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                        Err(e) => {
+                            warn!("ARP receive on {} failed: {}", interface, e);
+                            break;
+                        }
+                    }
+                }
+            }
+        });
 
         // Send all requests in batches
-        for chunk in ip_addresses.chunks(BATCH_SIZE) {
+        let send_started = Instant::now();
+        let mut sent_at = HashMap::new();
+        let mut sent: u32 = 0;
+        'send: for chunk in ip_addresses.chunks(BATCH_SIZE) {
             for &ip in chunk {
-                let arp_request = self.create_arp_request(ip);
-                let _ = self.sender.send_to(&arp_request, None);
+                if self.cancel.is_cancelled() || !self.budget.try_take() {
+                    break 'send;
+                }
+                self.cancel.run_until_cancelled(self.jitter.wait("arp-jitter", ip.into(), 0)).await;
+                let arp_request = self.create_arp_request(source_ip, ip);
+                sent_at.entry(ip).or_insert_with(Instant::now);
+                match self.sender.send_to(&arp_request, None) {
+                    Some(Err(e)) => warn!("ARP request to {} failed: {}", ip, e),
+                    None => warn!("ARP request to {} not sent: channel closed", ip),
+                    Some(Ok(())) => {}
+                }
 
-                // Small delay to avoid overwhelming the network interface
-                sleep(BURST_DELAY).await;
+                // Pace against a schedule rather than sleeping per packet: the
+                // timer only has millisecond resolution, far coarser than the delay
+                sent += 1;
+                if !self.burst_delay.is_zero() {
+                    let due = send_started + self.burst_delay * sent;
+                    if due > Instant::now() {
+                        self.cancel.run_until_cancelled(sleep_until(due.into())).await;
+                    }
+                }
             }
         }
 
-        // Collect responses for a short window
+        // Collect replies until the window closes, every target has answered,
+        // or (for a scaled window) no new host has answered for a while
         let start_time = Instant::now();
+        let mut last_new_reply = start_time;
         let mut responses_received = 0;
 
-        while start_time.elapsed() < RESPONSE_WINDOW {
-            // Try to read multiple packets in a tight loop
-            for _ in 0..50 { // Read up to 50 packets per iteration
-                match self.receiver.next() {
-                    Ok(packet) => {
-                        if let Some(ethernet_packet) = EthernetPacket::new(packet) {
-                            if ethernet_packet.get_ethertype() == EtherTypes::Arp {
-                                if let Some(arp_packet) = ArpPacket::new(ethernet_packet.payload()) {
-                                    if arp_packet.get_operation() == ArpOperations::Reply {
-                                        let sender_ip = arp_packet.get_sender_proto_addr();
-                                        let sender_mac = arp_packet.get_sender_hw_addr();
-                                        if ip_addresses.contains(&sender_ip) {
-                                            discovered_hosts.insert(sender_ip, sender_mac);
-                                            responses_received += 1;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(_) => break, // No more packets available right now
+        while start_time.elapsed() < response_window
+            && last_new_reply.elapsed() < quiet_period
+            && discovered_hosts.len() < sent as usize
+            && !self.cancel.is_cancelled()
+        {
+            let Ok(reply) = timeout(RECEIVE_POLL, replies.recv()).await else {
+                continue;
+            };
+            // The reader thread gave up
+            let Some(((sender_ip, sender_mac), received)) = reply else {
+                break;
+            };
+            // Replies that came before our request weren't answers to it
+            let requested = sent_at.get(&sender_ip).filter(|&&requested| requested <= received);
+            if let Some(requested) = requested {
+                let rtt = received - *requested;
+                debug!("ARP reply from {} ({}) after {:?}", sender_ip, sender_mac, rtt);
+                if let Entry::Vacant(entry) = discovered_hosts.entry(sender_ip) {
+                    entry.insert((sender_mac, rtt));
+                    last_new_reply = Instant::now();
+                    emit(
+                        &self.events,
+                        ScanEvent::HostDiscovered { ip: sender_ip.into(), method: DiscoveryMethod::Arp, mac: Some(sender_mac.to_string()) },
+                    );
                 }
+                responses_received += 1;
+            } else {
+                debug!("Unrequested ARP reply from {} ({}), ignored", sender_ip, sender_mac);
             }
-
-            // Very short sleep to allow more responses to arrive
-            sleep(Duration::from_millis(1)).await;
         }
+        stop.store(true, Ordering::Relaxed);
+        drop(replies);
+        // Exits within one receive poll
+        let _ = reader.join();
+        debug!("ARP replies collected for {:?}", start_time.elapsed());
+
+        info!(target: STATUS, "ARP scan completed: {} responses received", responses_received);
+        discovered_hosts.into_iter().map(|(ip, (mac, rtt))| (ip, mac, rtt)).collect()
+    }
+}
+
+/// Hosts whose ARP reply took over five times the median round trip (and at
+/// least 10ms): a wireless client, a bridge, or a misconfigured proxy ARP on
+/// the way, where a wired neighbour answers in well under a millisecond
+pub fn slow_arp_replies(rtts: &[(Ipv4Addr, Duration)]) -> Vec<Ipv4Addr> {
+    let mut sorted: Vec<Duration> = rtts.iter().map(|(_, rtt)| *rtt).collect();
+    sorted.sort();
+    let Some(median) = sorted.get(sorted.len() / 2) else {
+        return Vec::new();
+    };
+    let threshold = (*median * SLOW_ARP_FACTOR).max(SLOW_ARP_FLOOR);
+    rtts.iter().filter(|(_, rtt)| *rtt > threshold).map(|(ip, _)| *ip).collect()
+}
 
-        println!("ARP scan completed: {} responses received", responses_received);
-        discovered_hosts.into_iter().collect()
+/// Sender address of an ARP reply frame; anything else is `None`
+fn parse_arp_reply(frame: &[u8]) -> Option<(Ipv4Addr, MacAddr)> {
+    let ethernet_packet = EthernetPacket::new(frame)?;
+    if ethernet_packet.get_ethertype() != EtherTypes::Arp {
+        return None;
     }
+    let arp_packet = ArpPacket::new(ethernet_packet.payload())?;
+    (arp_packet.get_operation() == ArpOperations::Reply)
+        .then(|| (arp_packet.get_sender_proto_addr(), arp_packet.get_sender_hw_addr()))
+}
+
+/// The interface address to ARP `targets` from: the one whose subnet holds
+/// most of them, the first on a tie. Hosts ignore requests from a sender
+/// that isn't on their network.
+fn source_address(addresses: &[Ipv4Network], targets: &[Ipv4Addr]) -> Option<Ipv4Network> {
+    addresses
+        .iter()
+        .enumerate()
+        .max_by_key(|(index, address)| (targets.iter().filter(|ip| address.contains(**ip)).count(), std::cmp::Reverse(*index)))
+        .map(|(_, address)| *address)
+}
+
+/// Split targets into those on the interface's subnet and those that ARP can't reach.
+/// Off-subnet hosts are not asked about through the gateway's MAC either: a
+/// router answers ARP only for itself, so they are left to the ping sweep
+fn split_off_subnet(network: Option<Ipv4Network>, targets: Vec<Ipv4Addr>) -> (Vec<Ipv4Addr>, Vec<Ipv4Addr>) {
+    match network {
+        Some(network) => targets.into_iter().partition(|ip| network.contains(*ip)),
+        // Without a known subnet there is nothing to validate against
+        None => (targets, Vec::new()),
+    }
+}
+
+/// A MAC address that answered for more than one IP
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateMac {
+    pub mac: String,
+    pub ips: Vec<String>,
+}
+
+/// Group hosts by MAC and keep every MAC claiming several IPs, which is
+/// either a router doing proxy ARP or someone spoofing ARP replies
+pub fn find_duplicate_macs(mac_addresses: &HashMap<IpAddr, String>) -> Vec<DuplicateMac> {
+    let mut by_mac: BTreeMap<String, Vec<IpAddr>> = BTreeMap::new();
+    for (ip, mac) in mac_addresses {
+        by_mac.entry(mac.to_lowercase()).or_default().push(*ip);
+    }
+    by_mac
+        .into_iter()
+        .filter(|(_, ips)| ips.len() > 1)
+        .map(|(mac, mut ips)| {
+            ips.sort();
+            DuplicateMac { mac, ips: ips.iter().map(IpAddr::to_string).collect() }
+        })
+        .collect()
 }
 
 /// Read MAC addresses from the system's ARP cache
@@ -168,12 +482,100 @@ mod tests {
     use super::*;
     use std::str::FromStr;
 
+    #[test]
+    fn test_parse_arp_reply() {
+        let frame = |operation| {
+            let mut arp_buffer = [0u8; 28];
+            let mut arp = MutableArpPacket::new(&mut arp_buffer).unwrap();
+            arp.set_operation(operation);
+            arp.set_sender_hw_addr(MacAddr::new(0xaa, 0xbb, 0xcc, 0, 0, 1));
+            arp.set_sender_proto_addr(Ipv4Addr::new(192, 168, 1, 1));
+            let mut ethernet_buffer = [0u8; 42];
+            let mut ethernet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap();
+            ethernet.set_ethertype(EtherTypes::Arp);
+            ethernet.set_payload(arp.packet());
+            ethernet_buffer
+        };
+
+        assert_eq!(
+            parse_arp_reply(&frame(ArpOperations::Reply)),
+            Some((Ipv4Addr::new(192, 168, 1, 1), MacAddr::new(0xaa, 0xbb, 0xcc, 0, 0, 1)))
+        );
+        assert_eq!(parse_arp_reply(&frame(ArpOperations::Request)), None);
+        assert_eq!(parse_arp_reply(&[0u8; 10]), None);
+    }
+
+    #[test]
+    fn test_find_duplicate_macs() {
+        let ip = |s: &str| IpAddr::from_str(s).unwrap();
+        let mac_addresses = HashMap::from([
+            (ip("192.168.1.1"), "aa:bb:cc:00:00:01".to_string()),
+            (ip("192.168.1.20"), "AA:BB:CC:00:00:01".to_string()),
+            (ip("192.168.1.3"), "aa:bb:cc:00:00:01".to_string()),
+            (ip("192.168.1.10"), "aa:bb:cc:00:00:02".to_string()),
+        ]);
+
+        assert_eq!(
+            find_duplicate_macs(&mac_addresses),
+            vec![DuplicateMac {
+                mac: "aa:bb:cc:00:00:01".to_string(),
+                ips: vec!["192.168.1.1".to_string(), "192.168.1.3".to_string(), "192.168.1.20".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_slow_arp_replies() {
+        let ms = Duration::from_millis;
+        let host = |last| Ipv4Addr::new(192, 168, 1, last);
+        let rtts = [(host(1), ms(1)), (host(2), ms(2)), (host(3), ms(1)), (host(4), ms(40)), (host(5), ms(8))];
+        assert_eq!(slow_arp_replies(&rtts), vec![host(4)]);
+        // A uniformly slow subnet has nothing anomalous in it
+        assert!(slow_arp_replies(&[(host(1), ms(30)), (host(2), ms(35))]).is_empty());
+        assert!(slow_arp_replies(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_response_window() {
+        assert_eq!(response_window(0), Duration::from_millis(200));
+        assert_eq!(response_window(256), Duration::from_millis(328));
+        assert_eq!(response_window(65536), Duration::from_secs(3));
+    }
+
     #[tokio::test]
     async fn test_arp_scanner_creation() {
         let interfaces = datalink::interfaces();
         if let Some(interface) = interfaces.first() {
-            let result = ArpScanner::new(&interface.name);
+            let result = ArpScanner::new(&interface.name, false);
             assert!(result.is_ok() || result.is_err()); // Either works or needs privileges
         }
     }
+
+    #[test]
+    fn test_source_address() {
+        let addresses = [Ipv4Network::from_str("192.168.1.5/24").unwrap(), Ipv4Network::from_str("10.20.0.5/16").unwrap()];
+        let targets = [Ipv4Addr::new(10, 20, 3, 1), Ipv4Addr::new(10, 20, 3, 2), Ipv4Addr::new(192, 168, 1, 1)];
+        assert_eq!(source_address(&addresses, &targets), Some(addresses[1]));
+        assert_eq!(source_address(&addresses, &targets[2..]), Some(addresses[0]));
+        assert_eq!(source_address(&addresses, &[Ipv4Addr::new(172, 16, 0, 1)]), Some(addresses[0]));
+        assert_eq!(source_address(&[], &targets), None);
+    }
+
+    #[test]
+    fn test_split_off_subnet() {
+        let network = Ipv4Network::from_str("192.168.1.0/24").unwrap();
+        let targets = vec![
+            Ipv4Addr::from_str("192.168.1.10").unwrap(),
+            Ipv4Addr::from_str("10.0.0.1").unwrap(),
+            Ipv4Addr::from_str("192.168.1.254").unwrap(),
+        ];
+
+        let (on_subnet, off_subnet) = split_off_subnet(Some(network), targets.clone());
+        assert_eq!(on_subnet, vec![targets[0], targets[2]]);
+        assert_eq!(off_subnet, vec![targets[1]]);
+
+        let (on_subnet, off_subnet) = split_off_subnet(None, targets.clone());
+        assert_eq!(on_subnet, targets);
+        assert!(off_subnet.is_empty());
+    }
 }
\ No newline at end of file
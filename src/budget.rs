@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Caps the outbound probes of a whole scan: ICMP echoes, TCP connects and
+/// ARP requests alike, retries included. Shared by every scanner.
+#[derive(Debug, Default)]
+pub struct ProbeBudget {
+    limit: Option<u64>,
+    sent: AtomicU64,
+    refused: AtomicBool,
+}
+
+impl ProbeBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        ProbeBudget { limit, ..Default::default() }
+    }
+
+    /// Account for one probe, or return false if the budget is spent and the
+    /// probe must not be sent
+    pub fn try_take(&self) -> bool {
+        let taken = self
+            .sent
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sent| match self.limit {
+                Some(limit) if sent >= limit => None,
+                _ => Some(sent + 1),
+            })
+            .is_ok();
+        if !taken {
+            self.refused.store(true, Ordering::Relaxed);
+        }
+        taken
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    /// Whether any probe was held back for lack of budget
+    pub fn exhausted(&self) -> bool {
+        self.refused.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_budget() {
+        let budget = ProbeBudget::new(Some(2));
+        assert!(budget.try_take());
+        assert!(budget.try_take());
+        assert!(!budget.exhausted());
+        assert!(!budget.try_take());
+        assert!(budget.exhausted());
+        assert_eq!(budget.sent(), 2);
+
+        let unlimited = ProbeBudget::new(None);
+        assert!((0..1000).all(|_| unlimited.try_take()));
+        assert_eq!(unlimited.sent(), 1000);
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file looked for in the working directory and `$XDG_CONFIG_HOME`
+pub const CONFIG_FILE_NAME: &str = "angryether.toml";
+
+/// Like `-T`, the timing profile can be a level or a name: `timing = 4` or `timing = "aggressive"`
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+enum TimingSetting {
+    Level(u8),
+    Name(String),
+}
+
+/// Default options from `angryether.toml`. Keys are the long flag names;
+/// each value stands in for the flag's built-in default, so a flag given on
+/// the command line still wins.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    interface: Option<String>,
+    timeout: Option<u64>,
+    port_timeout: Option<u64>,
+    ports: Option<String>,
+    timing: Option<TimingSetting>,
+    json: Option<String>,
+    jsonl: Option<String>,
+    msgpack: Option<String>,
+    csv: Option<String>,
+    grepable: Option<String>,
+    output: Option<String>,
+    report_html: Option<String>,
+    sqlite: Option<String>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Each value set in the file, keyed by the id of the flag it defaults
+    pub fn defaults(&self) -> Vec<(&'static str, String)> {
+        let timing = self.timing.as_ref().map(|timing| match timing {
+            TimingSetting::Level(level) => level.to_string(),
+            TimingSetting::Name(name) => name.clone(),
+        });
+        [
+            ("interface", self.interface.clone()),
+            ("timeout", self.timeout.map(|ms| ms.to_string())),
+            ("port-timeout", self.port_timeout.map(|ms| ms.to_string())),
+            ("ports", self.ports.clone()),
+            ("timing", timing),
+            ("json", self.json.clone()),
+            ("jsonl", self.jsonl.clone()),
+            ("msgpack", self.msgpack.clone()),
+            ("csv", self.csv.clone()),
+            ("grepable", self.grepable.clone()),
+            ("output", self.output.clone()),
+            ("report-html", self.report_html.clone()),
+            ("sqlite", self.sqlite.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(id, value)| Some((id, value?)))
+        .collect()
+    }
+}
+
+pub fn read_config(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    Config::parse(&text).with_context(|| format!("invalid config {}", path.display()))
+}
+
+/// The `--config` value in `args`, found before clap runs because the file
+/// supplies clap's defaults
+pub fn explicit_config_path(args: impl IntoIterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// `./angryether.toml`, then `$XDG_CONFIG_HOME/angryether.toml` (`~/.config`
+/// when unset), whichever exists first
+pub fn find_config() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    std::iter::once(PathBuf::from(CONFIG_FILE_NAME))
+        .chain(config_home.map(|dir| dir.join(CONFIG_FILE_NAME)))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_defaults() {
+        let config = Config::parse("interface = \"eth0\"\ntiming = 4\nports = \"22,80,443\"\nreport-html = \"scan.html\"\n").unwrap();
+        assert_eq!(
+            config.defaults(),
+            vec![
+                ("interface", "eth0".to_string()),
+                ("ports", "22,80,443".to_string()),
+                ("timing", "4".to_string()),
+                ("report-html", "scan.html".to_string()),
+            ]
+        );
+        assert_eq!(Config::parse("timing = \"aggressive\"").unwrap().defaults(), vec![("timing", "aggressive".to_string())]);
+        // Typos fail loudly rather than being ignored
+        assert!(Config::parse("interfce = \"eth0\"").is_err());
+        assert!(Config::parse("").unwrap().defaults().is_empty());
+    }
+
+    #[test]
+    fn test_explicit_config_path() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(explicit_config_path(args(&["angryether", "-i", "eth0", "--config", "a.toml"])), Some(PathBuf::from("a.toml")));
+        assert_eq!(explicit_config_path(args(&["angryether", "--config=b.toml"])), Some(PathBuf::from("b.toml")));
+        assert_eq!(explicit_config_path(args(&["angryether", "-i", "eth0"])), None);
+    }
+}
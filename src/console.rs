@@ -0,0 +1,103 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// `log` target of the status lines the scanners report as they go. The CLI
+/// prints these like its own `status!` lines; an embedding app gets them
+/// only through whatever logger it installs.
+pub const STATUS: &str = "angryether::status";
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static PROGRESS_ENABLED: AtomicBool = AtomicBool::new(true);
+/// The bar on screen, if any; status lines are printed around it
+static PROGRESS: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Silence progress and status output, e.g. when only a count is wanted
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Whether `start_progress` shows a bar; status lines are unaffected
+pub fn set_progress_enabled(enabled: bool) {
+    PROGRESS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Put up a progress bar counting `len` units of `unit`, replacing any
+/// previous one. The bar is hidden when output is quiet or bars are disabled.
+pub fn start_progress(len: u64, unit: &str) -> ProgressBar {
+    let bar = if is_quiet() || !PROGRESS_ENABLED.load(Ordering::Relaxed) {
+        ProgressBar::hidden()
+    } else {
+        let style = ProgressStyle::with_template("{spinner} [{elapsed_precise}] [{bar:30}] {pos}/{len} {msg} (ETA {eta})")
+            .expect("valid progress template")
+            .progress_chars("=> ");
+        ProgressBar::new(len).with_style(style).with_message(unit.to_string())
+    };
+    if let Some(previous) = PROGRESS.lock().unwrap().replace(bar.clone()) {
+        previous.finish_and_clear();
+    }
+    bar
+}
+
+/// Take down the current progress bar
+pub fn finish_progress() {
+    if let Some(bar) = PROGRESS.lock().unwrap().take() {
+        bar.finish_and_clear();
+    }
+}
+
+/// Print a status line, clearing the progress bar around it so neither gets mangled
+pub fn print_status(args: fmt::Arguments) {
+    match PROGRESS.lock().unwrap().as_ref() {
+        Some(bar) => bar.suspend(|| println!("{}", args)),
+        None => println!("{}", args),
+    }
+}
+
+/// Ask a yes/no question on stderr and read the answer from stdin; anything
+/// but "y" or "yes" is a no. `None` when stdin isn't a terminal to ask.
+pub fn confirm(question: &str) -> Option<bool> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+    finish_progress();
+    eprint!("{} [y/N] ", question);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok()?;
+    Some(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// stderr for log records, clearing the progress bar around each one
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match PROGRESS.lock().unwrap().as_ref() {
+            Some(bar) => bar.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// `println!` for progress and status lines that `set_quiet` can silence
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::console::is_quiet() {
+            $crate::console::print_status(format_args!($($arg)*));
+        }
+    };
+}
+
+pub use status;
@@ -0,0 +1,90 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Environment variable naming an extra data directory to search first
+pub const DATA_DIR_ENV: &str = "ANGRYETHER_DATA_DIR";
+
+/// Where packaged installs put ports lists and other data files
+const SYSTEM_DATA_DIRS: &[&str] = &[
+    "/usr/local/share/angryether",
+    "/usr/share/angryether",
+    "/opt/angryether",
+];
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Search DIR before anything else (from `--data-dir`)
+pub fn set_data_dir(dir: PathBuf) {
+    let _ = DATA_DIR.set(dir);
+}
+
+/// Data directories in search order: `--data-dir`, `$ANGRYETHER_DATA_DIR`,
+/// the working directory, then the system install locations
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = DATA_DIR.get() {
+        dirs.push(dir.clone());
+    }
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs.push(PathBuf::from("."));
+    dirs.extend(SYSTEM_DATA_DIRS.iter().map(PathBuf::from));
+    dirs
+}
+
+fn resolve_in(dirs: &[PathBuf], name: &str) -> Result<PathBuf> {
+    let candidates: Vec<PathBuf> = dirs.iter().map(|dir| dir.join(name)).collect();
+    candidates
+        .iter()
+        .find(|path| path.exists())
+        .cloned()
+        .ok_or_else(|| {
+            let tried: Vec<String> = candidates.iter().map(|path| path.display().to_string()).collect();
+            anyhow::anyhow!("data file '{}' not found. Tried:\n{}", name, tried.join("\n"))
+        })
+}
+
+/// Find a data file such as `ports/10000.txt` in the data directories
+pub fn resolve_data_file(name: &str) -> Result<PathBuf> {
+    resolve_in(&search_dirs(), name)
+}
+
+/// Open `path` as given if it exists, otherwise look for a file of the same
+/// name under `category` in the data directories
+pub fn resolve_user_file(path: &str, category: &str) -> Result<PathBuf> {
+    if Path::new(path).exists() {
+        return Ok(PathBuf::from(path));
+    }
+    let filename = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("'{}' not found", path))?;
+    resolve_data_file(&format!("{}/{}", category, filename))
+        .map_err(|e| anyhow::anyhow!("'{}' not found and no installed copy exists: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_resolve_in_search_order() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        fs::create_dir(second.path().join("ports")).unwrap();
+        fs::write(second.path().join("ports/custom.txt"), "22").unwrap();
+        let dirs = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+
+        assert_eq!(resolve_in(&dirs, "ports/custom.txt").unwrap(), second.path().join("ports/custom.txt"));
+
+        fs::create_dir(first.path().join("ports")).unwrap();
+        fs::write(first.path().join("ports/custom.txt"), "80").unwrap();
+        assert_eq!(resolve_in(&dirs, "ports/custom.txt").unwrap(), first.path().join("ports/custom.txt"));
+
+        let missing = resolve_in(&dirs, "ports/missing.txt").unwrap_err().to_string();
+        assert!(missing.contains(&first.path().join("ports/missing.txt").display().to_string()));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{HostResult, ScanResults};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A port that opened or closed between two runs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PortChange {
+    pub ip: String,
+    pub port: u16,
+    pub protocol: String,
+}
+
+/// A host that answered ARP with a different MAC than last time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MacChange {
+    pub ip: String,
+    pub old_mac: String,
+    pub new_mac: String,
+}
+
+/// What changed from one scan to the next, as reported by `--compare`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanDiff {
+    pub new_hosts: Vec<String>,
+    pub disappeared_hosts: Vec<String>,
+    /// Ports open now but not last time, on hosts seen in both runs
+    pub opened_ports: Vec<PortChange>,
+    /// Ports open last time but not now, on hosts seen in both runs
+    pub closed_ports: Vec<PortChange>,
+    /// Hosts whose MAC changed, which may be ARP spoofing (or replaced hardware)
+    pub changed_macs: Vec<MacChange>,
+}
+
+impl ScanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.new_hosts.is_empty()
+            && self.disappeared_hosts.is_empty()
+            && self.opened_ports.is_empty()
+            && self.closed_ports.is_empty()
+            && self.changed_macs.is_empty()
+    }
+}
+
+fn hosts_by_ip(results: &ScanResults) -> BTreeMap<&str, &HostResult> {
+    results.hosts.iter().map(|host| (host.ip.as_str(), host)).collect()
+}
+
+fn port_set(host: &HostResult) -> BTreeSet<(u16, &str)> {
+    host.open_ports.iter().map(|port| (port.port, port.protocol.as_str())).collect()
+}
+
+/// Compare two runs host by host. Hosts and ports come out in sorted order so
+/// the same pair of scans always gives the same diff.
+pub fn diff_results(old: &ScanResults, new: &ScanResults) -> ScanDiff {
+    let old_hosts = hosts_by_ip(old);
+    let new_hosts = hosts_by_ip(new);
+    let mut diff = ScanDiff::default();
+
+    for (ip, new_host) in &new_hosts {
+        let Some(old_host) = old_hosts.get(ip) else {
+            diff.new_hosts.push(ip.to_string());
+            continue;
+        };
+        if let (Some(old_mac), Some(new_mac)) = (&old_host.mac_address, &new_host.mac_address) {
+            if !old_mac.eq_ignore_ascii_case(new_mac) {
+                diff.changed_macs.push(MacChange { ip: ip.to_string(), old_mac: old_mac.clone(), new_mac: new_mac.clone() });
+            }
+        }
+        let (old_ports, new_ports) = (port_set(old_host), port_set(new_host));
+        let change = |&(port, protocol): &(u16, &str)| PortChange { ip: ip.to_string(), port, protocol: protocol.to_string() };
+        diff.opened_ports.extend(new_ports.difference(&old_ports).map(change));
+        diff.closed_ports.extend(old_ports.difference(&new_ports).map(change));
+    }
+    diff.disappeared_hosts = old_hosts.keys().filter(|ip| !new_hosts.contains_key(*ip)).map(|ip| ip.to_string()).collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OpenPort, FORMAT_VERSION};
+    use chrono::Utc;
+
+    fn host(ip: &str, ports: &[u16]) -> HostResult {
+        HostResult {
+            ip: ip.to_string(),
+            discovery_method: "ICMP".to_string(),
+            open_ports: ports.iter().map(|&port| OpenPort::tcp(port)).collect(),
+            scan_duration_ms: 10,
+            ..Default::default()
+        }
+    }
+
+    fn results(hosts: Vec<HostResult>) -> ScanResults {
+        ScanResults {
+            format_version: FORMAT_VERSION,
+            timestamp: Utc::now(),
+            interface: "eth0".to_string(),
+            subnet: "192.0.2.0/24".to_string(),
+            timeout_ms: 500,
+            total_hosts_scanned: 256,
+            active_hosts_found: hosts.len(),
+            discovery_methods: vec!["ICMP".to_string()],
+            hosts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_results() {
+        let old = results(vec![host("192.0.2.1", &[22, 80]), host("192.0.2.2", &[443])]);
+        let new = results(vec![host("192.0.2.3", &[8080]), host("192.0.2.1", &[22, 3306])]);
+
+        let diff = diff_results(&old, &new);
+        assert_eq!(diff.new_hosts, vec!["192.0.2.3"]);
+        assert_eq!(diff.disappeared_hosts, vec!["192.0.2.2"]);
+        let ports = |changes: &[PortChange]| changes.iter().map(|change| (change.ip.clone(), change.port)).collect::<Vec<_>>();
+        assert_eq!(ports(&diff.opened_ports), vec![("192.0.2.1".to_string(), 3306)]);
+        assert_eq!(ports(&diff.closed_ports), vec![("192.0.2.1".to_string(), 80)]);
+        assert!(diff.changed_macs.is_empty());
+
+        let mut spoofed = new.clone();
+        spoofed.hosts[1].mac_address = Some("de:ad:be:ef:00:01".to_string());
+        let mut with_mac = new.clone();
+        with_mac.hosts[1].mac_address = Some("00:11:22:33:44:55".to_string());
+        let diff = diff_results(&with_mac, &spoofed);
+        assert_eq!(
+            diff.changed_macs,
+            vec![MacChange {
+                ip: "192.0.2.1".to_string(),
+                old_mac: "00:11:22:33:44:55".to_string(),
+                new_mac: "de:ad:be:ef:00:01".to_string(),
+            }]
+        );
+
+        assert!(diff_results(&new, &new).is_empty());
+    }
+}
@@ -0,0 +1,562 @@
+use crate::arp::{slow_arp_replies, ArpScanner};
+use crate::console::{self, STATUS};
+use crate::osfingerprint::{guess_os, guess_os_from_ttl};
+use crate::ping::{PingOutcome, PingReply, PingScanner};
+use crate::trace::{TimingTrace, PHASE_TRACK};
+use crate::{HostResult, OpenPort};
+use anyhow::Result;
+use colored::*;
+use log::info;
+use pnet::util::MacAddr;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The signal that showed a host to be alive
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DiscoveryMethod {
+    Icmp,
+    Tcp,
+    Arp,
+}
+
+impl DiscoveryMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiscoveryMethod::Icmp => "ICMP",
+            DiscoveryMethod::Tcp => "TCP",
+            DiscoveryMethod::Arp => "ARP",
+        }
+    }
+}
+
+impl fmt::Display for DiscoveryMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DiscoveryMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "icmp" => Ok(DiscoveryMethod::Icmp),
+            "tcp" => Ok(DiscoveryMethod::Tcp),
+            "arp" => Ok(DiscoveryMethod::Arp),
+            other => Err(anyhow::anyhow!("unknown discovery method '{}' (expected icmp, tcp or arp)", other)),
+        }
+    }
+}
+
+/// Every method that found a host, in a fixed order, e.g. `ICMP+ARP`
+pub fn describe_methods(methods: &HashSet<DiscoveryMethod>) -> String {
+    let mut methods: Vec<DiscoveryMethod> = methods.iter().copied().collect();
+    methods.sort();
+    methods.iter().map(DiscoveryMethod::name).collect::<Vec<_>>().join("+")
+}
+
+/// What an RTT band does with hosts whose RTT wasn't measured, such as
+/// hosts only ARP found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RttUnknownPolicy {
+    #[default]
+    Keep,
+    Drop,
+}
+
+impl FromStr for RttUnknownPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "keep" => Ok(RttUnknownPolicy::Keep),
+            "drop" => Ok(RttUnknownPolicy::Drop),
+            other => Err(anyhow::anyhow!("unknown RTT policy '{}' (expected keep or drop)", other)),
+        }
+    }
+}
+
+/// Latency range a host's ping RTT must fall in to be kept
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RttBand {
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    pub unknown: RttUnknownPolicy,
+}
+
+impl RttBand {
+    pub fn is_active(&self) -> bool {
+        self.min.is_some() || self.max.is_some()
+    }
+
+    pub fn admits(&self, rtt: Option<Duration>) -> bool {
+        match rtt {
+            Some(rtt) => self.min.is_none_or(|min| rtt >= min) && self.max.is_none_or(|max| rtt <= max),
+            None => self.unknown == RttUnknownPolicy::Keep,
+        }
+    }
+}
+
+/// Which discovery sweep runs first when both ARP and ICMP are enabled.
+///
+/// Both sweeps cover every host: ARP is what reads MAC addresses and the
+/// ping sweep what reads round trips and TTLs, except that the ping sweep
+/// leaves out hosts an ARP sweep before it already found unless
+/// `--ping-arp-found` asks for them too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiscoveryOrder {
+    #[default]
+    ArpFirst,
+    IcmpFirst,
+}
+
+impl DiscoveryOrder {
+    /// The sweeps in the order they run; ICMP stands for the ICMP/TCP ping sweep
+    pub fn sweeps(&self) -> [DiscoveryMethod; 2] {
+        match self {
+            DiscoveryOrder::ArpFirst => [DiscoveryMethod::Arp, DiscoveryMethod::Icmp],
+            DiscoveryOrder::IcmpFirst => [DiscoveryMethod::Icmp, DiscoveryMethod::Arp],
+        }
+    }
+}
+
+impl FromStr for DiscoveryOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "arp-first" => Ok(DiscoveryOrder::ArpFirst),
+            "icmp-first" => Ok(DiscoveryOrder::IcmpFirst),
+            other => Err(anyhow::anyhow!("unknown discovery order '{}' (expected arp-first or icmp-first)", other)),
+        }
+    }
+}
+
+/// Which combination of discovery signals marks a host as active.
+///
+/// Written like `icmp|tcp` or `arp&icmp`; `&` binds tighter than `|`, so a
+/// policy is a list of alternatives that each require all of their methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlivePolicy {
+    terms: Vec<Vec<DiscoveryMethod>>,
+}
+
+impl AlivePolicy {
+    pub fn is_alive(&self, methods: &HashSet<DiscoveryMethod>) -> bool {
+        self.terms
+            .iter()
+            .any(|term| term.iter().all(|method| methods.contains(method)))
+    }
+}
+
+impl Default for AlivePolicy {
+    /// Any single method is enough
+    fn default() -> Self {
+        AlivePolicy {
+            terms: vec![
+                vec![DiscoveryMethod::Icmp],
+                vec![DiscoveryMethod::Tcp],
+                vec![DiscoveryMethod::Arp],
+            ],
+        }
+    }
+}
+
+impl FromStr for AlivePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let terms = s
+            .split('|')
+            .map(|term| {
+                term.split('&')
+                    .map(|method| method.parse::<DiscoveryMethod>())
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow::anyhow!("invalid alive policy '{}': {}", s, e))?;
+
+        Ok(AlivePolicy { terms })
+    }
+}
+
+impl fmt::Display for AlivePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let terms: Vec<String> = self
+            .terms
+            .iter()
+            .map(|term| {
+                term.iter()
+                    .map(|method| method.name().to_lowercase())
+                    .collect::<Vec<_>>()
+                    .join("&")
+            })
+            .collect();
+        f.write_str(&terms.join("|"))
+    }
+}
+
+/// Everything the discovery sweeps learned, keyed by address
+#[derive(Debug, Default)]
+pub struct Discovered {
+    /// Every signal seen for each host; the alive policy decides which hosts count
+    pub methods: HashMap<IpAddr, HashSet<DiscoveryMethod>>,
+    pub mac_addresses: HashMap<IpAddr, String>,
+    pub ping_replies: HashMap<IpAddr, PingReply>,
+    pub arp_rtts: HashMap<IpAddr, Duration>,
+    /// Hosts whose ARP reply was far slower than the rest of the subnet's
+    pub slow_arp: HashSet<IpAddr>,
+    /// The sweeps that really went out, in the order they ran
+    pub methods_used: Vec<DiscoveryMethod>,
+    /// Why the ARP sweep couldn't run, when the ping sweep went on without it
+    pub arp_error: Option<anyhow::Error>,
+}
+
+impl Discovered {
+    /// Add a ping sweep's replies; `icmp` when it sent echoes rather than TCP pings only
+    pub fn record_ping(&mut self, replies: Vec<(IpAddr, PingReply)>, icmp: bool) {
+        if icmp {
+            self.methods_used.push(DiscoveryMethod::Icmp);
+        }
+        self.methods_used.push(DiscoveryMethod::Tcp);
+        for (host, reply) in replies {
+            self.methods.entry(host).or_default().insert(reply.outcome.method());
+            self.ping_replies.insert(host, reply);
+        }
+    }
+
+    /// Add an ARP sweep's replies and return the slow ones, lowest address first
+    pub fn record_arp(&mut self, replies: &[(Ipv4Addr, MacAddr, Duration)]) -> Vec<Ipv4Addr> {
+        self.methods_used.push(DiscoveryMethod::Arp);
+        for (ip, mac, rtt) in replies {
+            self.methods.entry(IpAddr::V4(*ip)).or_default().insert(DiscoveryMethod::Arp);
+            self.mac_addresses.insert(IpAddr::V4(*ip), mac.to_string());
+            self.arp_rtts.insert(IpAddr::V4(*ip), *rtt);
+        }
+        let rtts: Vec<(Ipv4Addr, Duration)> = replies.iter().map(|(ip, _, rtt)| (*ip, *rtt)).collect();
+        let mut slow = slow_arp_replies(&rtts);
+        slow.sort();
+        self.slow_arp.extend(slow.iter().copied().map(IpAddr::V4));
+        slow
+    }
+
+    /// A host's result with everything discovery learned about it filled in.
+    /// The hostname, vendor and gateway flag are left to the caller.
+    pub fn host_result(&self, ip: IpAddr, open_ports: Vec<OpenPort>, scan_duration_ms: u64) -> HostResult {
+        let reply = self.ping_replies.get(&ip);
+        let ttl = reply.and_then(|reply| reply.ttl);
+        HostResult {
+            ip: ip.to_string(),
+            hostname: None,
+            mac_address: self.mac_addresses.get(&ip).cloned(),
+            vendor: None,
+            discovery_method: self.methods.get(&ip).map(describe_methods).unwrap_or_default(),
+            os_guess: guess_os(ttl, &open_ports),
+            open_ports,
+            scan_duration_ms,
+            rtt_ms: reply.map(|reply| reply.rtt.as_secs_f64() * 1000.0),
+            ttl,
+            hops: ttl.map(|ttl| guess_os_from_ttl(ttl).1),
+            tcp_ping_port: reply.and_then(|reply| match reply.outcome {
+                PingOutcome::TcpFallback(port) => Some(port),
+                PingOutcome::Icmp => None,
+            }),
+            ping_stats: reply.and_then(|reply| reply.stats),
+            is_gateway: false,
+            arp_rtt_ms: self.arp_rtts.get(&ip).map(|rtt| rtt.as_secs_f64() * 1000.0),
+            slow_arp: self.slow_arp.contains(&ip),
+        }
+    }
+
+    /// The hosts `policy` counts as active, in the order of `hosts`
+    pub fn active_hosts(&self, hosts: &[IpAddr], policy: &AlivePolicy) -> Vec<IpAddr> {
+        hosts
+            .iter()
+            .copied()
+            .filter(|host| self.methods.get(host).is_some_and(|methods| policy.is_alive(methods)))
+            .collect()
+    }
+}
+
+/// Opens the ARP scanner when its sweep's turn comes
+type ArpOpener<'a> = Box<dyn FnOnce() -> Result<ArpScanner> + 'a>;
+
+/// The discovery sweeps of a scan, run in `DiscoveryOrder` by `run`
+pub struct Discovery<'a> {
+    order: DiscoveryOrder,
+    alive_policy: &'a AlivePolicy,
+    portscan_filter: Option<DiscoveryMethod>,
+    ping_arp_found: bool,
+    /// The ping scanner and its per-host timeout in milliseconds
+    ping: Option<(PingScanner, u64)>,
+    arp: Option<ArpOpener<'a>>,
+    trace: &'a TimingTrace,
+}
+
+impl<'a> Discovery<'a> {
+    pub fn new(order: DiscoveryOrder, alive_policy: &'a AlivePolicy, trace: &'a TimingTrace) -> Self {
+        Discovery { order, alive_policy, portscan_filter: None, ping_arp_found: false, ping: None, arp: None, trace }
+    }
+
+    /// Run the ICMP/TCP ping sweep, waiting `timeout_ms` for each host
+    pub fn with_ping(mut self, scanner: PingScanner, timeout_ms: u64) -> Self {
+        self.ping = Some((scanner, timeout_ms));
+        self
+    }
+
+    /// Run the ARP sweep with the scanner `open` returns. It is only opened
+    /// when the sweep's turn comes; should that fail, the ping sweep goes on
+    /// alone and `Discovered::arp_error` says why.
+    pub fn with_arp(mut self, open: impl FnOnce() -> Result<ArpScanner> + 'a) -> Self {
+        self.arp = Some(Box::new(open));
+        self
+    }
+
+    /// Ping hosts an earlier ARP sweep already proved alive as well, for
+    /// their round trips and TTLs; by default the ping sweep leaves them out
+    pub fn with_ping_arp_found(mut self, ping: bool) -> Self {
+        self.ping_arp_found = ping;
+        self
+    }
+
+    /// Only port scan hosts `method` found; those ARP found still get pinged
+    /// so they can answer it
+    pub fn with_portscan_filter(mut self, method: Option<DiscoveryMethod>) -> Self {
+        self.portscan_filter = method;
+        self
+    }
+
+    /// The hosts the ping sweep still has to probe after the sweeps in `found`
+    fn ping_targets(&self, hosts: &[IpAddr], found: &Discovered) -> Vec<IpAddr> {
+        let settled = |methods: &HashSet<DiscoveryMethod>| {
+            !self.ping_arp_found
+                && self.alive_policy.is_alive(methods)
+                && self.portscan_filter.is_none_or(|method| methods.contains(&method))
+        };
+        hosts.iter().copied().filter(|host| !found.methods.get(host).is_some_and(settled)).collect()
+    }
+
+    /// Sweep `hosts`. Fails only when the ARP scanner can't be opened and
+    /// there is no ping sweep to fall back on.
+    pub async fn run(mut self, hosts: &[IpAddr]) -> Result<Discovered> {
+        let (mut ping, mut arp, trace) = (self.ping.take(), self.arp.take(), self.trace);
+        let has_ping = ping.is_some();
+        let mut found = Discovered::default();
+
+        for sweep in self.order.sweeps() {
+            match sweep {
+                DiscoveryMethod::Icmp => {
+                    let Some((scanner, timeout_ms)) = ping.take() else {
+                        continue;
+                    };
+                    let icmp = scanner.sends_icmp();
+                    let targets = self.ping_targets(hosts, &found);
+                    let probes_saved = hosts.len() - targets.len();
+                    if probes_saved > 0 && icmp {
+                        info!(target: STATUS, "Saved {} ICMP probes on hosts ARP already found", probes_saved);
+                    }
+
+                    if icmp {
+                        info!(target: STATUS, "Performing enhanced ping sweep (ICMP + TCP fallback, {}ms timeout per host)...", timeout_ms);
+                    } else {
+                        info!(target: STATUS, "Performing TCP ping sweep ({}ms timeout per host)...", timeout_ms);
+                    }
+                    trace.begin("ping sweep", "phase", PHASE_TRACK);
+                    let progress = console::start_progress(targets.len() as u64, "hosts swept");
+                    let replies = scanner.with_progress(progress).sweep(targets, timeout_ms).await;
+                    console::finish_progress();
+                    trace.end("ping sweep", "phase", PHASE_TRACK);
+                    let count = replies.len();
+                    let tcp_only = replies
+                        .iter()
+                        .filter(|(_, reply)| matches!(reply.outcome, PingOutcome::TcpFallback(_)))
+                        .count();
+                    for (host, reply) in &replies {
+                        if let Some(stats) = reply.stats {
+                            info!(target: STATUS, "  {}: {}", host, stats.summary());
+                        }
+                    }
+                    found.record_ping(replies, icmp);
+                    info!(target: STATUS, "Found {} hosts via {} ping", count, if icmp { "ICMP" } else { "TCP" });
+                    if tcp_only > 0 && icmp {
+                        info!(target: STATUS, "{} of them answered only the TCP fallback, so ICMP may be filtered", tcp_only);
+                    }
+                }
+                DiscoveryMethod::Arp => {
+                    let Some(open) = arp.take() else {
+                        continue;
+                    };
+                    info!(target: STATUS, "Performing ARP sweep...");
+                    let mut scanner = match open() {
+                        Ok(scanner) => scanner,
+                        Err(e) if !has_ping => return Err(e.context("ARP-only mode failed, no results available")),
+                        Err(e) => {
+                            found.arp_error = Some(e);
+                            continue;
+                        }
+                    };
+                    trace.begin("arp sweep", "phase", PHASE_TRACK);
+                    let targets: Vec<Ipv4Addr> = hosts
+                        .iter()
+                        .filter_map(|host| match host {
+                            IpAddr::V4(ip) => Some(*ip),
+                            IpAddr::V6(_) => None,
+                        })
+                        .collect();
+                    let replies = scanner.sweep(targets).await;
+                    trace.end("arp sweep", "phase", PHASE_TRACK);
+                    let slow = found.record_arp(&replies);
+                    info!(target: STATUS, "Found {} hosts via ARP scan", replies.len());
+                    if !slow.is_empty() {
+                        let listed: Vec<String> = slow
+                            .iter()
+                            .map(|ip| format!("{} ({:.1}ms)", ip, found.arp_rtts[&IpAddr::V4(*ip)].as_secs_f64() * 1000.0))
+                            .collect();
+                        info!(
+                            target: STATUS,
+                            "{}",
+                            format!("Slow ARP replies, possibly wireless or behind a bridge: {}", listed.join(", ")).yellow()
+                        );
+                    }
+                    info!(target: STATUS, "Collected {} MAC addresses", found.mac_addresses.len());
+                    info!(target: STATUS, "Total unique hosts: {}", found.methods.len());
+                }
+                DiscoveryMethod::Tcp => {}
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn methods(list: &[DiscoveryMethod]) -> HashSet<DiscoveryMethod> {
+        list.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_alive_policy_parsing() {
+        assert_eq!("icmp|tcp|arp".parse::<AlivePolicy>().unwrap(), AlivePolicy::default());
+        assert_eq!("ARP & icmp".parse::<AlivePolicy>().unwrap().to_string(), "arp&icmp");
+        assert!("icmp|".parse::<AlivePolicy>().is_err());
+        assert!("icmp|udp".parse::<AlivePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_describe_methods() {
+        assert_eq!(describe_methods(&methods(&[DiscoveryMethod::Arp, DiscoveryMethod::Icmp])), "ICMP+ARP");
+        assert_eq!(describe_methods(&methods(&[DiscoveryMethod::Tcp])), "TCP");
+        assert_eq!(describe_methods(&methods(&[])), "");
+    }
+
+    #[test]
+    fn test_rtt_band() {
+        let ms = Duration::from_millis;
+        let band = RttBand { min: Some(ms(1)), max: Some(ms(10)), unknown: RttUnknownPolicy::Keep };
+        assert!(band.admits(Some(ms(1))) && band.admits(Some(ms(10))));
+        assert!(!band.admits(Some(Duration::from_micros(300))));
+        assert!(!band.admits(Some(ms(11))));
+        assert!(band.admits(None));
+
+        let far = RttBand { min: Some(ms(50)), max: None, unknown: "drop".parse().unwrap() };
+        assert!(far.admits(Some(ms(500))));
+        assert!(!far.admits(None));
+        assert!(!RttBand::default().is_active());
+    }
+
+    #[test]
+    fn test_discovery_order() {
+        assert_eq!("ICMP-first".parse::<DiscoveryOrder>().unwrap(), DiscoveryOrder::IcmpFirst);
+        assert_eq!(DiscoveryOrder::default().sweeps()[0], DiscoveryMethod::Arp);
+        assert!("tcp-first".parse::<DiscoveryOrder>().is_err());
+    }
+
+    #[test]
+    fn test_alive_policy_evaluation() {
+        let policy: AlivePolicy = "icmp|arp&tcp".parse().unwrap();
+        assert!(policy.is_alive(&methods(&[DiscoveryMethod::Icmp])));
+        assert!(policy.is_alive(&methods(&[DiscoveryMethod::Arp, DiscoveryMethod::Tcp])));
+        assert!(!policy.is_alive(&methods(&[DiscoveryMethod::Arp])));
+        assert!(!policy.is_alive(&methods(&[])));
+
+        let icmp_only: AlivePolicy = "icmp".parse().unwrap();
+        assert!(!icmp_only.is_alive(&methods(&[DiscoveryMethod::Tcp, DiscoveryMethod::Arp])));
+    }
+
+    #[test]
+    fn test_discovered_records_sweeps() {
+        let (a, b, c) = (Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2), Ipv4Addr::new(192, 0, 2, 3));
+        let reply = |outcome| PingReply { outcome, ttl: Some(64), rtt: Duration::from_millis(1), stats: None };
+        let mut found = Discovered::default();
+        found.record_arp(&[(a, MacAddr::new(0, 0x11, 0x22, 0x33, 0x44, 0x55), Duration::from_millis(1))]);
+        found.record_ping(vec![(a.into(), reply(PingOutcome::Icmp)), (c.into(), reply(PingOutcome::TcpFallback(80)))], false);
+
+        assert_eq!(found.methods_used, [DiscoveryMethod::Arp, DiscoveryMethod::Tcp]);
+        assert_eq!(found.methods[&IpAddr::V4(a)], methods(&[DiscoveryMethod::Arp, DiscoveryMethod::Icmp]));
+        assert_eq!(found.mac_addresses[&IpAddr::V4(a)], "00:11:22:33:44:55");
+        let hosts: Vec<IpAddr> = vec![c.into(), b.into(), a.into()];
+        assert_eq!(found.active_hosts(&hosts, &AlivePolicy::default()), [IpAddr::V4(c), IpAddr::V4(a)]);
+        assert_eq!(found.active_hosts(&hosts, &"arp&icmp".parse().unwrap()), [IpAddr::V4(a)]);
+
+        let result = found.host_result(a.into(), Vec::new(), 5);
+        assert_eq!(result.discovery_method, "ICMP+ARP");
+        assert_eq!(result.mac_address.as_deref(), Some("00:11:22:33:44:55"));
+        assert_eq!((result.ttl, result.hops, result.os_guess.as_deref()), (Some(64), Some(0), Some("Linux/Unix")));
+        assert_eq!(result.arp_rtt_ms, Some(1.0));
+        assert_eq!(found.host_result(c.into(), Vec::new(), 5).tcp_ping_port, Some(80));
+    }
+
+    #[test]
+    fn test_ping_targets() {
+        let policy = AlivePolicy::default();
+        let trace = TimingTrace::new(false);
+        let (a, b) = (Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2));
+        let hosts: Vec<IpAddr> = vec![a.into(), b.into()];
+        let mut found = Discovered::default();
+        found.record_arp(&[(a, MacAddr::new(0, 0x11, 0x22, 0x33, 0x44, 0x55), Duration::from_millis(1))]);
+
+        // Hosts ARP already found aren't pinged again unless asked for
+        let discovery = Discovery::new(DiscoveryOrder::ArpFirst, &policy, &trace);
+        assert_eq!(discovery.ping_targets(&hosts, &found), [IpAddr::V4(b)]);
+        let discovery = discovery.with_ping_arp_found(true);
+        assert_eq!(discovery.ping_targets(&hosts, &found), hosts);
+        // A host that still has to answer the ping sweep for the port scan gets pinged
+        let filtered = Discovery::new(DiscoveryOrder::ArpFirst, &policy, &trace).with_portscan_filter(Some(DiscoveryMethod::Icmp));
+        assert_eq!(filtered.ping_targets(&hosts, &found), hosts);
+    }
+
+    #[tokio::test]
+    async fn test_discovery_run() {
+        // A refused connect proves the host up as well as an accepted one
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let policy = AlivePolicy::default();
+        let trace = TimingTrace::new(false);
+        let hosts = [IpAddr::V4(Ipv4Addr::LOCALHOST)];
+
+        let found = Discovery::new(DiscoveryOrder::ArpFirst, &policy, &trace)
+            .with_ping(PingScanner::tcp_only().with_fallback_ports(vec![closed]), 1000)
+            .with_arp(|| Err(anyhow::anyhow!("no such interface")))
+            .run(&hosts)
+            .await
+            .unwrap();
+        // The ARP sweep that couldn't start isn't listed as used, but says why
+        assert_eq!(found.methods_used, [DiscoveryMethod::Tcp]);
+        assert_eq!(found.arp_error.as_ref().unwrap().to_string(), "no such interface");
+        assert_eq!(found.active_hosts(&hosts, &policy), hosts);
+        assert!(matches!(found.ping_replies[&hosts[0]].outcome, PingOutcome::TcpFallback(port) if port == closed));
+
+        // Without a ping sweep to fall back on, the ARP failure is the scan's
+        let arp_only = Discovery::new(DiscoveryOrder::ArpFirst, &policy, &trace)
+            .with_arp(|| Err(anyhow::anyhow!("no such interface")))
+            .run(&hosts)
+            .await;
+        assert!(arp_only.is_err());
+    }
+}
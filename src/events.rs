@@ -0,0 +1,56 @@
+use crate::discovery::DiscoveryMethod;
+use crate::portscan::Protocol;
+use serde::Serialize;
+use std::net::IpAddr;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// What a scan reports as it goes, for front ends that draw their own
+/// progress instead of reading the console output
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ScanEvent {
+    /// A sweep found `ip` alive; a host answering several sweeps is reported by each
+    HostDiscovered { ip: IpAddr, method: DiscoveryMethod, mac: Option<String> },
+    PortOpen { ip: IpAddr, port: u16, protocol: Protocol, banner: String },
+    /// `scan_ports` finished with `ip`
+    HostScanned { ip: IpAddr, open_ports: usize },
+    /// Sent by whatever drives the whole scan, as the CLI does for `--events`
+    ScanComplete { hosts_scanned: usize, active_hosts: usize, open_ports: usize, duration_ms: u64 },
+}
+
+/// Where scanners send their events. Unbounded, so a slow consumer never
+/// holds up a sweep; a dropped receiver just means nobody is listening.
+pub type EventSender = UnboundedSender<ScanEvent>;
+
+pub(crate) fn emit(events: &Option<EventSender>, event: ScanEvent) {
+    if let Some(events) = events {
+        let _ = events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_event_json() {
+        let event = ScanEvent::PortOpen {
+            ip: Ipv4Addr::new(192, 0, 2, 10).into(),
+            port: 53,
+            protocol: Protocol::Udp,
+            banner: String::new(),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"port_open","ip":"192.0.2.10","port":53,"protocol":"udp","banner":""}"#
+        );
+        let event = ScanEvent::HostDiscovered { ip: Ipv4Addr::new(192, 0, 2, 1).into(), method: DiscoveryMethod::Arp, mac: None };
+        assert!(serde_json::to_string(&event).unwrap().contains(r#""method":"ARP""#));
+
+        // Nobody listening is not an error
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        drop(receiver);
+        emit(&Some(sender), event);
+    }
+}
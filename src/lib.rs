@@ -0,0 +1,177 @@
+//! AngryEther's host discovery and port scanning as a library.
+//!
+//! The `angryether` binary is a thin CLI over these modules. Raw sockets
+//! (ICMP, ARP, the TCP TTL sniffer) need root or `CAP_NET_RAW`; see each
+//! scanner's constructor, or check up front with `has_raw_socket_capability`.
+
+pub mod arp;
+pub mod budget;
+pub mod config;
+pub mod console;
+pub mod data;
+pub mod diff;
+pub mod discovery;
+pub mod events;
+pub mod network;
+pub mod osfingerprint;
+#[cfg(feature = "online-oui")]
+pub mod oui;
+pub mod output;
+pub mod ping;
+pub mod portscan;
+pub mod privileges;
+pub mod probes;
+pub mod report;
+pub mod risk;
+pub mod rng;
+pub mod scan;
+pub mod services;
+pub mod socks;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod syslog;
+pub mod timing;
+pub mod tls;
+pub mod trace;
+
+pub use arp::ArpScanner;
+pub use network::{
+    get_local_subnet, get_local_subnet_v6, get_network_hosts, get_network_hosts_v6, list_interfaces, nearby_hosts,
+    shard_hosts, target_hosts,
+};
+pub use ping::{PingOutcome, PingReply, PingScanner, PingStats};
+pub use privileges::has_raw_socket_capability;
+pub use portscan::{PortFinding, PortScanner, ScanProtocol};
+/// Cancels scans through each scanner's `with_cancellation`
+pub use tokio_util::sync::CancellationToken;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// One open port of a host
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[cfg_attr(test, derive(Default))]
+pub struct OpenPort {
+    pub port: u16,
+    /// "tcp" or "udp"
+    pub protocol: String,
+    /// "open", "open|filtered" for UDP ports that never answered, or
+    /// "filtered" for TCP ports that never answered, with --show-filtered
+    pub state: String,
+    pub banner: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner_status: Option<String>,
+    /// The banner's bytes as received, hex-encoded, with --raw-banner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_banner: Option<String>,
+    /// Found by --smart-ports rather than the port list
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub correlated: bool,
+    /// Certificate details from a TLS handshake on HTTPS-style ports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<tls::TlsInfo>,
+    /// Status and redirect target of the reply to `GET /` on web ports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<portscan::HttpInfo>,
+    /// Service identified by a `--probes` file entry, else IANA's name for the port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+}
+
+/// An active host and what its port scan found
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[cfg_attr(test, derive(Default))]
+pub struct HostResult {
+    pub ip: String,
+    /// The `--target` hostname this address was resolved from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub mac_address: Option<String>,
+    /// Who the MAC's OUI is assigned to, with --online-oui
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    pub discovery_method: String,
+    pub open_ports: Vec<OpenPort>,
+    pub scan_duration_ms: u64,
+    /// Ping round trip in milliseconds (ICMP echo, or the TCP connect that answered)
+    pub rtt_ms: Option<f64>,
+    /// IP TTL of the ping reply (ICMP, or the TCP SYN-ACK/RST when only TCP answered)
+    pub ttl: Option<u8>,
+    pub hops: Option<u8>,
+    pub os_guess: Option<String>,
+    /// Port that answered the TCP fallback, when the host ignored ICMP echo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_ping_port: Option<u16>,
+    /// Echo loss and round trips, with --ping-count
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_stats: Option<ping::PingStats>,
+    /// The scanned interface's default gateway
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_gateway: bool,
+    /// ARP request-to-reply round trip in milliseconds, for hosts found by ARP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arp_rtt_ms: Option<f64>,
+    /// The ARP reply was far slower than the rest of the subnet's: a wireless
+    /// client, or a bridge or proxy ARP in the way
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub slow_arp: bool,
+}
+
+// Test fixtures set only the fields they check and take the rest from here or `Default`
+#[cfg(test)]
+impl OpenPort {
+    /// An open TCP port with nothing else known about it
+    pub(crate) fn tcp(port: u16) -> Self {
+        OpenPort { port, protocol: "tcp".to_string(), state: "open".to_string(), ..Default::default() }
+    }
+}
+
+/// Version of the `ScanResults` format, bumped whenever a field is removed,
+/// renamed or changes meaning. New optional fields don't bump it.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A whole scan, as written to the JSON, MessagePack and HTML outputs
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[cfg_attr(test, derive(Default))]
+pub struct ScanResults {
+    /// `FORMAT_VERSION` of the scan that wrote this; 0 for files from before versioning
+    #[serde(default)]
+    pub format_version: u32,
+    pub timestamp: DateTime<Utc>,
+    pub interface: String,
+    pub subnet: String,
+    pub timeout_ms: u64,
+    pub total_hosts_scanned: usize,
+    pub active_hosts_found: usize,
+    pub discovery_methods: Vec<String>,
+    pub hosts: Vec<HostResult>,
+    pub omitted_open_ports: usize,
+    pub shard: Option<String>,
+    /// Seed behind every randomized choice in the run; pass it to --seed to replay
+    pub seed: u64,
+    /// Outbound probes sent (ICMP echoes, TCP connects, ARP requests)
+    pub probes_sent: u64,
+    pub probe_budget: Option<u64>,
+    /// The scan was interrupted (Ctrl-C, or `--max-duration`) and some hosts were never port scanned
+    #[serde(default)]
+    pub partial: bool,
+    /// `--max-duration` ran out, which also makes the results partial
+    #[serde(default)]
+    pub timed_out: bool,
+    /// Wall-clock time of the whole scan, discovery included
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// Open ports recorded across all hosts
+    #[serde(default)]
+    pub total_open_ports: usize,
+    /// Ports probed on each host (TCP and UDP together)
+    #[serde(default)]
+    pub total_ports_per_host: usize,
+    /// MACs that answered for several IPs: proxy ARP, or possible ARP spoofing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub duplicate_macs: Vec<arp::DuplicateMac>,
+    /// Plaintext and legacy services among the open ports, most severe first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub risks: Vec<risk::RiskFinding>,
+}
@@ -1,20 +1,48 @@
-mod arp;
-mod network;
-mod ping;
-mod portscan;
-
+use angryether::arp::{find_duplicate_macs, read_system_arp_cache, ArpScanner};
+use angryether::budget::ProbeBudget;
+use angryether::config::{explicit_config_path, find_config, read_config};
+use angryether::console::{self, status};
+use angryether::diff::{diff_results, ScanDiff};
+use angryether::discovery::{AlivePolicy, Discovery, DiscoveryMethod, DiscoveryOrder, RttBand, RttUnknownPolicy};
+use angryether::events::{EventSender, ScanEvent};
+use angryether::network::{
+    default_interface, expand_targets, get_default_gateway, get_local_subnet, get_local_subnet_v6, get_network_hosts,
+    get_network_hosts_v6, interface_scope_id, list_interfaces, nearby_hosts, parse_nearby, parse_split, parse_target,
+    parse_target_list, resolve_hostnames, shard_hosts, target_hosts, Exclusions, Target, LARGE_SCAN_HOSTS,
+};
+use angryether::output::{
+    is_msgpack_path, merge_resumed, read_results, results_schema, write_banner_dump, write_hosts_export, write_target_plan,
+    JsonlWriter, OutputFiles, TargetPlan,
+};
+use angryether::ping::{PingScanner, DEFAULT_FALLBACK_PORTS, DEFAULT_ICMP_RETRIES, DEFAULT_PING_SIZE};
+use angryether::portscan::{
+    builtin_ports, check_source_port, is_port_spec, parse_banner_protocols, parse_knock_sequence, parse_port_spec, read_ports_from_file, top_ports, BannerProtocol,
+    BannerMode, PortScanner, Protocol, ScanProtocol, DEFAULT_SNMP_COMMUNITY, DEFAULT_UDP_PORTS,
+};
+use angryether::probes::{CustomProbe, ProbeSet};
+use angryether::risk::Severity;
+use angryether::scan::{HostScans, ScannedHosts};
+use angryether::socks::Socks5Proxy;
+use angryether::syslog::SyslogSink;
+use angryether::timing::{Jitter, TimingProfile};
+use angryether::trace::{TimingTrace, PHASE_TRACK};
+use angryether::{data, has_raw_socket_capability, rng, HostResult, ScanResults, FORMAT_VERSION};
 use anyhow::Result;
-use arp::{ArpScanner, read_system_arp_cache};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
+use clap::parser::ValueSource;
 use clap::{Arg, Command};
 use colored::*;
-use network::{get_local_subnet, get_network_hosts, list_interfaces};
-use ping::PingScanner;
-use portscan::{read_ports_from_file, PortScanner};
-use serde::{Deserialize, Serialize};
+use ipnetwork::Ipv4Network;
 use std::collections::{HashSet, HashMap};
 use std::fs;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
 const BANNER: &str = r#"
 ░█▀█░█▀█░█▀▀░█▀▄░█░█░█▀▀░▀█▀░█░█░█▀▀░█▀▄
@@ -23,59 +51,43 @@ const BANNER: &str = r#"
                     Network Scanner v1.0.1
 "#;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct OpenPort {
-    port: u16,
-    banner: String,
-}
+/// Ports list used when `--ports` isn't given, looked up in the data directories
+const DEFAULT_PORTS_FILE: &str = "ports/10000.txt";
 
-#[derive(Serialize, Deserialize, Debug)]
-struct HostResult {
-    ip: String,
-    mac_address: Option<String>,
-    discovery_method: String,
-    open_ports: Vec<OpenPort>,
+fn parse_rtt_ms(s: &str) -> Result<Duration, String> {
+    let ms: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !ms.is_finite() || ms < 0.0 {
+        return Err(format!("invalid RTT '{}' (expected a non-negative number of milliseconds)", s));
+    }
+    Ok(Duration::from_secs_f64(ms / 1000.0))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ScanResults {
-    timestamp: DateTime<Utc>,
-    interface: String,
-    subnet: String,
-    timeout_ms: u64,
-    total_hosts_scanned: usize,
-    active_hosts_found: usize,
-    discovery_methods: Vec<String>,
-    hosts: Vec<HostResult>,
-}
+fn print_slowest_hosts(hosts: &[HostResult], count: usize) {
+    if count == 0 || hosts.is_empty() {
+        return;
+    }
+
+    let mut by_duration: Vec<&HostResult> = hosts.iter().collect();
+    by_duration.sort_by_key(|host| std::cmp::Reverse(host.scan_duration_ms));
 
-fn get_default_ports_file() -> String {
-    // Try local ports file first
-    let local_path = "ports/10000.txt";
-    if std::path::Path::new(local_path).exists() {
-        return local_path.to_string();
-    }
-    
-    // Try system-installed locations
-    let system_paths = [
-        "/usr/local/share/angryether/ports/10000.txt",
-        "/usr/share/angryether/ports/10000.txt", 
-        "/opt/angryether/ports/10000.txt",
-    ];
-    
-    for path in &system_paths {
-        if std::path::Path::new(path).exists() {
-            return path.to_string();
-        }
-    }
-    
-    // Fallback to local path (will trigger proper error handling later)
-    local_path.to_string()
+    status!("\nSlowest hosts:");
+    for host in by_duration.into_iter().take(count) {
+        status!("   {} {}ms", host.ip.green(), host.scan_duration_ms.to_string().yellow());
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let matches = Command::new("AngryEther")
+    let config_path = explicit_config_path(std::env::args()).or_else(find_config);
+    let config = match config_path.as_deref().map(read_config).transpose() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Error loading config: {}", format!("{:#}", e).red());
+            std::process::exit(SETUP_ERROR_EXIT_CODE);
+        }
+    };
+
+    let mut cli = Command::new("AngryEther")
         .version("1.0.1")
         .about("Network scanner for host discovery and port scanning")
         .arg(
@@ -83,15 +95,196 @@ async fn main() -> Result<()> {
                 .short('i')
                 .long("interface")
                 .value_name("INTERFACE")
-                .help("Network interface to scan (e.g., enp37s0)")
-                .default_value("enp37s0")
+                .help("Network interface to scan (default: the interface of the default route)")
+        )
+        .arg(
+            Arg::new("source-ip")
+                .long("source-ip")
+                .visible_alias("interface-ip")
+                .value_name("IP")
+                .help("Which IPv4 address of a multi-homed interface ARP requests come from and the subnet is detected from (default: the address on the targets' subnet)")
+                .value_parser(clap::value_parser!(Ipv4Addr))
+                .conflicts_with("ipv6")
+        )
+        .arg(
+            Arg::new("nearby")
+                .long("nearby")
+                .value_name("IP[/N]")
+                .help("Scan the neighbours of IP instead of the local subnet: its /24, or IP plus or minus N addresses")
+                .value_parser(|s: &str| parse_nearby(s).map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("target")
+                .long("target")
+                .value_name("RANGE")
+                .help("Scan RANGE instead of the local subnet: a CIDR (10.0.5.0/24), a single IP, a dash range (10.0.5.10-10.0.5.50), or hostnames (router.lan,nas.lan), each resolved to all its IPv4 addresses")
+                .value_parser(|s: &str| parse_target(s).map_err(|e| e.to_string()))
+                .conflicts_with("nearby")
+        )
+        .arg(
+            Arg::new("stdin")
+                .long("stdin")
+                .help("Read the targets from stdin instead of scanning the local subnet: one IP, CIDR, dash range or hostname per line, # starts a comment")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["nearby", "target"])
+        )
+        .arg(
+            Arg::new("ipv6")
+                .short('6')
+                .long("ipv6")
+                .help("Scan the interface's IPv6 subnet instead of its IPv4 one (ICMPv6 and TCP discovery)")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["nearby", "target", "stdin"])
+        )
+        .arg(
+            Arg::new("max-hosts")
+                .long("max-hosts")
+                .value_name("COUNT")
+                .help("Scan at most the first COUNT addresses of the subnet; required for IPv6 prefixes shorter than /120 and --target ranges larger than a /8")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Work out the targets and ports (subnet, exclusions, --max-hosts, --randomize order) and list them without sending a probe; with --json the list goes to that file")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .help("Scan more than 65536 addresses without asking first; needed for such scans when stdin is not a terminal")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("LIST")
+                .help("Never probe these hosts: a comma-separated list of IPs and CIDR ranges (e.g. 192.168.1.1,10.0.0.0/28)")
+                .value_parser(|s: &str| {
+                    let mut exclusions = Exclusions::default();
+                    exclusions.add_list(s).map(|_| exclusions).map_err(|e| e.to_string())
+                })
+        )
+        .arg(
+            Arg::new("exclude-file")
+                .long("exclude-file")
+                .value_name("FILE")
+                .help("Never probe the IPs and CIDR ranges listed in FILE, one per line (# starts a comment)")
+        )
+        .arg(
+            Arg::new("split")
+                .long("split")
+                .value_name("N/M")
+                .help("Scan only the M-th of N equal shards of the target hosts, for spreading a scan across machines")
+                .value_parser(|s: &str| parse_split(s).map_err(|e| e.to_string()))
         )
         .arg(
             Arg::new("ports")
                 .short('p')
                 .long("ports")
-                .value_name("PORTS_FILE")
-                .help("Path to ports file (default: ports/10000.txt or system location)")
+                .value_name("PORTS")
+                .help("Ports to scan: a list like 22,80,8000-8100 (- for all ports), or a ports file (default: ports/10000.txt from the data directories)")
+        )
+        .arg(
+            Arg::new("top-ports")
+                .long("top-ports")
+                .value_name("N")
+                .help("Scan the N most common TCP ports from the built-in list (e.g. 10, 100, 1000) instead of a ports file")
+                .conflicts_with("ports")
+                .value_parser(|s: &str| {
+                    let count = s.parse::<usize>().map_err(|e| e.to_string())?;
+                    top_ports(count).map(|ports| (count, ports)).map_err(|e| e.to_string())
+                })
+        )
+        .arg(
+            Arg::new("data-dir")
+                .long("data-dir")
+                .value_name("DIR")
+                .help("Look for ports lists and other data files in DIR before $ANGRYETHER_DATA_DIR, ./ and the system locations")
+                .value_parser(clap::value_parser!(std::path::PathBuf))
+        )
+        .arg(
+            Arg::new("scan-protocol")
+                .long("scan-protocol")
+                .value_name("PROTOCOL")
+                .help("Port scan over tcp, udp or both")
+                .default_value("tcp")
+                .value_parser(|s: &str| s.parse::<ScanProtocol>().map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("udp")
+                .long("udp")
+                .help("Also scan UDP ports (same as --scan-protocol both)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("host-concurrency")
+                .long("host-concurrency")
+                .value_name("COUNT")
+                .help("Port scan up to COUNT hosts at once (default: from --timing, 10 at T3)")
+                .value_parser(clap::value_parser!(u64).range(1..))
+        )
+        .arg(
+            Arg::new("udp-ports")
+                .long("udp-ports")
+                .value_name("PORTS")
+                .help("UDP ports to probe, e.g. 53,123,161 or 1-1024 (default: a list of common UDP services)")
+                .value_parser(|s: &str| parse_port_spec(s).map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("udp-timeout")
+                .long("udp-timeout")
+                .value_name("MILLISECONDS")
+                .help("How long to wait for a UDP reply before calling the port open|filtered")
+                .default_value("2000")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("snmp-community")
+                .long("snmp-community")
+                .value_name("COMMUNITY")
+                .help("Community string the SNMP probe on UDP 161 asks for sysDescr with")
+                .default_value(DEFAULT_SNMP_COMMUNITY)
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_name("COUNT")
+                .help("Retry TCP connects that time out up to COUNT times with exponential backoff (refused ports are not retried)")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u8))
+        )
+        .arg(
+            Arg::new("source-port")
+                .long("source-port")
+                .value_name("PORT")
+                .help("Make every port scan connect from local port PORT, to test firewall rules that trust source ports such as 53 or 20 (ports below 1024 need root or CAP_NET_BIND_SERVICE)")
+                .value_parser(clap::value_parser!(u16).range(1..))
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .help("Make TCP connects through a SOCKS5 proxy, socks5://[user:password@]host:port; ICMP, ARP and UDP can't be proxied and are turned off")
+                .value_parser(|s: &str| s.parse::<Socks5Proxy>().map_err(|e| e.to_string()))
+                .conflicts_with_all(["source-port", "arp-only", "ping-count"])
+        )
+        .arg(
+            Arg::new("knock")
+                .long("knock")
+                .value_name("PORTS")
+                .help("Knock on these TCP ports in order, e.g. 7000,8000,9000, with brief connects before scanning each host, to reach services behind a port-knock daemon")
+                .value_parser(|s: &str| parse_knock_sequence(s).map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("knock-guard")
+                .long("knock-guard")
+                .value_name("PORT")
+                .help("Port the knock should open: checked before and after knocking, to report whether the knock worked")
+                .requires("knock")
+                .value_parser(clap::value_parser!(u16).range(1..))
         )
         .arg(
             Arg::new("arp")
@@ -105,202 +298,1632 @@ async fn main() -> Result<()> {
                 .help("Use only ARP scanning (no ICMP ping)")
                 .action(clap::ArgAction::SetTrue)
         )
+        .arg(
+            Arg::new("arp-rate")
+                .long("arp-rate")
+                .value_name("PPS")
+                .help("Send at most PPS ARP requests per second (default: from --timing, 10000 at T3)")
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+        .arg(
+            Arg::new("arp-timeout")
+                .long("arp-timeout")
+                .value_name("MILLISECONDS")
+                .help("Collect ARP replies for exactly this long after the last request (default: 200ms plus 0.5ms per target, up to 3s, ending early once replies stop)")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("promisc")
+                .long("promisc")
+                .help("Put the interface in promiscuous mode during ARP scanning (needs root or CAP_NET_RAW)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("online-oui")
+                .long("online-oui")
+                .help("Look up the vendor of each MAC address's OUI online at api.macvendors.com, caching answers; needs a build with the online-oui feature")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("no-icmp")
+                .long("no-icmp")
+                .help("Discover hosts with TCP connects only, sending no ICMP echo (no privileges needed)")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("arp-only")
+        )
+        .arg(
+            Arg::new("ping-count")
+                .long("ping-count")
+                .value_name("COUNT")
+                .help("Send COUNT ICMP echoes to every host and report loss and min/avg/max round trip, like ping -c")
+                .value_parser(clap::value_parser!(u32).range(1..=u16::MAX as i64))
+                .conflicts_with_all(["no-icmp", "arp-only"])
+        )
+        .arg(
+            Arg::new("icmp-retries")
+                .long("icmp-retries")
+                .value_name("N")
+                .help("ICMP echoes sent after the first, spread over the ping timeout; a reply to any of them counts until the timeout ends (default: 1)")
+                .value_parser(clap::value_parser!(u32).range(0..=100))
+                .conflicts_with_all(["no-icmp", "arp-only", "ping-count"])
+        )
+        .arg(
+            Arg::new("ping-size")
+                .long("ping-size")
+                .value_name("BYTES")
+                .help("Payload bytes in each ICMP echo (default: 56)")
+                .value_parser(clap::value_parser!(u16).range(0..=65507))
+                .conflicts_with_all(["no-icmp", "arp-only"])
+        )
+        .arg(
+            Arg::new("discovery-ports")
+                .long("discovery-ports")
+                .value_name("PORTS")
+                .help("TCP ports the ping sweep tries when a host ignores ICMP, in order (default: 80,443,22,21,23,53,25)")
+                .value_parser(|s: &str| parse_port_spec(s).map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("alive-policy")
+                .long("alive-policy")
+                .value_name("POLICY")
+                .help("Discovery signals that mark a host active, combined with & and | (e.g. icmp, icmp|tcp, arp&icmp)")
+                .default_value("icmp|tcp|arp")
+                .value_parser(|s: &str| s.parse::<AlivePolicy>().map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("discovery-order")
+                .long("discovery-order")
+                .value_name("ORDER")
+                .help("With --arp, which sweep runs first: arp-first or icmp-first")
+                .default_value("arp-first")
+                .value_parser(|s: &str| s.parse::<DiscoveryOrder>().map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("ping-arp-found")
+                .long("ping-arp-found")
+                .help("With --arp, also ping hosts an earlier ARP sweep already found, for their rtt_ms, ttl and os_guess (always on with --ping-count, --min-rtt-ms and --max-rtt-ms)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("max-probes")
+                .long("max-probes")
+                .value_name("COUNT")
+                .help("Send at most COUNT probes (ICMP echoes, TCP connects, ARP requests, retries included) across the whole scan, then finish with partial results")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed for all randomized probe choices, to reproduce an earlier scan (default: random, printed at start)")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("randomize")
+                .long("randomize")
+                .help("Probe hosts and ports in a shuffled order rather than ascending, so the scan doesn't look like a sweep; --seed reproduces the order")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("max-duration")
+                .long("max-duration")
+                .value_name("SECONDS")
+                .help("Stop the scan after SECONDS, write the partial results (marked timed_out) and exit with status 124; writing them gets 10 more seconds at most")
+                .value_parser(clap::value_parser!(u64).range(1..))
+        )
+        .arg(
+            Arg::new("jitter")
+                .long("jitter")
+                .value_name("MILLISECONDS")
+                .help("Wait a random 0 to MILLISECONDS before every ping, ARP request and port probe, so probe timing has no fixed pattern (default: from --timing, 500 at T0, 100 at T1, otherwise 0); --seed reproduces the delays")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("max-rate-per-host")
+                .long("max-rate-per-host")
+                .value_name("PROBES")
+                .help("Send any one host at most PROBES port probes a second, evenly spaced, while other hosts are scanned at their own pace (default: unlimited)")
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+        .arg(
+            Arg::new("portscan-method-filter")
+                .long("portscan-method-filter")
+                .value_name("METHOD")
+                .help("Port scan only hosts confirmed by this discovery method: icmp, arp, tcp or any")
+                .default_value("any")
+                .value_parser(|s: &str| match s.trim().to_ascii_lowercase().as_str() {
+                    "any" => Ok(None),
+                    method => method.parse::<DiscoveryMethod>().map(Some).map_err(|e| e.to_string()),
+                })
+        )
+        .arg(
+            Arg::new("min-rtt-ms")
+                .long("min-rtt-ms")
+                .value_name("MS")
+                .help("Drop hosts whose ping round trip is below MS milliseconds (fractions allowed)")
+                .value_parser(parse_rtt_ms)
+        )
+        .arg(
+            Arg::new("max-rtt-ms")
+                .long("max-rtt-ms")
+                .value_name("MS")
+                .help("Drop hosts whose ping round trip is above MS milliseconds (fractions allowed)")
+                .value_parser(parse_rtt_ms)
+        )
+        .arg(
+            Arg::new("rtt-unknown")
+                .long("rtt-unknown")
+                .value_name("POLICY")
+                .help("With an RTT filter, keep or drop hosts that have no RTT (e.g. found only by ARP)")
+                .default_value("keep")
+                .value_parser(|s: &str| s.parse::<RttUnknownPolicy>().map_err(|e| e.to_string()))
+        )
         .arg(
             Arg::new("timeout")
                 .short('t')
                 .long("timeout")
                 .value_name("MILLISECONDS")
-                .help("Timeout for ping operations in milliseconds")
-                .default_value("500")
+                .help("Timeout for ping operations in milliseconds (default: from --timing, 500 at T3)")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("port-timeout")
+                .long("port-timeout")
+                .value_name("MILLISECONDS")
+                .help("Timeout for each TCP port connect in milliseconds (default: from --timing, 1000 at T3)")
                 .value_parser(clap::value_parser!(u64))
         )
+        .arg(
+            Arg::new("banner-timeout")
+                .long("banner-timeout")
+                .value_name("MILLISECONDS")
+                .help("How long to wait for a banner or probe reply once a port accepts; unprompted banners get half")
+                .default_value("1000")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("banner-bytes")
+                .long("banner-bytes")
+                .value_name("BYTES")
+                .help("Read up to BYTES of each banner or probe reply (HTTP responses are read up to 16 KiB regardless)")
+                .default_value("1024")
+                .value_parser(clap::value_parser!(u32).range(1..=1024 * 1024))
+        )
+        .arg(
+            Arg::new("timing")
+                .short('T')
+                .long("timing")
+                .value_name("0-5")
+                .help("Timing profile for timeouts, concurrency, ARP pacing and jitter, 0 (paranoid) to 5 (insane); --timeout, --port-timeout and --host-concurrency override it")
+                .long_help(
+                    "Timing profile setting ping and connect timeouts, how many hosts are pinged, hosts port scanned \
+                     and ports probed at once, the pause after each ARP request and the jitter before each probe. Accepts 0-5 or the profile name; \
+                     --timeout overrides the ping timeout, --port-timeout the connect timeout, --host-concurrency the hosts \
+                     scanned at once, --arp-rate the ARP delay and --jitter the random delay before each probe.\n\n  \
+                     0 paranoid:   ping 5000ms, connect 5000ms, 1 host, 1 scanned, 1 port, 100ms ARP delay, 0-500ms jitter\n  \
+                     1 sneaky:     ping 3000ms, connect 3000ms, 5 hosts, 2 scanned, 10 ports, 10ms ARP delay, 0-100ms jitter\n  \
+                     2 polite:     ping 1000ms, connect 2000ms, 20 hosts, 5 scanned, 100 ports, 1ms ARP delay\n  \
+                     3 normal:     ping 500ms, connect 1000ms, 50 hosts, 10 scanned, 1000 ports, 100µs ARP delay\n  \
+                     4 aggressive: ping 250ms, connect 500ms, 200 hosts, 20 scanned, 2000 ports, 20µs ARP delay\n  \
+                     5 insane:     ping 100ms, connect 250ms, 500 hosts, 50 scanned, 5000 ports, no ARP delay"
+                )
+                .default_value("3")
+                .value_parser(|s: &str| s.parse::<TimingProfile>().map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Don't draw progress bars (status lines are still printed)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("count-only")
+                .long("count-only")
+                .help("Run discovery only and print just the number of active hosts; exits 1 when none are found")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("discover-only")
+                .long("discover-only")
+                .help("Run discovery only and list the active hosts without port scanning them; --json and the other outputs still get every host, with no open ports")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["count-only", "open-only"])
+        )
         .arg(
             Arg::new("json")
                 .short('j')
                 .long("json")
                 .value_name("FILE_PATH")
-                .help("Output scan results to JSON file")
+                .help("Output scan results to JSON file, gzipped if FILE_PATH ends in .gz")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("compact-json")
+                .long("compact-json")
+                .help("Write the JSON results on one line, without indentation, to keep big scans small")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("open-only")
+                .long("open-only")
+                .help("Leave hosts without open ports out of the console and the saved results; discovery counts still include them")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("show-filtered")
+                .long("show-filtered")
+                .help("Also report TCP ports that never answered (a firewall likely dropped the connect) as filtered; closed ports, which refused, are never listed")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("jsonl")
+                .long("jsonl")
+                .value_name("FILE")
+                .help("Write each host's result to FILE as one JSON line the moment its port scan finishes")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("events")
+                .long("events")
+                .value_name("FILE")
+                .help("Write progress events (hosts discovered, open ports, hosts scanned, scan complete) to FILE as JSON lines as they happen")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("syslog")
+                .long("syslog")
+                .value_name("HOST:PORT")
+                .help("Send an RFC 5424 syslog message over UDP to HOST:PORT for each open port, each scanned host and the end of the scan, as they happen")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("msgpack")
+                .long("msgpack")
+                .value_name("FILE")
+                .help("Also write the scan results to FILE as MessagePack, a compact binary form of the JSON")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("csv")
+                .long("csv")
+                .value_name("FILE")
+                .help("Also write the results to FILE as CSV: one row per open port (ip, hostname, port, protocol, banner, discovery_method)")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("grepable")
+                .long("grepable")
+                .visible_alias("oG")
+                .value_name("FILE")
+                .help("Also write the results to FILE in nmap's grepable format (Host: ... Ports: 22/open/tcp//ssh///)")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .visible_alias("oN")
+                .value_name("FILE")
+                .help("Also write a plain text report to FILE: the scan's details, then each host's open ports, services and banners, without colours or escape codes")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("report-html")
+                .long("report-html")
+                .visible_alias("html")
+                .value_name("FILE")
+                .help("Write a self-contained HTML report (sortable host table, port details, top services) to FILE")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("sqlite")
+                .long("sqlite")
+                .value_name("FILE")
+                .help("Append the run to the SQLite database FILE (tables scans, hosts and ports); needs a build with the sqlite feature")
                 .value_parser(clap::value_parser!(String))
         )
+        .arg(
+            Arg::new("compare")
+                .long("compare")
+                .value_name("OLD.json")
+                .help("Compare the results with an earlier --json file and print new and disappeared hosts and opened and closed ports")
+                .value_parser(clap::value_parser!(String))
+                .conflicts_with("count-only")
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .value_name("FILE")
+                .help("Continue an interrupted scan saved with --json: skip the hosts already in FILE, scan the rest and write the combined results back to FILE")
+                .value_parser(clap::value_parser!(String))
+                .conflicts_with("count-only")
+        )
+        .arg(
+            Arg::new("custom-probe")
+                .long("custom-probe")
+                .value_name("PORT:SEND:REGEX")
+                .help("Send SEND to PORT and use REGEX's first group as the banner (repeatable; escapes like \\r\\n and \\x3a allowed in SEND)")
+                .action(clap::ArgAction::Append)
+                .value_parser(|s: &str| s.parse::<CustomProbe>().map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("probes")
+                .long("probes")
+                .value_name("FILE")
+                .help("Identify services with the probes in FILE, one 'PORTS SERVICE q|PAYLOAD| m|REGEX|' per line, tried before the built-in banner grabbers")
+        )
+        .arg(
+            Arg::new("smart-ports")
+                .long("smart-ports")
+                .help("When a service is found, also probe ports that usually accompany it (e.g. 135, 139 and 3389 after 445)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("banner-protocols")
+                .long("banner-protocols")
+                .value_name("LIST")
+                .help("Only run these banner probes (comma-separated: http, tls, ftp, ssh, telnet, smtp, pop3, imap, dns, mysql, postgresql, redis, smb, generic); other open ports are reported without a probe")
+                .value_parser(|s: &str| parse_banner_protocols(s).map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("no-banner")
+                .long("no-banner")
+                .help("Only check which TCP ports are open, without reading their banners (much faster on hosts with many open ports)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("banner-status")
+                .long("banner-status")
+                .help("Record each banner grab outcome (success, read-timeout, connection-reset, no-data, skipped) in the JSON output")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("banner-mode")
+                .long("banner-mode")
+                .value_name("MODE")
+                .help("How much of each banner the results keep: none, short (the console's 50-character excerpt) or full")
+                .default_value("full")
+                .value_parser(|s: &str| s.parse::<BannerMode>().map_err(|e| e.to_string()))
+        )
+        .arg(
+            Arg::new("raw-banner")
+                .long("raw-banner")
+                .help("Also record each banner's bytes as received, hex-encoded, for inspecting binary protocols (TLS ports: the certificate)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("export-hosts")
+                .long("export-hosts")
+                .value_name("FILE")
+                .help("Write the active host IPs to FILE, one per line, right after discovery")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("export-details")
+                .long("export-details")
+                .help("Write --export-hosts rows as ip,mac,hostname")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("banner-dump-dir")
+                .long("banner-dump-dir")
+                .value_name("DIR")
+                .help("Write each open port's raw banner bytes to DIR/<ip>_<port>.bin")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("max-results")
+                .long("max-results")
+                .value_name("COUNT")
+                .help("Record at most COUNT open ports across the whole scan; further open ports are only counted (default: unlimited)")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("timing-trace")
+                .long("timing-trace")
+                .value_name("FILE")
+                .help("Write Chrome trace events (chrome://tracing, Perfetto) for the scan phases and per-host scans to FILE")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            Arg::new("slowest")
+                .long("slowest")
+                .value_name("COUNT")
+                .help("Number of slowest hosts to list after the scan (0 to disable)")
+                .default_value("5")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Show more detail, such as how each banner grab went, and log debug diagnostics to stderr")
+                .action(clap::ArgAction::SetTrue)
+        )
         .arg(
             Arg::new("interfaces")
                 .long("interfaces")
                 .help("List available network interfaces and exit")
                 .action(clap::ArgAction::SetTrue)
         )
-        .get_matches();
+        .arg(
+            Arg::new("schema")
+                .long("schema")
+                .help("Print the JSON Schema of the --json output and exit")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read default options from FILE instead of ./angryether.toml or $XDG_CONFIG_HOME/angryether.toml; flags on the command line override the file, which overrides the built-in defaults")
+                .value_parser(clap::value_parser!(std::path::PathBuf))
+        );
+    // Config values replace the built-in defaults, so clap still validates them and command-line flags still win
+    for (id, value) in config.defaults() {
+        cli = cli.mut_arg(id, |arg| arg.default_value(value));
+    }
+    let matches = cli.get_matches();
 
     // Check if user wants to list interfaces
     if matches.get_flag("interfaces") {
         return list_interfaces();
     }
+    if matches.get_flag("schema") {
+        println!("{}", results_schema()?);
+        return Ok(());
+    }
+
+    let count_only = matches.get_flag("count-only");
+    let discover_only = matches.get_flag("discover-only");
+    console::set_quiet(count_only);
+    console::set_progress_enabled(!matches.get_flag("quiet"));
 
     // Print banner
-    println!("{}", BANNER.red());
-
-    let interface = matches.get_one::<String>("interface").unwrap();
-    let default_ports = get_default_ports_file();
-    let ports_file = matches.get_one::<String>("ports")
-        .map(|s| s.as_str())
-        .unwrap_or(&default_ports);
-    let enable_arp = matches.get_flag("arp");
-    let arp_only = matches.get_flag("arp-only");
-    let timeout_ms = *matches.get_one::<u64>("timeout").unwrap();
-    let json_output = matches.get_one::<String>("json");
+    status!("{}", BANNER.red());
+    let scan_started = Utc::now();
+    let scan_clock = Instant::now();
+    let seed = matches.get_one::<u64>("seed").copied().unwrap_or_else(rng::generate_seed);
+    let randomize = matches.get_flag("randomize");
+    let dry_run = matches.get_flag("dry-run");
+    let echo_count = matches.get_one::<u32>("ping-count").copied();
+    let icmp_retries = matches.get_one::<u32>("icmp-retries").copied().unwrap_or(DEFAULT_ICMP_RETRIES);
+    let ping_size = matches.get_one::<u16>("ping-size").map_or(DEFAULT_PING_SIZE, |&size| size.into());
+    let open_only = matches.get_flag("open-only");
+    let show_filtered = matches.get_flag("show-filtered");
+    let arp_timeout = matches.get_one::<u64>("arp-timeout").map(|&ms| Duration::from_millis(ms));
+    status!("Seed: {}", seed);
 
-    // Get local subnet
-    let subnet = match get_local_subnet(interface) {
-        Ok(subnet) => {
-            println!("Detected Subnet: {}", subnet.to_string().green());
-            subnet
+    let interface = match matches.get_one::<String>("interface") {
+        Some(interface) => interface.clone(),
+        None => match default_interface() {
+            Ok(interface) => {
+                status!("Auto-selected interface: {}", interface.green());
+                interface
+            }
+            Err(e) => {
+                eprintln!("Error choosing an interface (name one with --interface): {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        },
+    };
+    let interface = &interface;
+    let source_ip = matches.get_one::<Ipv4Addr>("source-ip").copied();
+    if let Some(ip) = source_ip {
+        if let Err(e) = get_local_subnet(interface, Some(ip)) {
+            eprintln!("Error: {}", e.to_string().red());
+            std::process::exit(SETUP_ERROR_EXIT_CODE);
         }
-        Err(e) => {
-            eprintln!("Error detecting subnet: {}", e.to_string().red());
-            return Ok(());
+    }
+    if let Some(dir) = matches.get_one::<std::path::PathBuf>("data-dir") {
+        data::set_data_dir(dir.clone());
+    }
+    let ports_arg = matches.get_one::<String>("ports").map(|s| s.as_str());
+    let preset_ports = matches.get_one::<(usize, Vec<u16>)>("top-ports").cloned();
+    // Only TCP connects can go through a proxy, so ICMP and ARP are off with one
+    let proxy = matches.get_one::<Socks5Proxy>("proxy").cloned();
+    let enable_arp = matches.get_flag("arp") && proxy.is_none();
+    let arp_only = matches.get_flag("arp-only");
+    let promiscuous = matches.get_flag("promisc");
+    let arp_rate = matches.get_one::<u32>("arp-rate").copied();
+    let no_icmp = matches.get_flag("no-icmp") || proxy.is_some();
+    let fallback_ports = matches
+        .get_one::<Vec<u16>>("discovery-ports")
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_FALLBACK_PORTS.to_vec());
+    let nearby = matches.get_one::<(Ipv4Addr, Option<u32>)>("nearby");
+    let target = matches.get_one::<Target>("target");
+    let read_stdin = matches.get_flag("stdin");
+    let ipv6 = matches.get_flag("ipv6");
+    let max_hosts = matches.get_one::<usize>("max-hosts").copied();
+    let split = matches.get_one::<(usize, usize)>("split").copied();
+    let mut exclusions = matches.get_one::<Exclusions>("exclude").cloned().unwrap_or_default();
+    let alive_policy = matches.get_one::<AlivePolicy>("alive-policy").unwrap();
+    let discovery_order = *matches.get_one::<DiscoveryOrder>("discovery-order").unwrap();
+    let portscan_filter = *matches.get_one::<Option<DiscoveryMethod>>("portscan-method-filter").unwrap();
+    let probe_budget = Arc::new(ProbeBudget::new(matches.get_one::<u64>("max-probes").copied()));
+    let rtt_band = RttBand {
+        min: matches.get_one::<Duration>("min-rtt-ms").copied(),
+        max: matches.get_one::<Duration>("max-rtt-ms").copied(),
+        unknown: *matches.get_one::<RttUnknownPolicy>("rtt-unknown").unwrap(),
+    };
+    // Ping statistics and the RTT band need every host's ping replies
+    let ping_arp_found =
+        matches.get_flag("ping-arp-found") || echo_count.is_some() || rtt_band.min.is_some() || rtt_band.max.is_some();
+    let timing = *matches.get_one::<TimingProfile>("timing").unwrap();
+    let timeout_ms = matches.get_one::<u64>("timeout").copied().unwrap_or(timing.ping_timeout_ms);
+    let port_timeout_ms = matches.get_one::<u64>("port-timeout").copied().unwrap_or(timing.connect_timeout_ms);
+    let host_concurrency = matches.get_one::<u64>("host-concurrency").map_or(timing.host_concurrency, |&count| count as usize);
+    let banner_timeout_ms = *matches.get_one::<u64>("banner-timeout").unwrap();
+    let jitter = Jitter::new(
+        matches.get_one::<u64>("jitter").map_or(timing.jitter, |&ms| Duration::from_millis(ms)),
+        seed,
+    );
+    if !jitter.max().is_zero() {
+        status!("Jitter: up to {:?} before each probe", jitter.max());
+    }
+    let max_rate_per_host = matches.get_one::<u32>("max-rate-per-host").copied();
+    if let Some(rate) = max_rate_per_host {
+        status!("Rate limit: {} probes a second per host", rate);
+    }
+    let banner_bytes = *matches.get_one::<u32>("banner-bytes").unwrap() as usize;
+    let resume_path = matches.get_one::<String>("resume");
+    // A resumed scan is written back to the file it came from, in its format, unless
+    // --json or --msgpack says otherwise
+    let resume_msgpack = resume_path.filter(|path| is_msgpack_path(path));
+    let resume_json = resume_path.filter(|path| !is_msgpack_path(path));
+    let json_output = match matches.value_source("json") {
+        Some(ValueSource::CommandLine) => matches.get_one::<String>("json"),
+        _ => resume_json.or(matches.get_one::<String>("json")),
+    };
+    let compact_json = matches.get_flag("compact-json");
+    let html_report = matches.get_one::<String>("report-html");
+    let msgpack_output = match matches.value_source("msgpack") {
+        Some(ValueSource::CommandLine) => matches.get_one::<String>("msgpack"),
+        _ => resume_msgpack.or(matches.get_one::<String>("msgpack")),
+    };
+    let csv_output = matches.get_one::<String>("csv");
+    let grepable_output = matches.get_one::<String>("grepable");
+    let text_output = matches.get_one::<String>("output");
+    let sqlite_output = matches.get_one::<String>("sqlite");
+    if sqlite_output.is_some() && !cfg!(feature = "sqlite") {
+        eprintln!("{}", "Error: --sqlite needs AngryEther built with the sqlite feature (cargo build --features sqlite)".red());
+        std::process::exit(SETUP_ERROR_EXIT_CODE);
+    }
+    let online_oui = matches.get_flag("online-oui");
+    if online_oui && !cfg!(feature = "online-oui") {
+        eprintln!("{}", "Error: --online-oui needs AngryEther built with the online-oui feature (cargo build --features online-oui)".red());
+        std::process::exit(SETUP_ERROR_EXIT_CODE);
+    }
+    let banner_dump_dir = matches.get_one::<String>("banner-dump-dir").map(Path::new);
+    let export_hosts = matches.get_one::<String>("export-hosts");
+    let export_details = matches.get_flag("export-details");
+    let slowest_count = *matches.get_one::<usize>("slowest").unwrap();
+    let max_results = matches.get_one::<usize>("max-results").copied();
+    let record_banner_status = matches.get_flag("banner-status");
+    let record_raw_banner = matches.get_flag("raw-banner");
+    let banner_mode = *matches.get_one::<BannerMode>("banner-mode").unwrap();
+    let smart_ports = matches.get_flag("smart-ports");
+    let grab_banners = !matches.get_flag("no-banner");
+    let retries = *matches.get_one::<u8>("retries").unwrap();
+    let knock = matches.get_one::<Vec<u16>>("knock").cloned().unwrap_or_default();
+    let knock_guard = matches.get_one::<u16>("knock-guard").copied();
+    let source_port = matches.get_one::<u16>("source-port").copied();
+    if let Some(port) = source_port {
+        if let Err(e) = check_source_port(port) {
+            eprintln!("Error: {}", e.to_string().red());
+            std::process::exit(SETUP_ERROR_EXIT_CODE);
         }
+    }
+    let mut scan_protocol = if matches.get_flag("udp") {
+        ScanProtocol::Both
+    } else {
+        *matches.get_one::<ScanProtocol>("scan-protocol").unwrap()
     };
+    if let Some(proxy) = &proxy {
+        if scan_protocol == ScanProtocol::Udp {
+            eprintln!("Error: {}", "UDP can't go through a SOCKS5 proxy; use --scan-protocol tcp with --proxy".red());
+            std::process::exit(SETUP_ERROR_EXIT_CODE);
+        }
+        if let Err(e) = proxy.check().await {
+            eprintln!("Error: {}", e.to_string().red());
+            std::process::exit(SETUP_ERROR_EXIT_CODE);
+        }
+        scan_protocol = ScanProtocol::Tcp;
+        eprintln!(
+            "{}",
+            "Warning: ICMP, ARP and UDP can't go through the proxy, so hosts are discovered by TCP connects through it and only TCP ports are scanned".yellow()
+        );
+        status!("Proxy: TCP connects go through {}", proxy.address());
+    }
+    let udp_ports = matches
+        .get_one::<Vec<u16>>("udp-ports")
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_UDP_PORTS.to_vec());
+    let udp_timeout_ms = *matches.get_one::<u64>("udp-timeout").unwrap();
+    let snmp_community = matches.get_one::<String>("snmp-community").unwrap();
+    let banner_protocols = matches.get_one::<HashSet<BannerProtocol>>("banner-protocols").cloned();
+    let timing_trace_path = matches.get_one::<String>("timing-trace");
+    let timing_trace = TimingTrace::new(timing_trace_path.is_some());
+    timing_trace.name_track(PHASE_TRACK, "scan phases");
+    let verbose = matches.get_flag("verbose");
+    // Diagnostics go to stderr through `log`, apart from the status lines on stdout;
+    // RUST_LOG overrides the level
+    let log_filter = if verbose { "warn,angryether=debug" } else { "warn" };
+    let logger = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_filter))
+        .target(env_logger::Target::Pipe(Box::new(console::LogWriter)))
+        .build();
+    log::set_max_level(logger.filter().max(log::LevelFilter::Info));
+    log::set_boxed_logger(Box::new(ConsoleLogger(logger)))?;
+    let custom_probes: Vec<CustomProbe> = matches
+        .get_many::<CustomProbe>("custom-probe")
+        .map(|probes| probes.cloned().collect())
+        .unwrap_or_default();
+
+    // First Ctrl-C cancels the scan so partial results still get written; a
+    // second one quits at once
+    let cancel = CancellationToken::new();
+    let timed_out = Arc::new(AtomicBool::new(false));
+    if let Some(&seconds) = matches.get_one::<u64>("max-duration") {
+        // Cancels the scan like Ctrl-C does, and the grace period after it
+        // bounds the time spent writing results
+        tokio::spawn({
+            let cancel = cancel.clone();
+            let timed_out = Arc::clone(&timed_out);
+            async move {
+                tokio::time::sleep(Duration::from_secs(seconds)).await;
+                timed_out.store(true, Ordering::Relaxed);
+                cancel.cancel();
+                eprintln!("{}", format!("\nTime limit of {}s reached: stopping the scan, then writing partial results", seconds).yellow());
+                tokio::time::sleep(MAX_DURATION_GRACE).await;
+                eprintln!("{}", "Results still not written after the time limit, quitting".red());
+                std::process::exit(TIMED_OUT_EXIT_CODE);
+            }
+        });
+    }
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            cancel.cancel();
+            eprintln!("{}", "\nInterrupted: stopping the scan, then writing partial results (Ctrl-C again to quit now)".yellow());
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        }
+    });
 
-    // Get all hosts in the subnet
-    let hosts = get_network_hosts(subnet);
-    println!("Scanning {} hosts in subnet...", hosts.len());
+    let probe_set = match matches.get_one::<String>("probes") {
+        Some(path) => match ProbeSet::load(path) {
+            Ok(probe_set) => {
+                status!("Loaded {} service probes from {}", probe_set.len(), path);
+                probe_set
+            }
+            Err(e) => {
+                eprintln!("Error reading probes file: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        },
+        None => ProbeSet::default(),
+    };
 
-    let mut active_hosts = HashSet::new();
-    let mut mac_addresses: HashMap<Ipv4Addr, String> = HashMap::new();
+    let mut jsonl_writer = match matches.get_one::<String>("jsonl") {
+        Some(path) => match JsonlWriter::create(path) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("Error creating JSONL file '{}': {}", path, e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        },
+        None => None,
+    };
 
-    if !arp_only {
-        // Initialize ping scanner
-        let ping_scanner = PingScanner::new()?;
-
-        // Perform ping sweep
-        println!("Performing enhanced ping sweep (ICMP + TCP fallback, {}ms timeout per host)...", timeout_ms);
-        let ping_hosts = ping_scanner.sweep(hosts.clone(), timeout_ms).await;
-        for host in ping_hosts {
-            active_hosts.insert(host);
-        }
-        println!("Found {} hosts via ICMP ping", active_hosts.len());
-    }
-
-    // Perform ARP sweep only if explicitly enabled
-    if enable_arp || arp_only {
-        println!("Performing ARP sweep...");
-        match ArpScanner::new(interface) {
-            Ok(mut arp_scanner) => {
-                let arp_hosts = arp_scanner.sweep(hosts).await;
-                let arp_count = arp_hosts.len();
-                for (ip, mac) in &arp_hosts {
-                    active_hosts.insert(*ip);
-                    mac_addresses.insert(*ip, mac.to_string());
-                }
-                println!("Found {} hosts via ARP scan", arp_count);
-                println!("Collected {} MAC addresses", mac_addresses.len());
-                println!("Total unique hosts: {}", active_hosts.len());
+    let events_file = match matches.get_one::<String>("events") {
+        Some(path) => match JsonlWriter::create(path) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("Error creating events file '{}': {}", path, e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        },
+        None => None,
+    };
+    let syslog = match matches.get_one::<String>("syslog") {
+        Some(target) => match SyslogSink::connect(target, scan_started).await {
+            Ok(sink) => Some(sink),
+            Err(e) => {
+                eprintln!("Error connecting to syslog collector '{}': {}", target, e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        },
+        None => None,
+    };
+    let event_log = (events_file.is_some() || syslog.is_some()).then(|| EventLog::start(events_file, syslog));
+    let events = event_log.as_ref().map(|log| log.sender.clone());
+
+    // Loaded up front so a bad file fails before the scan rather than after it
+    let previous_results = match matches.get_one::<String>("compare") {
+        Some(path) => match read_results(path) {
+            Ok(results) => Some(results),
+            Err(e) => {
+                eprintln!("Error reading results to compare against: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(path) = matches.get_one::<String>("exclude-file") {
+        if let Err(e) = exclusions.add_file(path) {
+            eprintln!("Error reading exclude file: {}", e.to_string().red());
+            std::process::exit(SETUP_ERROR_EXIT_CODE);
+        }
+    }
+
+    if let Some(dir) = banner_dump_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Error creating banner dump directory '{}': {}", dir.display(), e.to_string().red());
+            std::process::exit(SETUP_ERROR_EXIT_CODE);
+        }
+    }
+
+    let gateway = if ipv6 { None } else { get_default_gateway(interface) };
+    if let Some(gateway) = gateway {
+        status!("Default gateway: {}", gateway.to_string().green());
+    }
+    // The hostname each resolved --target address came from
+    let mut target_names: HashMap<IpAddr, String> = HashMap::new();
+    let (mut hosts, subnet): (Vec<IpAddr>, String) = if let Some((center, window)) = nearby {
+        // Focused sweep around a known host instead of the whole local subnet
+        let hosts = nearby_hosts(*center, *window);
+        let label = match window {
+            Some(_) => format!("{}-{}", hosts[0], hosts[hosts.len() - 1]),
+            None => Ipv4Network::new(*center, 24).map(|net| format!("{}/24", net.network())).unwrap_or_default(),
+        };
+        status!("Nearby sweep around {}: {}", center.to_string().green(), label.green());
+        (hosts.into_iter().map(IpAddr::V4).collect(), label)
+    } else if let Some(&Target::Range(first, last)) = target {
+        let hosts = match target_hosts(first, last, max_hosts) {
+            Ok(hosts) => hosts,
+            Err(e) => {
+                eprintln!("Error: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
             }
+        };
+        let label = if first == last { first.to_string() } else { format!("{}-{}", first, last) };
+        status!("Target range: {}", label.green());
+        (hosts.into_iter().map(IpAddr::V4).collect(), label)
+    } else if let Some(Target::Hostnames(names)) = target {
+        let resolved = match resolve_hostnames(names).await {
+            Ok(resolved) => resolved,
             Err(e) => {
-                eprintln!("Warning: ARP scanning failed: {}", e.to_string().yellow());
-                if arp_only {
-                    eprintln!("ARP-only mode failed, no results available.");
-                    return Ok(());
+                eprintln!("Error resolving target: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        };
+        for (ip, name) in &resolved {
+            status!("Resolved {} to {}", name.green(), ip.to_string().green());
+            target_names.insert(IpAddr::V4(*ip), name.clone());
+        }
+        (resolved.into_iter().map(|(ip, _)| IpAddr::V4(ip)).collect(), names.join(","))
+    } else if read_stdin {
+        let targets = match std::io::read_to_string(std::io::stdin()).map_err(anyhow::Error::from).and_then(|text| parse_target_list(&text)) {
+            Ok(targets) => targets,
+            Err(e) => {
+                eprintln!("Error reading targets from stdin: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        };
+        let expanded = match expand_targets(&targets, max_hosts).await {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                eprintln!("Error: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        };
+        status!("Read {} targets from stdin: {} addresses", targets.len(), expanded.len().to_string().green());
+        let mut hosts = Vec::with_capacity(expanded.len());
+        for (ip, name) in expanded {
+            if let Some(name) = name {
+                target_names.insert(IpAddr::V4(ip), name);
+            }
+            hosts.push(IpAddr::V4(ip));
+        }
+        (hosts, "stdin".to_string())
+    } else if ipv6 {
+        let subnet = match get_local_subnet_v6(interface) {
+            Ok(subnet) => {
+                status!("Detected IPv6 Subnet: {}", subnet.to_string().green());
+                subnet
+            }
+            Err(e) => {
+                eprintln!("Error detecting IPv6 subnet: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        };
+
+        let hosts = match get_network_hosts_v6(subnet, max_hosts) {
+            Ok(hosts) => hosts,
+            Err(e) => {
+                eprintln!("Error: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        };
+        if (hosts.len() as u128) < subnet.size() {
+            status!("Taking the first {} of {} addresses (--max-hosts)", hosts.len(), subnet.size());
+        }
+        (hosts.into_iter().map(IpAddr::V6).collect(), subnet.to_string())
+    } else {
+        // Get local subnet
+        let subnet = match get_local_subnet(interface, source_ip) {
+            Ok(subnet) => {
+                status!("Detected Subnet: {}", subnet.to_string().green());
+                subnet
+            }
+            Err(e) => {
+                eprintln!("Error detecting subnet: {}", e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        };
+
+        // Get all hosts in the subnet
+        (get_network_hosts(subnet, max_hosts).into_iter().map(IpAddr::V4).collect(), subnet.to_string())
+    };
+    if !exclusions.is_empty() {
+        let before = hosts.len();
+        hosts.retain(|&host| !exclusions.contains(host));
+        status!("Excluded {} of {} hosts", before - hosts.len(), before);
+    }
+    if let Some(max) = max_hosts {
+        hosts.truncate(max);
+    }
+    // A dry run sends nothing, so any size is fine to list
+    if hosts.len() > LARGE_SCAN_HOSTS && !matches.get_flag("yes") && !dry_run {
+        let question = format!("Really scan {} addresses?", hosts.len());
+        // The read blocks, so Ctrl-C at the prompt has to end it from here
+        let answer = tokio::select! {
+            answer = tokio::task::spawn_blocking(move || console::confirm(&question)) => answer?,
+            _ = cancel.cancelled() => std::process::exit(130),
+        };
+        match answer {
+            Some(true) => {}
+            Some(false) => {
+                eprintln!("Scan cancelled");
+                std::process::exit(1);
+            }
+            None => {
+                eprintln!(
+                    "Error: {}",
+                    format!(
+                        "refusing to scan {} addresses (more than {}) without --yes when stdin is not a terminal",
+                        hosts.len(),
+                        LARGE_SCAN_HOSTS
+                    )
+                    .red()
+                );
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        }
+    }
+    // Link-local IPv6 targets are only reachable through the scanned interface
+    let scope_id = if ipv6 { interface_scope_id(interface) } else { None };
+
+    let mut hosts = match split {
+        Some((count, index)) => {
+            let shard = shard_hosts(&hosts, count, index);
+            match (shard.first(), shard.last()) {
+                (Some(first), Some(last)) => status!(
+                    "Shard {} of {}: {}-{} ({} of {} hosts)",
+                    index, count, first.to_string().green(), last.to_string().green(), shard.len(), hosts.len()
+                ),
+                _ => status!("Shard {} of {} is empty ({} hosts total)", index, count, hosts.len()),
+            }
+            shard
+        }
+        None => hosts,
+    };
+
+    let resumed = match resume_path {
+        Some(path) => match read_resume_file(path, interface, &subnet) {
+            Ok(resumed) => Some(resumed),
+            Err(e) => {
+                eprintln!("Error resuming from {}: {}", path, e.to_string().red());
+                std::process::exit(SETUP_ERROR_EXIT_CODE);
+            }
+        },
+        None => None,
+    };
+    if let Some(resumed) = &resumed {
+        let done: HashSet<&str> = resumed.hosts.iter().map(|host| host.ip.as_str()).collect();
+        let before = hosts.len();
+        hosts.retain(|host| !done.contains(host.to_string().as_str()));
+        status!(
+            "Resuming {}: skipping {} hosts already scanned",
+            resume_path.unwrap().green(),
+            before - hosts.len()
+        );
+    }
+    // After exclusions, sharding and resume so only the hosts left to scan are shuffled
+    if randomize {
+        rng::shuffle_seeded(&mut hosts, seed, "host-order");
+    }
+    status!("Scanning {} hosts in subnet...", hosts.len());
+    let hosts_scanned = hosts.len();
+
+    // Load ports from the preset, an inline list, or a file when one exists by that name
+    let mut ports = match (preset_ports, ports_arg) {
+        (Some((count, ports)), _) => {
+            status!("Using the top {} ports", count);
+            ports
+        }
+        (None, Some(ports_arg)) => {
+            let (ports, ports_source) = if is_port_spec(ports_arg) && !Path::new(ports_arg).exists() {
+                let source = match matches.value_source("ports") {
+                    Some(ValueSource::CommandLine) => "the command line",
+                    _ => "the config file",
+                };
+                (parse_port_spec(ports_arg), source)
+            } else {
+                (read_ports_from_file(ports_arg), ports_arg)
+            };
+            match ports {
+                Ok(ports) => {
+                    status!("Loaded {} ports from {}", ports.len(), ports_source);
+                    ports
+                }
+                Err(e) => {
+                    eprintln!("Error reading ports '{}': {}", ports_arg, e.to_string().red());
+                    std::process::exit(SETUP_ERROR_EXIT_CODE);
                 }
-                eprintln!("Continuing with ICMP results only...");
             }
         }
+        (None, None) => match read_ports_from_file(DEFAULT_PORTS_FILE) {
+            Ok(ports) => {
+                status!("Loaded {} ports from {}", ports.len(), DEFAULT_PORTS_FILE);
+                ports
+            }
+            Err(_) => {
+                // No ports directory installed: the same list is built in
+                let ports = builtin_ports();
+                status!("{} not found, using the built-in list of {} ports", DEFAULT_PORTS_FILE, ports.len());
+                ports
+            }
+        },
+    };
+
+    if randomize {
+        rng::shuffle_seeded(&mut ports, seed, "port-order");
+    }
+
+    if dry_run {
+        let tcp_ports = if scan_protocol.includes(Protocol::Tcp) { ports.clone() } else { Vec::new() };
+        let udp_ports = if scan_protocol.includes(Protocol::Udp) { udp_ports.clone() } else { Vec::new() };
+        status!("\nDry run: {} hosts, {} ports per host", hosts.len(), tcp_ports.len() + udp_ports.len());
+        for host in &hosts {
+            println!("{}", host);
+        }
+        if let Some(json_path) = json_output {
+            let plan = TargetPlan {
+                interface: interface.clone(),
+                subnet,
+                seed,
+                hosts: hosts.iter().map(IpAddr::to_string).collect(),
+                tcp_ports,
+                udp_ports,
+            };
+            match write_target_plan(&plan, json_path) {
+                Ok(_) => status!("Target list saved to {}", json_path.green()),
+                Err(e) => eprintln!("Failed to write JSON file: {}", e.to_string().red()),
+            }
+        }
+        return Ok(());
+    }
+
+    let arp_enabled = enable_arp || arp_only;
+    if ipv6 && arp_enabled {
+        eprintln!("{}", "Warning: ARP only resolves IPv4 addresses, so it finds nothing in an IPv6 scan".yellow());
+    }
+    if !grab_banners && (!custom_probes.is_empty() || !probe_set.is_empty() || banner_protocols.is_some()) {
+        eprintln!(
+            "{}",
+            "Warning: --no-banner skips all banner probes, so --custom-probe, --probes and --banner-protocols have no effect".yellow()
+        );
+    }
+    if no_icmp && !alive_policy.is_alive(&HashSet::from([DiscoveryMethod::Tcp, DiscoveryMethod::Arp])) {
+        eprintln!(
+            "{}",
+            format!("Warning: alive policy '{}' needs ICMP, so no host can pass it with --no-icmp", alive_policy).yellow()
+        );
+    }
+    if promiscuous && !arp_enabled {
+        eprintln!("{}", "Warning: --promisc only affects ARP scanning (use --arp or --arp-only)".yellow());
+    }
+    if !arp_enabled && !alive_policy.is_alive(&HashSet::from([DiscoveryMethod::Icmp, DiscoveryMethod::Tcp])) {
+        eprintln!("{}", "Warning: alive policy requires ARP but ARP scanning is disabled (use --arp)".yellow());
+    }
+    if arp_only && !alive_policy.is_alive(&HashSet::from([DiscoveryMethod::Arp])) {
+        eprintln!("{}", "Warning: alive policy needs ICMP/TCP signals, which --arp-only never collects".yellow());
+    }
+    if let (Some(min), Some(max)) = (rtt_band.min, rtt_band.max) {
+        if min > max {
+            eprintln!("{}", "Warning: --min-rtt-ms is above --max-rtt-ms, so only hosts without an RTT can pass".yellow());
+        }
+    }
+    match portscan_filter {
+        Some(DiscoveryMethod::Arp) if !arp_enabled => {
+            eprintln!("{}", "Warning: --portscan-method-filter arp needs ARP scanning (use --arp)".yellow());
+        }
+        Some(DiscoveryMethod::Icmp | DiscoveryMethod::Tcp) if arp_only => {
+            eprintln!("{}", "Warning: --portscan-method-filter needs the ping sweep, which --arp-only skips".yellow());
+        }
+        _ => {}
+    }
+
+    // Raw socket failures otherwise surface as bare channel/permission errors
+    if ((!arp_only && !no_icmp) || arp_enabled) && !has_raw_socket_capability() {
+        eprintln!(
+            "{}",
+            "Warning: ARP and ICMP require sudo or CAP_NET_RAW (e.g. sudo setcap cap_net_raw+ep on the binary); --no-icmp sweeps with TCP only and needs neither".yellow()
+        );
+    }
+
+    let mut discovery = Discovery::new(discovery_order, alive_policy, &timing_trace)
+        .with_ping_arp_found(ping_arp_found)
+        .with_portscan_filter(portscan_filter);
+    if !arp_only {
+        let ping_scanner = if no_icmp { PingScanner::tcp_only() } else { PingScanner::new()? };
+        let mut ping_scanner = ping_scanner
+            .with_fallback_ports(fallback_ports.clone())
+            .with_seed(seed)
+            .with_budget(Arc::clone(&probe_budget))
+            .with_jitter(jitter)
+            .with_cancellation(cancel.clone())
+            .with_concurrency(timing.ping_concurrency)
+            .with_icmp_retries(icmp_retries)
+            .with_ping_size(ping_size);
+        if let Some(count) = echo_count {
+            ping_scanner = ping_scanner.with_ping_count(count);
+        }
+        if let Some(proxy) = &proxy {
+            ping_scanner = ping_scanner.with_proxy(proxy.clone());
+        }
+        if let Some(events) = &events {
+            ping_scanner = ping_scanner.with_events(events.clone());
+        }
+        if ipv6 {
+            ping_scanner = ping_scanner.with_ipv6_interface(interface);
+        }
+        discovery = discovery.with_ping(ping_scanner, timeout_ms);
+    }
+    if arp_enabled {
+        discovery = discovery.with_arp(|| {
+            if promiscuous {
+                status!(
+                    "{}",
+                    "Enabling promiscuous mode for the scan (requires root or CAP_NET_RAW; released when the scan ends)".yellow()
+                );
+            }
+            let mut scanner = ArpScanner::new(interface, promiscuous)?
+                .with_budget(Arc::clone(&probe_budget))
+                .with_jitter(jitter)
+                .with_cancellation(cancel.clone());
+            if let Some(ip) = source_ip {
+                scanner = scanner.with_source_ip(ip);
+            }
+            if let Some(window) = arp_timeout {
+                scanner = scanner.with_response_window(window);
+            }
+            if let Some(events) = &events {
+                scanner = scanner.with_events(events.clone());
+            }
+            let scanner = match arp_rate {
+                Some(rate) => scanner.with_rate(rate),
+                None => scanner.with_burst_delay(timing.arp_burst_delay),
+            };
+            if promiscuous || verbose {
+                let state = match scanner.interface_promiscuous() {
+                    Some(true) => "on",
+                    Some(false) => "off",
+                    None => "unknown on this platform",
+                };
+                status!("Interface {} promiscuous mode: {}", interface, state);
+            }
+            Ok(scanner)
+        });
+    }
+    let mut found = match discovery.run(&hosts).await {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("Error: {}", format!("{:#}", e).red());
+            std::process::exit(SETUP_ERROR_EXIT_CODE);
+        }
+    };
+    if let Some(e) = &found.arp_error {
+        eprintln!("Warning: ARP scanning failed, so hosts were only found by ping: {}", e.to_string().yellow());
     }
 
-    let active_hosts: Vec<_> = active_hosts.into_iter().collect();
+    // In sweep order, which the port scans and outputs keep
+    let mut active_hosts = found.active_hosts(&hosts, alive_policy);
+    if *alive_policy != AlivePolicy::default() {
+        status!(
+            "{} of {} responding hosts satisfy alive policy '{}'",
+            active_hosts.len(),
+            found.methods.len(),
+            alive_policy
+        );
+    }
 
+    if rtt_band.is_active() {
+        let before = active_hosts.len();
+        active_hosts.retain(|host| rtt_band.admits(found.ping_replies.get(host).map(|reply| reply.rtt)));
+        status!("RTT filter dropped {} of {} active hosts", before - active_hosts.len(), before);
+    }
+
+    if count_only {
+        println!("{}", active_hosts.len());
+        if let Some(log) = event_log {
+            log.finish(ScanEvent::ScanComplete {
+                hosts_scanned,
+                active_hosts: active_hosts.len(),
+                open_ports: 0,
+                duration_ms: scan_clock.elapsed().as_millis() as u64,
+            })
+            .await;
+        }
+        save_timing_trace(&timing_trace, timing_trace_path);
+        // A stopped count is short, and its status says so before found/not-found
+        exit_if_stopped(&cancel, &timed_out);
+        std::process::exit(if active_hosts.is_empty() { 1 } else { 0 });
+    }
+
+    // Carries on regardless, so --json and the other outputs still get an empty hosts list
     if active_hosts.is_empty() {
-        println!("No active hosts found.");
-        return Ok(());
+        status!("No active hosts found.");
     }
 
     // Try to fill in missing MAC addresses from system ARP cache
-    if enable_arp || arp_only {
+    if arp_enabled {
         let system_cache = read_system_arp_cache();
         let mut cache_hits = 0;
         for host in &active_hosts {
-            if !mac_addresses.contains_key(host) {
-                if let Some(mac) = system_cache.get(host) {
-                    mac_addresses.insert(*host, mac.clone());
+            // The kernel ARP cache only holds IPv4 neighbours
+            let IpAddr::V4(ip) = host else {
+                continue;
+            };
+            if !found.mac_addresses.contains_key(host) {
+                if let Some(mac) = system_cache.get(ip) {
+                    found.mac_addresses.insert(*host, mac.clone());
                     cache_hits += 1;
                 }
             }
         }
         if cache_hits > 0 {
-            println!("Filled {} MAC addresses from system ARP cache", cache_hits);
+            status!("Filled {} MAC addresses from system ARP cache", cache_hits);
         }
     }
 
-    println!("\nProceeding with {} active hosts for port scanning", active_hosts.len());
+    // Keyed by MAC; empty unless --online-oui asked for lookups
+    #[cfg(feature = "online-oui")]
+    let vendors = if online_oui && !found.mac_addresses.is_empty() {
+        use angryether::oui::{default_cache_path, lookup_vendors, VendorCache};
+        let mut cache = VendorCache::load(default_cache_path());
+        let vendors = lookup_vendors(found.mac_addresses.values(), &mut cache).await;
+        if let Err(e) = cache.save() {
+            eprintln!("{}", format!("Warning: OUI vendor cache not saved: {}", e).yellow());
+        }
+        status!("Found vendors for {} of {} MAC addresses", vendors.len(), found.mac_addresses.len());
+        vendors
+    } else {
+        HashMap::new()
+    };
+    #[cfg(not(feature = "online-oui"))]
+    let vendors: HashMap<String, String> = HashMap::new();
+
+    let duplicate_macs = find_duplicate_macs(&found.mac_addresses);
+    for duplicate in &duplicate_macs {
+        eprintln!(
+            "{}",
+            format!(
+                "Warning: MAC {} answers for {} IPs ({}): proxy ARP on a router, or possible ARP spoofing",
+                duplicate.mac,
+                duplicate.ips.len(),
+                duplicate.ips.join(", ")
+            )
+            .yellow()
+        );
+    }
 
-    // Load ports from file
-    let ports = match read_ports_from_file(ports_file) {
-        Ok(ports) => {
-            println!("Loaded {} ports from {}", ports.len(), ports_file);
-            ports
+    if let Some(export_path) = export_hosts {
+        match write_hosts_export(export_path, &active_hosts, &found.mac_addresses, &target_names, export_details, scan_started, &subnet) {
+            Ok(_) => status!("Exported {} hosts to {}", active_hosts.len(), export_path.green()),
+            Err(e) => eprintln!("Failed to export hosts: {}", e.to_string().red()),
         }
-        Err(e) => {
-            eprintln!("Error reading ports file '{}': {}", ports_file, e.to_string().red());
-            return Ok(());
+    }
+
+    // The filter only decides who gets port scanned, so it has nothing to do here
+    let scan_targets: Vec<IpAddr> = match portscan_filter.filter(|_| !discover_only) {
+        Some(method) => {
+            let targets: Vec<IpAddr> = active_hosts
+                .iter()
+                .copied()
+                .filter(|host| found.methods[host].contains(&method))
+                .collect();
+            status!(
+                "{} of {} active hosts answered {} and will be port scanned",
+                targets.len(),
+                active_hosts.len(),
+                method
+            );
+            targets
         }
+        None => active_hosts.clone(),
     };
 
+    if discover_only {
+        status!("\n{} active hosts:", scan_targets.len());
+        for host in &scan_targets {
+            match found.mac_addresses.get(host) {
+                Some(mac) => match vendors.get(mac) {
+                    Some(vendor) => status!("  {} [MAC: {}, {}]", host.to_string().green(), mac, vendor),
+                    None => status!("  {} [MAC: {}]", host.to_string().green(), mac),
+                },
+                None => status!("  {}", host.to_string().green()),
+            }
+        }
+    } else {
+        status!("\nProceeding with {} active hosts for port scanning", scan_targets.len());
+    }
+
     // Initialize port scanner
-    let port_scanner = PortScanner::new(1000);
-    
-    // Scan each active host and collect results
-    println!("\nStarting port scans...");
-    let mut scan_results = Vec::new();
-    
-    for host in &active_hosts {
-        let mac_addr = mac_addresses.get(host);
-        let open_ports_data = port_scanner.scan_ports(*host, &ports, mac_addr.map(|s| s.as_str())).await;
-        let open_ports: Vec<OpenPort> = open_ports_data
-            .into_iter()
-            .map(|(port, banner)| OpenPort { port, banner })
-            .collect();
-
-        scan_results.push(HostResult {
-            ip: host.to_string(),
-            mac_address: mac_addr.cloned(),
-            discovery_method: "ICMP/TCP".to_string(), // Simplified for now
-            open_ports,
-        });
+    let mut port_scanner = PortScanner::new(port_timeout_ms)
+        .with_concurrency(timing.port_concurrency)
+        .with_banner_timeout(banner_timeout_ms)
+        .with_banner_bytes(banner_bytes)
+        .with_open_only(open_only)
+        .with_show_filtered(show_filtered)
+        .with_custom_probes(custom_probes)
+        .with_probe_set(probe_set)
+        .with_verbose(verbose)
+        .with_smart_ports(smart_ports)
+        .with_banners(grab_banners)
+        .with_banner_mode(banner_mode)
+        .with_retries(retries)
+        .with_knock(knock, knock_guard)
+        .with_banner_protocols(banner_protocols)
+        .with_budget(Arc::clone(&probe_budget))
+        .with_jitter(jitter)
+        .with_cancellation(cancel.clone())
+        .with_scope_id(scope_id)
+        .with_source_port(source_port)
+        .with_udp(udp_ports, udp_timeout_ms)
+        .with_snmp_community(snmp_community);
+    if let Some(events) = events {
+        port_scanner = port_scanner.with_events(events);
     }
+    if let Some(rate) = max_rate_per_host {
+        port_scanner = port_scanner.with_max_rate_per_host(rate);
+    }
+    if let Some(proxy) = &proxy {
+        port_scanner = port_scanner.with_proxy(proxy.clone());
+    }
+
+    // Scan each active host and collect results
+    let ports_per_host = if discover_only {
+        0
+    } else {
+        status!("\nStarting port scans...");
+        port_scanner.probe_count(&ports, scan_protocol)
+    };
+    let progress = console::start_progress((scan_targets.len() * ports_per_host) as u64, "ports scanned");
+    let port_scanner = port_scanner.with_progress(progress);
 
-    println!("\nScan completed!");
-    
-    // Generate JSON output if requested
-    if let Some(json_path) = json_output {
-        let mut discovery_methods = vec!["ICMP", "TCP"];
-        if enable_arp || arp_only {
-            discovery_methods.push("ARP");
-        }
-        
-        let results = ScanResults {
-            timestamp: Utc::now(),
-            interface: interface.clone(),
-            subnet: subnet.to_string(),
-            timeout_ms,
-            total_hosts_scanned: subnet.size() as usize,
-            active_hosts_found: active_hosts.len(),
-            discovery_methods: discovery_methods.into_iter().map(String::from).collect(),
-            hosts: scan_results,
+    let mut host_scans = HostScans::new(&port_scanner, &found, &timing_trace)
+        .with_concurrency(host_concurrency)
+        .with_max_results(max_results)
+        .with_banner_details(record_banner_status, record_raw_banner)
+        .with_hostnames(&target_names)
+        .with_vendors(&vendors)
+        .with_gateway(gateway);
+    if !discover_only {
+        host_scans = host_scans.with_ports(&ports, scan_protocol);
+    }
+    let scanned = host_scans
+        .run(&scan_targets, |host, result, findings| {
+            if let Some(dir) = banner_dump_dir {
+                for finding in findings.iter().filter(|finding| !finding.banner.raw.is_empty()) {
+                    if let Err(e) = write_banner_dump(dir, host, finding.port, &finding.banner.raw) {
+                        eprintln!("Failed to write banner dump for {}:{}: {}", host, finding.port, e.to_string().red());
+                    }
+                }
+            }
+            if let Some(writer) = &mut jsonl_writer {
+                if let Err(e) = writer.write_host(result) {
+                    eprintln!("Failed to write JSONL line for {}: {}", host, e.to_string().red());
+                }
+            }
+        })
+        .await;
+    console::finish_progress();
+    let mut partial = false;
+    if scanned.skipped > 0 {
+        let reason = if timed_out.load(Ordering::Relaxed) {
+            partial = true;
+            "Time limit reached"
+        } else if cancel.is_cancelled() {
+            partial = true;
+            "Interrupted"
+        } else {
+            "Probe budget spent"
         };
-        
-        match serde_json::to_string_pretty(&results) {
-            Ok(json_string) => {
-                match fs::write(json_path, json_string) {
-                    Ok(_) => println!("Results saved to {}", json_path.green()),
-                    Err(e) => eprintln!("Failed to write JSON file: {}", e.to_string().red()),
+        status!("{}", format!("{}: skipped port scans of the remaining {} hosts", reason, scanned.skipped).yellow());
+    }
+    let ScannedHosts { hosts: scan_results, risks, recorded_open_ports, omitted_open_ports, .. } = scanned;
+
+    if partial && timed_out.load(Ordering::Relaxed) {
+        status!("{}", "\nTime limit reached, results are partial".yellow());
+    } else if partial {
+        status!("{}", "\nScan interrupted, results are partial".yellow());
+    } else {
+        status!("\nScan completed!");
+    }
+    if let Some(gateway) = gateway.filter(|&gateway| active_hosts.contains(&IpAddr::V4(gateway))) {
+        let open_ports = scan_results.iter().find(|host| host.is_gateway).map_or(0, |host| host.open_ports.len());
+        status!("{} {} ({} open ports)", "Gateway:".bold(), gateway.to_string().green().bold(), open_ports);
+    }
+    if omitted_open_ports > 0 {
+        status!(
+            "{}",
+            format!(
+                "Result cap of {} open ports reached: {} further open ports were counted but not recorded",
+                recorded_open_ports, omitted_open_ports
+            )
+            .yellow()
+        );
+    }
+    match probe_budget.limit() {
+        Some(limit) => {
+            let line = format!("Probes sent: {} of a {} probe budget", probe_budget.sent(), limit);
+            if probe_budget.exhausted() {
+                status!("{}", format!("{} (budget reached, results are partial)", line).yellow());
+            } else {
+                status!("{}", line);
+            }
+        }
+        None => status!("Probes sent: {}", probe_budget.sent()),
+    }
+    if !discover_only {
+        print_slowest_hosts(&scan_results, slowest_count);
+    }
+
+    // Built before the risk summary so it and the output files agree after a --resume
+    let mut results = ScanResults {
+        format_version: FORMAT_VERSION,
+        timestamp: Utc::now(),
+        interface: interface.clone(),
+        subnet,
+        timeout_ms,
+        total_hosts_scanned: hosts_scanned,
+        active_hosts_found: active_hosts.len(),
+        discovery_methods: found.methods_used.iter().map(|method| method.name().to_string()).collect(),
+        hosts: scan_results,
+        omitted_open_ports,
+        shard: split.map(|(count, index)| format!("{}/{}", count, index)),
+        seed,
+        probes_sent: probe_budget.sent(),
+        probe_budget: probe_budget.limit(),
+        partial,
+        timed_out: timed_out.load(Ordering::Relaxed),
+        duration_ms: scan_clock.elapsed().as_millis() as u64,
+        total_open_ports: recorded_open_ports,
+        total_ports_per_host: ports_per_host,
+        duplicate_macs,
+        risks,
+    };
+    if let Some(resumed) = resumed {
+        merge_resumed(&mut results, resumed);
+    }
+    if !discover_only {
+        summarize_risks(&results);
+    }
+    if let Some(log) = event_log {
+        log.finish(ScanEvent::ScanComplete {
+            hosts_scanned,
+            active_hosts: active_hosts.len(),
+            open_ports: recorded_open_ports,
+            duration_ms: scan_clock.elapsed().as_millis() as u64,
+        })
+        .await;
+    }
+
+    // Generate JSON, MessagePack, CSV, grepable, text, HTML and SQLite output and the comparison if requested
+    let output_files = OutputFiles {
+        json: json_output.cloned(),
+        compact_json,
+        msgpack: msgpack_output.cloned(),
+        csv: csv_output.cloned(),
+        grepable: grepable_output.cloned(),
+        text: text_output.cloned(),
+        html: html_report.cloned(),
+        sqlite: sqlite_output.cloned(),
+    };
+    if !output_files.is_empty() || previous_results.is_some() {
+        for written in output_files.write(&results) {
+            match written.result {
+                Ok(note) => {
+                    let note = note.map(|note| format!(" {}", note)).unwrap_or_default();
+                    status!("Saved {} to {}{}", written.what, written.path.green(), note);
                 }
+                Err(e) => eprintln!("Failed to write {} to {}: {}", written.what, written.path, e.to_string().red()),
             }
-            Err(e) => eprintln!("Failed to serialize results to JSON: {}", e.to_string().red()),
+        }
+        if let Some(previous) = &previous_results {
+            print_diff(&diff_results(previous, &results), previous);
         }
     }
-    
+
+    save_timing_trace(&timing_trace, timing_trace_path);
+    exit_if_stopped(&cancel, &timed_out);
+
     Ok(())
 }
 
+/// Exit status of a scan stopped by `--max-duration`, as `timeout(1)` uses
+const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+/// Exit status when a bad option or failed setup stops the run before
+/// scanning, distinct from `--count-only`'s 1 for no hosts found
+const SETUP_ERROR_EXIT_CODE: i32 = 2;
+
+/// How long results may take to write once `--max-duration` has stopped the scan
+const MAX_DURATION_GRACE: Duration = Duration::from_secs(10);
+
+/// Exit with 124 after `--max-duration` stopped the scan or 130 after
+/// Ctrl-C did, so scripts can tell partial results from complete ones
+fn exit_if_stopped(cancel: &CancellationToken, timed_out: &AtomicBool) {
+    if timed_out.load(Ordering::Relaxed) {
+        std::process::exit(TIMED_OUT_EXIT_CODE);
+    }
+    if cancel.is_cancelled() {
+        std::process::exit(130);
+    }
+}
+
+/// Prints the scanners' `console::STATUS` records as status lines and
+/// leaves every other record to env_logger
+struct ConsoleLogger(env_logger::Logger);
+
+impl log::Log for ConsoleLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target() == console::STATUS || self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.target() == console::STATUS {
+            status!("{}", record.args());
+        } else {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// The --events and --syslog consumer: a task handing each event to the
+/// file and the collector as it arrives
+struct EventLog {
+    sender: EventSender,
+    writer: JoinHandle<()>,
+}
+
+impl EventLog {
+    fn start(mut file: Option<JsonlWriter>, mut syslog: Option<SyslogSink>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                // A sink that fails is dropped with one error; the other carries on
+                if let Some(Err(e)) = file.as_mut().map(|file| file.write_line(&event)) {
+                    eprintln!("Failed to write event: {}", e.to_string().red());
+                    file = None;
+                }
+                if let Some(sink) = &syslog {
+                    if let Err(e) = sink.send(&event).await {
+                        eprintln!("Failed to send syslog message: {}", e.to_string().red());
+                        syslog = None;
+                    }
+                }
+                // Scanners may still hold senders, so the channel never closes by itself
+                if matches!(event, ScanEvent::ScanComplete { .. }) {
+                    break;
+                }
+            }
+        });
+        EventLog { sender, writer }
+    }
+
+    /// Send the closing event and wait until everything before it is written
+    async fn finish(self, complete: ScanEvent) {
+        let _ = self.sender.send(complete);
+        let _ = self.writer.await;
+    }
+}
+
+/// Load the results of an interrupted scan for --resume, refusing ones taken
+/// on another interface or subnet since their hosts wouldn't line up
+fn read_resume_file(path: &str, interface: &str, subnet: &str) -> Result<ScanResults> {
+    let resumed = read_results(path)?;
+    if resumed.interface != interface || resumed.subnet != subnet {
+        anyhow::bail!(
+            "it was scanned on {} ({}), not {} ({})",
+            resumed.interface, resumed.subnet, interface, subnet
+        );
+    }
+    Ok(resumed)
+}
+
+fn print_diff(diff: &ScanDiff, previous: &ScanResults) {
+    println!("\nChanges since the scan of {}:", previous.timestamp.format("%Y-%m-%d %H:%M:%S UTC"));
+    if diff.is_empty() {
+        println!("  none");
+        return;
+    }
+    for ip in &diff.new_hosts {
+        println!("  {} new host {}", "+".green(), ip.green());
+    }
+    for ip in &diff.disappeared_hosts {
+        println!("  {} host gone {}", "-".red(), ip.red());
+    }
+    for change in &diff.opened_ports {
+        println!("  {} {}:{}/{} opened", "+".green(), change.ip, change.port.to_string().green(), change.protocol);
+    }
+    for change in &diff.closed_ports {
+        println!("  {} {}:{}/{} closed", "-".red(), change.ip, change.port.to_string().red(), change.protocol);
+    }
+    for change in &diff.changed_macs {
+        println!(
+            "  {} {} MAC changed {} -> {} (possible ARP spoofing)",
+            "!".yellow(), change.ip, change.old_mac, change.new_mac.yellow()
+        );
+    }
+}
+
+/// The risky services of the finished results, resumed hosts included, coloured by severity
+fn summarize_risks(results: &ScanResults) {
+    let risks = &results.risks;
+    if risks.is_empty() {
+        return;
+    }
+    let count = |severity| risks.iter().filter(|risk| risk.severity == severity).count();
+    status!(
+        "\n{} ({} high, {} medium, {} low):",
+        "Risky services".bold(),
+        count(Severity::High),
+        count(Severity::Medium),
+        count(Severity::Low)
+    );
+    for risk in risks {
+        let severity = format!("{:<6}", risk.severity.name().to_uppercase());
+        let severity = match risk.severity {
+            Severity::High => severity.red().bold(),
+            Severity::Medium => severity.yellow(),
+            Severity::Low => severity.normal(),
+        };
+        status!("   {} {}:{}/{} {}: {}", severity, risk.ip, risk.port, risk.protocol, risk.service.cyan(), risk.reason);
+    }
+}
+
+fn save_timing_trace(trace: &TimingTrace, path: Option<&String>) {
+    if let Some(path) = path {
+        match trace.write(path) {
+            Ok(_) => status!("Timing trace saved to {}", path.green()),
+            Err(e) => eprintln!("Failed to write timing trace: {}", e.to_string().red()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -308,6 +1931,6 @@ mod tests {
     #[test]
     fn test_banner_display() {
         assert!(!BANNER.is_empty());
-        assert!(BANNER.contains("Ethernet Scanner"));
+        assert!(BANNER.contains("Network Scanner"));
     }
 }
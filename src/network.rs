@@ -1,15 +1,22 @@
 use anyhow::Result;
 use if_addrs::{get_if_addrs, IfAddr};
-use ipnetwork::Ipv4Network;
-use std::net::Ipv4Addr;
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use std::collections::HashSet;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 
-pub fn get_local_subnet(interface_name: &str) -> Result<Ipv4Network> {
+/// The subnet of `interface_name`'s first IPv4 address, or of `address` on
+/// an interface that has several
+pub fn get_local_subnet(interface_name: &str, address: Option<Ipv4Addr>) -> Result<Ipv4Network> {
     let if_addrs = get_if_addrs()?;
     
     for iface in if_addrs {
         if iface.name == interface_name {
             if let IfAddr::V4(addr) = iface.addr {
                 let ip = addr.ip;
+                if address.is_some_and(|address| address != ip) {
+                    continue;
+                }
                 let netmask = addr.netmask;
                 let network_addr = Ipv4Addr::from(u32::from(ip) & u32::from(netmask));
                 let prefix_len = netmask.to_bits().count_ones() as u8;
@@ -19,11 +26,415 @@ pub fn get_local_subnet(interface_name: &str) -> Result<Ipv4Network> {
         }
     }
     
-    Err(anyhow::anyhow!("Interface '{}' not found or has no IPv4 address", interface_name))
+    match address {
+        Some(address) => Err(anyhow::anyhow!("Interface '{}' has no IPv4 address {}", interface_name, address)),
+        None => Err(anyhow::anyhow!("Interface '{}' not found or has no IPv4 address", interface_name)),
+    }
+}
+
+/// The host addresses of `network`, or just the first `max_hosts` of them.
+/// The network and broadcast addresses are left out, except on a /31
+/// point-to-point link (RFC 3021) where both addresses are hosts; a /32 is
+/// its single address.
+pub fn get_network_hosts(network: Ipv4Network, max_hosts: Option<usize>) -> Vec<Ipv4Addr> {
+    let first = u32::from(network.network());
+    let last = u32::from(network.broadcast());
+    let hosts = if network.prefix() >= 31 { first..=last } else { first + 1..=last - 1 };
+    hosts.take(max_hosts.unwrap_or(usize::MAX)).map(Ipv4Addr::from).collect()
+}
+
+/// Shortest IPv6 prefix that is enumerated without an explicit `--max-hosts`
+pub const MIN_ENUMERABLE_V6_PREFIX: u8 = 120;
+
+/// The interface's IPv6 network, preferring a global address over a link-local one
+pub fn get_local_subnet_v6(interface_name: &str) -> Result<Ipv6Network> {
+    let mut link_local = None;
+
+    for iface in get_if_addrs()? {
+        if iface.name == interface_name {
+            if let IfAddr::V6(addr) = iface.addr {
+                if addr.ip.is_loopback() {
+                    continue;
+                }
+                let prefix_len = u128::from(addr.netmask).count_ones() as u8;
+                let network_addr = Ipv6Addr::from(u128::from(addr.ip) & u128::from(addr.netmask));
+                let network = Ipv6Network::new(network_addr, prefix_len)?;
+                if !addr.ip.is_unicast_link_local() {
+                    return Ok(network);
+                }
+                link_local.get_or_insert(network);
+            }
+        }
+    }
+
+    link_local.ok_or_else(|| anyhow::anyhow!("Interface '{}' not found or has no IPv6 address", interface_name))
+}
+
+/// Addresses of an IPv6 network from its start, at most `max_hosts` of them.
+///
+/// Prefixes shorter than /120 are far too large to sweep, so they are refused
+/// unless `max_hosts` says how much of the front of the network to take.
+pub fn get_network_hosts_v6(network: Ipv6Network, max_hosts: Option<usize>) -> Result<Vec<Ipv6Addr>> {
+    if network.prefix() < MIN_ENUMERABLE_V6_PREFIX && max_hosts.is_none() {
+        return Err(anyhow::anyhow!(
+            "refusing to enumerate {} ({} addresses): prefixes shorter than /{} need --max-hosts",
+            network,
+            network.size(),
+            MIN_ENUMERABLE_V6_PREFIX
+        ));
+    }
+    Ok(network.iter().take(max_hosts.unwrap_or(usize::MAX)).collect())
+}
+
+/// Largest `--target` range enumerated without an explicit `--max-hosts`: a /8
+pub const MAX_TARGET_HOSTS: u64 = 1 << 24;
+
+/// Scans of more addresses than this, a /16, need confirmation or `--yes`
+pub const LARGE_SCAN_HOSTS: usize = 65536;
+
+/// What `--target` names: an address range, or hosts to look up in DNS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// First and last address, inclusive
+    Range(Ipv4Addr, Ipv4Addr),
+    Hostnames(Vec<String>),
+}
+
+/// Letters, digits and hyphens in dot-separated labels, with at least one
+/// letter so a mistyped address isn't sent to DNS
+fn is_hostname(name: &str) -> bool {
+    name.len() <= 253
+        && name.chars().any(|c| c.is_ascii_alphabetic())
+        && name.trim_end_matches('.').split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Parse a `--target` spec: a CIDR range (`10.0.5.0/24`), a single IP, a
+/// dash range (`10.0.5.10-10.0.5.50`), or hostnames, comma-separated
+/// (`router.lan,nas.lan`)
+pub fn parse_target(spec: &str) -> Result<Target> {
+    let spec = spec.trim();
+    let parse_ip = |ip: &str| {
+        ip.trim()
+            .parse::<Ipv4Addr>()
+            .map_err(|_| anyhow::anyhow!("invalid target address '{}'", ip.trim()))
+    };
+
+    if spec.contains(',') || is_hostname(spec) {
+        let names: Vec<String> = spec.split(',').map(|name| name.trim().to_string()).collect();
+        if let Some(name) = names.iter().find(|name| !is_hostname(name) && name.parse::<Ipv4Addr>().is_err()) {
+            return Err(anyhow::anyhow!("invalid target hostname '{}'", name));
+        }
+        Ok(Target::Hostnames(names))
+    } else if spec.contains('/') {
+        let network = spec
+            .parse::<Ipv4Network>()
+            .map_err(|_| anyhow::anyhow!("invalid target range '{}'", spec))?;
+        Ok(Target::Range(network.network(), network.broadcast()))
+    } else if let Some((first, last)) = spec.split_once('-') {
+        let (first, last) = (parse_ip(first)?, parse_ip(last)?);
+        if first > last {
+            return Err(anyhow::anyhow!("invalid target range '{}': {} comes after {}", spec, first, last));
+        }
+        Ok(Target::Range(first, last))
+    } else {
+        let ip = parse_ip(spec)?;
+        Ok(Target::Range(ip, ip))
+    }
+}
+
+/// Look up each name's IPv4 addresses, keeping every A record. Addresses
+/// come back in the order the names were given, each once, paired with the
+/// first name that resolved to it.
+pub async fn resolve_hostnames(names: &[String]) -> Result<Vec<(Ipv4Addr, String)>> {
+    let mut resolved: Vec<(Ipv4Addr, String)> = Vec::new();
+    for name in names {
+        let addrs = tokio::net::lookup_host((name.as_str(), 0))
+            .await
+            .map_err(|e| anyhow::anyhow!("cannot resolve '{}': {}", name, e))?;
+        let mut found = false;
+        for addr in addrs {
+            if let IpAddr::V4(ip) = addr.ip() {
+                found = true;
+                if !resolved.iter().any(|(seen, _)| *seen == ip) {
+                    resolved.push((ip, name.clone()));
+                }
+            }
+        }
+        if !found {
+            return Err(anyhow::anyhow!("'{}' has no IPv4 address", name));
+        }
+    }
+    Ok(resolved)
 }
 
-pub fn get_network_hosts(network: Ipv4Network) -> Vec<Ipv4Addr> {
-    network.iter().collect()
+/// Parse one `--target` spec per line, as piped to `--stdin`. Blank lines and
+/// `#` comments are skipped; errors name the offending line.
+pub fn parse_target_list(text: &str) -> Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if !line.is_empty() {
+            targets.push(parse_target(line).map_err(|e| anyhow::anyhow!("line {}: {}", number + 1, e))?);
+        }
+    }
+    Ok(targets)
+}
+
+/// Every address the targets name, each once in the order first named,
+/// with the hostname it was resolved from if any. `max_hosts` caps each
+/// range, as for a single `--target`.
+pub async fn expand_targets(targets: &[Target], max_hosts: Option<usize>) -> Result<Vec<(Ipv4Addr, Option<String>)>> {
+    let mut expanded: Vec<(Ipv4Addr, Option<String>)> = Vec::new();
+    let mut seen = HashSet::new();
+    for target in targets {
+        let addresses = match target {
+            Target::Range(first, last) => target_hosts(*first, *last, max_hosts)?.into_iter().map(|ip| (ip, None)).collect(),
+            Target::Hostnames(names) => {
+                resolve_hostnames(names).await?.into_iter().map(|(ip, name)| (ip, Some(name))).collect::<Vec<_>>()
+            }
+        };
+        expanded.extend(addresses.into_iter().filter(|(ip, _)| seen.insert(*ip)));
+    }
+    Ok(expanded)
+}
+
+/// Addresses from `first` to `last`, at most `max_hosts` of them.
+///
+/// Ranges larger than a /8 are refused unless `max_hosts` caps them.
+pub fn target_hosts(first: Ipv4Addr, last: Ipv4Addr, max_hosts: Option<usize>) -> Result<Vec<Ipv4Addr>> {
+    let size = u64::from(u32::from(last)) - u64::from(u32::from(first)) + 1;
+    if size > MAX_TARGET_HOSTS && max_hosts.is_none() {
+        return Err(anyhow::anyhow!(
+            "refusing to scan {}-{} ({} addresses): ranges larger than {} need --max-hosts",
+            first,
+            last,
+            size,
+            MAX_TARGET_HOSTS
+        ));
+    }
+    Ok((u32::from(first)..=u32::from(last))
+        .take(max_hosts.unwrap_or(usize::MAX))
+        .map(Ipv4Addr::from)
+        .collect())
+}
+
+/// The interface's default IPv4 gateway from the kernel routing table.
+/// Only Linux is supported; elsewhere this is always `None`.
+pub fn get_default_gateway(interface: &str) -> Option<Ipv4Addr> {
+    #[cfg(target_os = "linux")]
+    {
+        parse_default_gateway(&fs::read_to_string("/proc/net/route").ok()?, interface)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = interface;
+        None
+    }
+}
+
+/// The interface a scan uses when none is named: the one carrying the
+/// default route, else the first non-loopback interface with an IPv4 address
+pub fn default_interface() -> Result<String> {
+    #[cfg(target_os = "linux")]
+    if let Some(interface) = fs::read_to_string("/proc/net/route").ok().as_deref().and_then(parse_default_route_interface) {
+        return Ok(interface);
+    }
+    get_if_addrs()?
+        .into_iter()
+        .find(|iface| !iface.is_loopback() && matches!(iface.addr, IfAddr::V4(_)))
+        .map(|iface| iface.name)
+        .ok_or_else(|| anyhow::anyhow!("no network interface with an IPv4 address found"))
+}
+
+/// Each default route via a gateway in `/proc/net/route` text, whose
+/// addresses are hex in host byte order, as (interface, metric, gateway)
+#[cfg(any(target_os = "linux", test))]
+fn default_routes(routes: &str) -> impl Iterator<Item = (&str, u32, Ipv4Addr)> {
+    const RTF_UP: u32 = 0x1;
+    const RTF_GATEWAY: u32 = 0x2;
+    let hex = |field: &str| u32::from_str_radix(field, 16).ok();
+
+    routes.lines().skip(1).filter_map(move |line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 8 {
+            return None;
+        }
+        let (destination, gateway, flags, mask) = (hex(fields[1])?, hex(fields[2])?, hex(fields[3])?, hex(fields[7])?);
+        let metric: u32 = fields[6].parse().ok()?;
+        let is_default = destination == 0 && mask == 0 && flags & (RTF_UP | RTF_GATEWAY) == RTF_UP | RTF_GATEWAY;
+        is_default.then(|| (fields[0], metric, Ipv4Addr::from(gateway.to_ne_bytes())))
+    })
+}
+
+/// The gateway of the lowest-metric default route on `interface`
+#[cfg(any(target_os = "linux", test))]
+fn parse_default_gateway(routes: &str, interface: &str) -> Option<Ipv4Addr> {
+    default_routes(routes)
+        .filter(|(name, _, _)| *name == interface)
+        .min_by_key(|(_, metric, _)| *metric)
+        .map(|(_, _, gateway)| gateway)
+}
+
+/// The interface of the lowest-metric default route
+#[cfg(any(target_os = "linux", test))]
+fn parse_default_route_interface(routes: &str) -> Option<String> {
+    default_routes(routes)
+        .min_by_key(|(_, metric, _)| *metric)
+        .map(|(name, _, _)| name.to_string())
+}
+
+/// Index of the named interface, the scope id link-local IPv6 addresses need
+pub fn interface_scope_id(interface_name: &str) -> Option<u32> {
+    get_if_addrs()
+        .ok()?
+        .into_iter()
+        .find(|iface| iface.name == interface_name)
+        .and_then(|iface| iface.index)
+}
+
+/// Socket address for `ip`, carrying `scope_id` when `ip` is IPv6 link-local
+pub fn scoped_socket_addr(ip: IpAddr, port: u16, scope_id: Option<u32>) -> SocketAddr {
+    match (ip, scope_id) {
+        (IpAddr::V6(ip), Some(scope_id)) if ip.is_unicast_link_local() => {
+            SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id))
+        }
+        _ => SocketAddr::new(ip, port),
+    }
+}
+
+/// Parse a `--split N/M` spec into (shard count, 1-based shard index)
+pub fn parse_split(spec: &str) -> Result<(usize, usize)> {
+    let (count, index) = spec
+        .split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("invalid split '{}': expected N/M", spec))?;
+    let count = count.trim().parse::<usize>().map_err(|_| anyhow::anyhow!("invalid shard count '{}'", count))?;
+    let index = index.trim().parse::<usize>().map_err(|_| anyhow::anyhow!("invalid shard index '{}'", index))?;
+
+    if count < 1 || index < 1 || index > count {
+        return Err(anyhow::anyhow!("invalid split '{}': need 1 <= M <= N", spec));
+    }
+    Ok((count, index))
+}
+
+/// The `index`-th (1-based) of `count` contiguous, near-equal shards of `hosts`.
+///
+/// Shards differ in size by at most one host and together cover every host
+/// exactly once, so N instances with the same target list split the work.
+pub fn shard_hosts<T: Clone>(hosts: &[T], count: usize, index: usize) -> Vec<T> {
+    let base = hosts.len() / count;
+    let extra = hosts.len() % count;
+    // The first `extra` shards take one additional host each
+    let shard = index - 1;
+    let start = shard * base + shard.min(extra);
+    let len = base + usize::from(shard < extra);
+    hosts[start..start + len].to_vec()
+}
+
+/// Largest `±N` window accepted by `--nearby`
+const MAX_NEARBY_WINDOW: u32 = 32768;
+
+/// Parse a `--nearby` spec: `IP` for the IP's /24, or `IP/N` for the IP plus or minus N addresses
+pub fn parse_nearby(spec: &str) -> Result<(Ipv4Addr, Option<u32>)> {
+    let (ip, window) = match spec.split_once('/') {
+        Some((ip, window)) => (ip, Some(window)),
+        None => (spec, None),
+    };
+
+    let ip = ip
+        .trim()
+        .parse::<Ipv4Addr>()
+        .map_err(|_| anyhow::anyhow!("invalid nearby address '{}'", ip))?;
+    let window = match window {
+        Some(window) => {
+            let window = window
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|w| (1..=MAX_NEARBY_WINDOW).contains(w))
+                .ok_or_else(|| anyhow::anyhow!("invalid nearby window '{}' (expected 1-{})", window, MAX_NEARBY_WINDOW))?;
+            Some(window)
+        }
+        None => None,
+    };
+
+    Ok((ip, window))
+}
+
+/// Addresses around `center`: its /24 by default, or `center` plus or minus `window` addresses
+pub fn nearby_hosts(center: Ipv4Addr, window: Option<u32>) -> Vec<Ipv4Addr> {
+    match window {
+        Some(window) => {
+            let center = u32::from(center);
+            let first = center.saturating_sub(window);
+            let last = center.saturating_add(window);
+            (first..=last).map(Ipv4Addr::from).collect()
+        }
+        None => {
+            // The whole /24, .0 and .255 included, since it needn't be the real subnet
+            let first = u32::from(center) & !0xff;
+            (first..=first + 0xff).map(Ipv4Addr::from).collect()
+        }
+    }
+}
+
+/// Addresses and networks that must never be probed
+#[derive(Debug, Clone, Default)]
+pub struct Exclusions {
+    hosts: HashSet<IpAddr>,
+    networks: Vec<IpNetwork>,
+}
+
+impl Exclusions {
+    /// Add one IP or CIDR range
+    pub fn add(&mut self, entry: &str) -> Result<()> {
+        let entry = entry.trim();
+        if entry.contains('/') {
+            let network = entry
+                .parse::<IpNetwork>()
+                .map_err(|_| anyhow::anyhow!("invalid excluded range '{}'", entry))?;
+            self.networks.push(network);
+        } else {
+            let ip = entry
+                .parse::<IpAddr>()
+                .map_err(|_| anyhow::anyhow!("invalid excluded address '{}'", entry))?;
+            self.hosts.insert(ip);
+        }
+        Ok(())
+    }
+
+    /// Add a comma-separated list of IPs and CIDR ranges, as given to `--exclude`
+    pub fn add_list(&mut self, list: &str) -> Result<()> {
+        for entry in list.split(',').filter(|entry| !entry.trim().is_empty()) {
+            self.add(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Add every line of an exclude file; blank lines and `#` comments are skipped
+    pub fn add_file(&mut self, path: &str) -> Result<()> {
+        let content = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("cannot read '{}': {}", path, e))?;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if !line.is_empty() {
+                self.add(line).map_err(|e| anyhow::anyhow!("{}:{}: {}", path, number + 1, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.networks.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.hosts.contains(&ip) || self.networks.iter().any(|network| network.contains(ip))
+    }
 }
 
 pub fn list_interfaces() -> Result<()> {
@@ -85,4 +496,180 @@ pub fn list_interfaces() -> Result<()> {
 mod tests {
     use super::*;
 
-}
\ No newline at end of file
+    #[test]
+    fn test_parse_nearby() {
+        let ip = Ipv4Addr::new(192, 168, 1, 50);
+        assert_eq!(parse_nearby("192.168.1.50").unwrap(), (ip, None));
+        assert_eq!(parse_nearby("192.168.1.50/8").unwrap(), (ip, Some(8)));
+        assert!(parse_nearby("192.168.1.50/0").is_err());
+        assert!(parse_nearby("router/4").is_err());
+    }
+
+    #[test]
+    fn test_network_hosts_v6() {
+        let small: Ipv6Network = "2001:db8::/124".parse().unwrap();
+        let hosts = get_network_hosts_v6(small, None).unwrap();
+        assert_eq!(hosts.len(), 16);
+        assert_eq!(hosts[15], "2001:db8::f".parse::<Ipv6Addr>().unwrap());
+
+        let lan: Ipv6Network = "2001:db8::/64".parse().unwrap();
+        assert!(get_network_hosts_v6(lan, None).is_err());
+        let capped = get_network_hosts_v6(lan, Some(300)).unwrap();
+        assert_eq!(capped.len(), 300);
+        assert_eq!(capped[299], "2001:db8::12b".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn test_scoped_socket_addr() {
+        let link_local: IpAddr = "fe80::1".parse().unwrap();
+        assert_eq!(scoped_socket_addr(link_local, 22, Some(3)).to_string(), "[fe80::1%3]:22");
+        let global: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(scoped_socket_addr(global, 22, Some(3)).to_string(), "[2001:db8::1]:22");
+        assert_eq!(scoped_socket_addr(IpAddr::V4(Ipv4Addr::LOCALHOST), 80, Some(3)).to_string(), "127.0.0.1:80");
+    }
+
+    #[test]
+    fn test_split_shards() {
+        assert_eq!(parse_split("4/2").unwrap(), (4, 2));
+        assert!(parse_split("4/5").is_err());
+        assert!(parse_split("0/0").is_err());
+        assert!(parse_split("4").is_err());
+
+        let hosts: Vec<u32> = (0..10).collect();
+        let shards: Vec<Vec<u32>> = (1..=3).map(|i| shard_hosts(&hosts, 3, i)).collect();
+        assert_eq!(shards, vec![vec![0, 1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]]);
+
+        // More shards than hosts leaves the trailing shards empty
+        assert_eq!(shard_hosts(&hosts[..2], 4, 4), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_network_hosts() {
+        let hosts = |cidr: &str| get_network_hosts(cidr.parse().unwrap(), None);
+        let lan = hosts("192.0.2.0/24");
+        assert_eq!(lan.len(), 254);
+        assert_eq!(lan[0], Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(lan[253], Ipv4Addr::new(192, 0, 2, 254));
+        assert_eq!(hosts("192.0.2.4/30"), vec![Ipv4Addr::new(192, 0, 2, 5), Ipv4Addr::new(192, 0, 2, 6)]);
+        assert_eq!(hosts("192.0.2.6/31"), vec![Ipv4Addr::new(192, 0, 2, 6), Ipv4Addr::new(192, 0, 2, 7)]);
+        assert_eq!(hosts("192.0.2.9/32"), vec![Ipv4Addr::new(192, 0, 2, 9)]);
+        assert_eq!(hosts("255.255.255.255/32"), vec![Ipv4Addr::BROADCAST]);
+        assert_eq!(get_network_hosts("192.0.2.0/24".parse().unwrap(), Some(2)).len(), 2);
+    }
+
+    #[test]
+    fn test_nearby_hosts() {
+        let hosts = nearby_hosts(Ipv4Addr::new(10, 0, 5, 77), None);
+        assert_eq!(hosts.len(), 256);
+        assert_eq!(hosts[0], Ipv4Addr::new(10, 0, 5, 0));
+        assert_eq!(hosts[255], Ipv4Addr::new(10, 0, 5, 255));
+
+        let hosts = nearby_hosts(Ipv4Addr::new(10, 0, 5, 255), Some(2));
+        assert_eq!(
+            hosts,
+            vec![
+                Ipv4Addr::new(10, 0, 5, 253),
+                Ipv4Addr::new(10, 0, 5, 254),
+                Ipv4Addr::new(10, 0, 5, 255),
+                Ipv4Addr::new(10, 0, 6, 0),
+                Ipv4Addr::new(10, 0, 6, 1),
+            ]
+        );
+
+        // The window is clamped at the ends of the address space
+        assert_eq!(nearby_hosts(Ipv4Addr::new(0, 0, 0, 1), Some(3)).len(), 5);
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let mut exclusions = Exclusions::default();
+        assert!(exclusions.is_empty());
+        exclusions.add_list("192.168.1.1, 10.0.0.0/30,fd00::1").unwrap();
+        assert!(exclusions.contains("192.168.1.1".parse().unwrap()));
+        assert!(exclusions.contains("10.0.0.3".parse().unwrap()));
+        assert!(!exclusions.contains("10.0.0.4".parse().unwrap()));
+        assert!(exclusions.contains("fd00::1".parse().unwrap()));
+        assert!(exclusions.add("10.0.0.0/33").is_err());
+        assert!(exclusions.add("gateway").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("exclude.txt");
+        fs::write(&path, "# sensors\n172.16.0.0/16\n\n172.17.0.1 # gateway\nbogus\n").unwrap();
+        let error = exclusions.add_file(path.to_str().unwrap()).unwrap_err().to_string();
+        assert!(error.ends_with(":5: invalid excluded address 'bogus'"), "{}", error);
+        assert!(exclusions.contains("172.16.9.9".parse().unwrap()));
+        assert!(exclusions.contains("172.17.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_target_hosts() {
+        let ip = |s: &str| s.parse::<Ipv4Addr>().unwrap();
+        assert_eq!(parse_target("10.0.5.7/24").unwrap(), Target::Range(ip("10.0.5.0"), ip("10.0.5.255")));
+        assert_eq!(parse_target("10.0.5.10").unwrap(), Target::Range(ip("10.0.5.10"), ip("10.0.5.10")));
+        assert_eq!(parse_target(" 10.0.5.10 - 10.0.5.50").unwrap(), Target::Range(ip("10.0.5.10"), ip("10.0.5.50")));
+        assert!(parse_target("10.0.5.50-10.0.5.10").is_err());
+        assert!(parse_target("10.0.5.0/40").is_err());
+        assert!(parse_target("10.0.5.300").is_err());
+        assert_eq!(parse_target("printer").unwrap(), Target::Hostnames(vec!["printer".to_string()]));
+        assert_eq!(
+            parse_target("router.lan, my-nas.lan,10.0.5.9").unwrap(),
+            Target::Hostnames(vec!["router.lan".to_string(), "my-nas.lan".to_string(), "10.0.5.9".to_string()])
+        );
+        assert!(parse_target("router.lan,-bad-").is_err());
+
+        assert_eq!(target_hosts(ip("10.0.5.10"), ip("10.0.5.50"), None).unwrap().len(), 41);
+        assert_eq!(target_hosts(ip("10.0.0.0"), ip("10.1.255.255"), None).unwrap().len(), 131072);
+        // Larger than a /8 needs a cap, which takes from the start
+        let Target::Range(first, last) = parse_target("10.0.0.0/7").unwrap() else { panic!("not a range") };
+        assert!(target_hosts(first, last, None).is_err());
+        assert_eq!(target_hosts(first, last, Some(2)).unwrap(), vec![ip("10.0.0.0"), ip("10.0.0.1")]);
+        assert_eq!(target_hosts(ip("255.255.255.255"), ip("255.255.255.255"), None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_default_gateway() {
+        let hex = |ip: [u8; 4]| format!("{:08X}", u32::from_ne_bytes(ip));
+        let routes = format!(
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+             eth0\t{zero}\t{gw}\t0003\t0\t0\t100\t{zero}\t0\t0\t0\n\
+             eth0\t{net}\t{zero}\t0001\t0\t0\t0\t{mask}\t0\t0\t0\n\
+             eth0\t{zero}\t{backup}\t0003\t0\t0\t600\t{zero}\t0\t0\t0\n\
+             wlan0\t{zero}\t{other}\t0003\t0\t0\t0\t{zero}\t0\t0\t0\n",
+            zero = hex([0, 0, 0, 0]),
+            gw = hex([192, 0, 2, 1]),
+            net = hex([192, 0, 2, 0]),
+            mask = hex([255, 255, 255, 0]),
+            backup = hex([192, 0, 2, 254]),
+            other = hex([198, 51, 100, 1]),
+        );
+        assert_eq!(parse_default_gateway(&routes, "eth0"), Some(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(parse_default_gateway(&routes, "wlan0"), Some(Ipv4Addr::new(198, 51, 100, 1)));
+        assert_eq!(parse_default_gateway(&routes, "eth1"), None);
+        // wlan0's metric 0 beats eth0's 100
+        assert_eq!(parse_default_route_interface(&routes).as_deref(), Some("wlan0"));
+        assert_eq!(parse_default_route_interface(routes.lines().next().unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn test_target_list() {
+        let targets = parse_target_list("# from the recon step\n10.0.5.1\n\n10.0.5.0/30  # lab\nlocalhost\n").unwrap();
+        assert_eq!(targets.len(), 3);
+        let expanded = expand_targets(&targets, None).await.unwrap();
+        let ips: Vec<String> = expanded.iter().map(|(ip, _)| ip.to_string()).collect();
+        // 10.0.5.1 is in both the first line and the range, and kept once
+        assert_eq!(ips, vec!["10.0.5.1", "10.0.5.0", "10.0.5.2", "10.0.5.3", "127.0.0.1"]);
+        assert_eq!(expanded[4].1.as_deref(), Some("localhost"));
+
+        let error = parse_target_list("10.0.5.1\n10.0.5.300\n").unwrap_err().to_string();
+        assert_eq!(error, "line 2: invalid target address '10.0.5.300'");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_hostnames() {
+        let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        let resolved = resolve_hostnames(&names).await.unwrap();
+        // Both name the same address, which is scanned once under the first name
+        assert_eq!(resolved, vec![(Ipv4Addr::LOCALHOST, "localhost".to_string())]);
+        assert!(resolve_hostnames(&["no-such-host.invalid".to_string()]).await.is_err());
+    }
+}
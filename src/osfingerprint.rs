@@ -0,0 +1,85 @@
+use crate::OpenPort;
+
+/// OS families by the initial TTL they send with, lowest first
+const INITIAL_TTLS: &[(u8, &str)] = &[(64, "Linux/Unix"), (128, "Windows"), (255, "Network device")];
+
+/// Open TCP ports that point at an OS, most telling first, with the TTL
+/// family that has to agree for the port to count. Samba or xrdp on Linux
+/// open Windows ports too, but answer with a Linux TTL.
+const PORT_HINTS: &[(u16, &str, Option<&str>)] = &[
+    (62078, "iOS", Some("Linux/Unix")),       // iPhone sync (lockdownd)
+    (548, "macOS", Some("Linux/Unix")),       // AFP
+    (135, "Windows", Some("Windows")),        // RPC endpoint mapper
+    (3389, "Windows", Some("Windows")),
+    (5985, "Windows", Some("Windows")),       // WinRM
+    (445, "Windows", Some("Windows")),
+    (139, "Windows", Some("Windows")),
+    (9100, "Printer", None),                  // JetDirect raw printing
+    (515, "Printer", None),                   // LPD
+];
+
+/// Guess the sender's OS family from a received IP TTL, assuming it started
+/// from the usual initial value (64, 128 or 255). Returns the guess and the
+/// number of hops the reply travelled.
+pub fn guess_os_from_ttl(ttl: u8) -> (&'static str, u8) {
+    let (initial, os) = INITIAL_TTLS.iter().find(|(initial, _)| ttl <= *initial).unwrap_or(&INITIAL_TTLS[2]);
+    (os, initial - ttl)
+}
+
+/// What the open TCP ports alone suggest; a host whose only open port is
+/// SSH is most likely Linux or another Unix
+fn guess_os_from_ports(open_ports: &[OpenPort]) -> Option<(&'static str, Option<&'static str>)> {
+    let open: Vec<u16> = open_ports
+        .iter()
+        .filter(|port| port.protocol == "tcp" && port.state == "open")
+        .map(|port| port.port)
+        .collect();
+    PORT_HINTS
+        .iter()
+        .find(|(port, _, _)| open.contains(port))
+        .map(|(_, os, family)| (*os, *family))
+        .or_else(|| (open == [22]).then_some(("Linux/Unix", Some("Linux/Unix"))))
+}
+
+/// A rough OS guess for `HostResult::os_guess` from the reply TTL and the
+/// open ports, not a fingerprint. A port hint is used when the TTL agrees
+/// with it or wasn't read; otherwise the TTL's family stands.
+pub fn guess_os(ttl: Option<u8>, open_ports: &[OpenPort]) -> Option<String> {
+    let from_ttl = ttl.map(|ttl| guess_os_from_ttl(ttl).0);
+    let guess = match (from_ttl, guess_os_from_ports(open_ports)) {
+        (Some(family), Some((_, Some(needs)))) if family != needs => family,
+        (_, Some((os, _))) => os,
+        (family, None) => family?,
+    };
+    Some(guess.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(ports: &[u16]) -> Vec<OpenPort> {
+        ports.iter().map(|&port| OpenPort::tcp(port)).collect()
+    }
+
+    #[test]
+    fn test_guess_os_from_ttl() {
+        assert_eq!(guess_os_from_ttl(64), ("Linux/Unix", 0));
+        assert_eq!(guess_os_from_ttl(125), ("Windows", 3));
+        assert_eq!(guess_os_from_ttl(250), ("Network device", 5));
+    }
+
+    #[test]
+    fn test_guess_os() {
+        assert_eq!(guess_os(None, &[]), None);
+        assert_eq!(guess_os(Some(62), &[]).as_deref(), Some("Linux/Unix"));
+        assert_eq!(guess_os(None, &open(&[80, 445])).as_deref(), Some("Windows"));
+        assert_eq!(guess_os(Some(127), &open(&[135, 445])).as_deref(), Some("Windows"));
+        // Samba answers with a Linux TTL
+        assert_eq!(guess_os(Some(64), &open(&[22, 445])).as_deref(), Some("Linux/Unix"));
+        assert_eq!(guess_os(None, &open(&[22])).as_deref(), Some("Linux/Unix"));
+        assert_eq!(guess_os(Some(64), &open(&[548])).as_deref(), Some("macOS"));
+        assert_eq!(guess_os(Some(255), &open(&[80, 9100])).as_deref(), Some("Printer"));
+        assert_eq!(guess_os(None, &open(&[22, 80])), None);
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use tokio_rustls::rustls::crypto;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+/// The public lookup service: `GET /<prefix>` answers the vendor name as
+/// plain text, or 404 for an unassigned prefix
+const API_HOST: &str = "api.macvendors.com";
+
+/// Longest wait for one lookup, connect to last byte
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Pause between lookups; the free tier allows two a second
+const LOOKUP_INTERVAL: Duration = Duration::from_millis(600);
+
+/// Most time all a scan's lookups may take; the rest go without a vendor
+const LOOKUP_BUDGET: Duration = Duration::from_secs(10);
+
+/// Largest reply read; a vendor name is a line of text
+const MAX_RESPONSE_BYTES: u64 = 4096;
+
+/// CA bundles tried in order when `$SSL_CERT_FILE` isn't set
+const CA_BUNDLES: &[&str] = &["/etc/ssl/certs/ca-certificates.crt", "/etc/pki/tls/certs/ca-bundle.crt", "/etc/ssl/cert.pem"];
+
+/// The OUI of `mac`, its first three octets as `00:11:22` in upper case.
+/// Locally administered MACs, such as randomised ones, have none.
+pub fn oui_prefix(mac: &str) -> Option<String> {
+    let octets: Vec<&str> = mac.split([':', '-']).collect();
+    if octets.len() != 6 || !octets.iter().all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit())) {
+        return None;
+    }
+    if u8::from_str_radix(octets[0], 16).ok()? & 0x02 != 0 {
+        return None;
+    }
+    Some(octets[..3].join(":").to_ascii_uppercase())
+}
+
+/// `$XDG_CACHE_HOME/angryether/oui-vendors.json`, or under `~/.cache`
+pub fn default_cache_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|dir| dir.join("angryether").join("oui-vendors.json"))
+}
+
+/// Vendor names already looked up, by OUI, kept between runs so each prefix
+/// is asked about once. Only answers are cached: an unknown prefix is asked
+/// about again next run, in case it has been assigned since.
+#[derive(Debug, Default)]
+pub struct VendorCache {
+    path: Option<PathBuf>,
+    vendors: BTreeMap<String, String>,
+    changed: bool,
+}
+
+impl VendorCache {
+    /// The cache at `path`; empty when there's no file yet or it can't be read
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let vendors = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        VendorCache { path, vendors, changed: false }
+    }
+
+    pub fn get(&self, prefix: &str) -> Option<&str> {
+        self.vendors.get(prefix).map(String::as_str)
+    }
+
+    fn insert(&mut self, prefix: String, vendor: String) {
+        self.vendors.insert(prefix, vendor);
+        self.changed = true;
+    }
+
+    /// Write the cache back, if a lookup added to it
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = self.path.as_deref().filter(|_| self.changed) else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&self.vendors)?)
+            .with_context(|| format!("cannot write {}", path.display()))
+    }
+}
+
+/// Trusts the system's CA bundle, unlike the scanner's own TLS probing
+fn tls_connector() -> Result<TlsConnector> {
+    let bundle = std::env::var("SSL_CERT_FILE")
+        .ok()
+        .or_else(|| CA_BUNDLES.iter().find(|path| Path::new(path).exists()).map(|path| path.to_string()))
+        .ok_or_else(|| anyhow::anyhow!("no CA bundle found (set SSL_CERT_FILE)"))?;
+    let pem = fs::read(&bundle).with_context(|| format!("cannot read {}", bundle))?;
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(CertificateDer::pem_slice_iter(&pem).flatten());
+    anyhow::ensure!(added > 0, "no certificates in {}", bundle);
+
+    let config = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// The vendor in an HTTP reply: `Some` for 200, `None` for 404, an error for
+/// anything else (429 means the rate limit was hit)
+fn parse_response(response: &[u8]) -> Result<Option<String>> {
+    let text = String::from_utf8_lossy(response);
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+    let status = head.lines().next().and_then(|line| line.split_whitespace().nth(1)).unwrap_or("");
+    match status {
+        "200" => Ok(Some(body.trim().to_string()).filter(|vendor| !vendor.is_empty())),
+        "404" => Ok(None),
+        _ => Err(anyhow::anyhow!("{} answered '{}'", API_HOST, head.lines().next().unwrap_or(""))),
+    }
+}
+
+async fn query(connector: &TlsConnector, prefix: &str) -> Result<Option<String>> {
+    let stream = TcpStream::connect((API_HOST, 443)).await?;
+    let mut session = connector.connect(ServerName::try_from(API_HOST)?, stream).await?;
+    // HTTP/1.0, so the reply is never chunked and ends when the server closes
+    let request = format!("GET /{} HTTP/1.0\r\nHost: {}\r\nUser-Agent: angryether\r\n\r\n", prefix, API_HOST);
+    session.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    session.take(MAX_RESPONSE_BYTES).read_to_end(&mut response).await?;
+    parse_response(&response)
+}
+
+/// Vendor names for `macs`, by MAC, from the cache or else the online
+/// service. Best effort: lookups stop at the first connection failure or
+/// refusal and once `LOOKUP_BUDGET` is spent, leaving those MACs out.
+pub async fn lookup_vendors<'a>(macs: impl IntoIterator<Item = &'a String>, cache: &mut VendorCache) -> HashMap<String, String> {
+    let started = Instant::now();
+    let mut connector = None;
+    let mut queried = false;
+    let mut missing = HashSet::new();
+    let mut vendors = HashMap::new();
+
+    for mac in macs {
+        let Some(prefix) = oui_prefix(mac) else { continue };
+        if let Some(vendor) = cache.get(&prefix) {
+            vendors.insert(mac.clone(), vendor.to_string());
+            continue;
+        }
+        if missing.contains(&prefix) {
+            continue;
+        }
+        let Some(left) = LOOKUP_BUDGET.checked_sub(started.elapsed()) else {
+            debug!("OUI lookup budget spent, {} left without a vendor", mac);
+            continue;
+        };
+        if connector.is_none() {
+            match tls_connector() {
+                Ok(built) => connector = Some(built),
+                Err(e) => {
+                    warn!("Online OUI lookup unavailable: {}", e);
+                    break;
+                }
+            }
+        }
+        let Some(connector) = &connector else { break };
+        if queried {
+            sleep(LOOKUP_INTERVAL).await;
+        }
+        queried = true;
+        match timeout(left.min(LOOKUP_TIMEOUT), query(connector, &prefix)).await {
+            Ok(Ok(Some(vendor))) => {
+                cache.insert(prefix, vendor.clone());
+                vendors.insert(mac.clone(), vendor);
+            }
+            Ok(Ok(None)) => {
+                missing.insert(prefix);
+            }
+            Ok(Err(e)) => {
+                warn!("Online OUI lookup for {} failed, giving up: {}", prefix, e);
+                break;
+            }
+            Err(_) => {
+                warn!("Online OUI lookup for {} timed out, giving up", prefix);
+                break;
+            }
+        }
+    }
+    vendors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_oui_prefix_and_response() {
+        assert_eq!(oui_prefix("00:1a:2b:3c:4d:5e").as_deref(), Some("00:1A:2B"));
+        assert_eq!(oui_prefix("00-1A-2B-3C-4D-5E").as_deref(), Some("00:1A:2B"));
+        assert_eq!(oui_prefix("00:1a:2b"), None);
+        assert_eq!(oui_prefix("zz:1a:2b:3c:4d:5e"), None);
+        assert_eq!(oui_prefix("02:fc:00:00:00:05"), None);
+
+        let reply = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nRaspberry Pi Trading Ltd";
+        assert_eq!(parse_response(reply).unwrap().as_deref(), Some("Raspberry Pi Trading Ltd"));
+        assert_eq!(parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n{\"errors\":{}}").unwrap(), None);
+        assert!(parse_response(b"HTTP/1.1 429 Too Many Requests\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_vendor_cache_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("cache").join("oui-vendors.json");
+        let mut cache = VendorCache::load(Some(path.clone()));
+        assert_eq!(cache.get("00:1A:2B"), None);
+        cache.insert("00:1A:2B".to_string(), "Acme Networks".to_string());
+        cache.save().unwrap();
+        assert_eq!(VendorCache::load(Some(path)).get("00:1A:2B"), Some("Acme Networks"));
+    }
+}
@@ -0,0 +1,845 @@
+use crate::report::write_html_report;
+use crate::services::port_service;
+use crate::{HostResult, ScanResults, FORMAT_VERSION};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{Read as _, Write as _};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Build a filesystem-safe `ip_port.bin` name for a banner dump
+fn banner_dump_name(ip: IpAddr, port: u16) -> String {
+    format!("{}_{}.bin", ip, port)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// Write the raw banner bytes of one open port into `dir`
+pub fn write_banner_dump(dir: &Path, ip: IpAddr, port: u16, raw: &[u8]) -> Result<PathBuf> {
+    let path = dir.join(banner_dump_name(ip, port));
+    fs::write(&path, raw)?;
+    Ok(path)
+}
+
+/// Replace control characters with their escaped text form
+fn sanitize_banner(banner: &str) -> String {
+    banner
+        .chars()
+        .map(|c| if c.is_control() { c.escape_default().to_string() } else { c.to_string() })
+        .collect()
+}
+
+/// First bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Serialize results as JSON, pretty-printed unless `compact`
+pub fn results_to_json(results: &ScanResults, compact: bool) -> Result<String> {
+    let json = if compact { serde_json::to_string(results)? } else { serde_json::to_string_pretty(results)? };
+    Ok(json)
+}
+
+/// Write `json` to `path`, gzipped when the path ends in `.gz`
+fn write_json_file(path: &str, json: &str) -> Result<()> {
+    if path.ends_with(".gz") {
+        let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+        encoder.write_all(json.as_bytes())?;
+        encoder.finish()?;
+    } else {
+        fs::write(path, json)?;
+    }
+    Ok(())
+}
+
+pub fn write_json(results: &ScanResults, path: &str, compact: bool) -> Result<()> {
+    write_json_file(path, &results_to_json(results, compact)?)
+}
+
+/// Whether `path` names a MessagePack file, by its `.msgpack` or `.mpk` extension
+pub fn is_msgpack_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("msgpack") || ext.eq_ignore_ascii_case("mpk"))
+}
+
+/// Results from `write_json` output, gzipped or not
+fn parse_json(bytes: Vec<u8>) -> Result<ScanResults> {
+    let json = if bytes.starts_with(&GZIP_MAGIC) {
+        let mut json = String::new();
+        GzDecoder::new(bytes.as_slice()).read_to_string(&mut json)?;
+        json
+    } else {
+        String::from_utf8(bytes)?
+    };
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Load results written by `write_json` or `write_msgpack`, told apart by
+/// the extension, e.g. a previous run to compare against. Files from a newer
+/// format are refused rather than misread.
+pub fn read_results(path: &str) -> Result<ScanResults> {
+    let bytes = fs::read(path)?;
+    let results = if is_msgpack_path(path) { rmp_serde::from_slice(&bytes)? } else { parse_json(bytes)? };
+    if results.format_version > FORMAT_VERSION {
+        anyhow::bail!(
+            "format version {} is newer than this build understands ({})",
+            results.format_version,
+            FORMAT_VERSION
+        );
+    }
+    Ok(results)
+}
+
+/// Fold the hosts of a `--resume`d run in with this run's, which covered the rest.
+/// A host in both keeps this run's result, and the resumed hosts keep the
+/// risks saved with them, which were read from the whole banners whatever
+/// `--banner-mode` left in the file. Totals are counted again from the merged
+/// hosts, except the active and scanned counts, which also cover hosts that
+/// `--open-only` left out of `hosts`.
+pub fn merge_resumed(results: &mut ScanResults, resumed: ScanResults) {
+    let rescanned: HashSet<&str> = results.hosts.iter().map(|host| host.ip.as_str()).collect();
+    let kept: Vec<HostResult> = resumed.hosts.into_iter().filter(|host| !rescanned.contains(host.ip.as_str())).collect();
+    results.total_hosts_scanned += kept.len();
+    results.active_hosts_found += kept.len();
+    results.omitted_open_ports += resumed.omitted_open_ports;
+    results.probes_sent += resumed.probes_sent;
+    results.duration_ms += resumed.duration_ms;
+    let kept_ips: HashSet<&str> = kept.iter().map(|host| host.ip.as_str()).collect();
+    results.risks.extend(resumed.risks.into_iter().filter(|risk| kept_ips.contains(risk.ip.as_str())));
+    results.risks.sort_by_key(|risk| (risk.severity, risk.ip.parse::<IpAddr>().ok(), risk.port));
+    results.hosts.extend(kept);
+    results.hosts.sort_by_key(|host| host.ip.parse::<IpAddr>().ok());
+    results.total_open_ports = results
+        .hosts
+        .iter()
+        .flat_map(|host| &host.open_ports)
+        .filter(|port| port.state != "filtered")
+        .count();
+}
+
+/// JSON Schema of the `write_json` output, generated from `ScanResults`
+pub fn results_schema() -> Result<String> {
+    Ok(serde_json::to_string_pretty(&schemars::schema_for!(ScanResults))?)
+}
+
+/// What `--dry-run` found it would scan, written instead of results to the `--json` file
+#[derive(Serialize, Debug, Clone)]
+pub struct TargetPlan {
+    pub interface: String,
+    pub subnet: String,
+    pub seed: u64,
+    /// In the order a real run would probe them
+    pub hosts: Vec<String>,
+    pub tcp_ports: Vec<u16>,
+    pub udp_ports: Vec<u16>,
+}
+
+pub fn write_target_plan(plan: &TargetPlan, path: &str) -> Result<()> {
+    write_json_file(path, &serde_json::to_string_pretty(plan)?)
+}
+
+/// Streams one JSON `HostResult` per line as each host finishes, so a crash
+/// still leaves every completed host on disk
+pub struct JsonlWriter {
+    file: File,
+}
+
+impl JsonlWriter {
+    pub fn create(path: &str) -> Result<Self> {
+        Ok(JsonlWriter { file: File::create(path)? })
+    }
+
+    pub fn write_host(&mut self, host: &HostResult) -> Result<()> {
+        self.write_line(host)
+    }
+
+    /// Append any value as one JSON line, flushed straight away
+    pub fn write_line<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        let mut line = serde_json::to_string(value)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Write results as MessagePack, a compact binary twin of the JSON output.
+///
+/// Structs are encoded as maps with field names, since positional arrays
+/// can't represent the fields the JSON leaves out when empty.
+pub fn write_msgpack(results: &ScanResults, path: &str) -> Result<()> {
+    fs::write(path, rmp_serde::to_vec_named(results)?)?;
+    Ok(())
+}
+
+/// Write one CSV row per open port, plus an empty-port row for each host
+/// without open ports so no host is lost
+pub fn write_csv(results: &ScanResults, path: &str) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["ip", "hostname", "port", "protocol", "banner", "discovery_method"])?;
+    for host in &results.hosts {
+        let hostname = host.hostname.as_deref().unwrap_or_default();
+        if host.open_ports.is_empty() {
+            writer.write_record([host.ip.as_str(), hostname, "", "", "", host.discovery_method.as_str()])?;
+        }
+        for port in &host.open_ports {
+            writer.write_record([
+                host.ip.as_str(),
+                hostname,
+                &port.port.to_string(),
+                port.protocol.as_str(),
+                port.banner.as_str(),
+                host.discovery_method.as_str(),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Render results in nmap's grepable (`-oG`) format: a `Status` line per
+/// host and a `Ports` line for hosts with open ports.
+///
+/// Banners follow the ports after a `Banners:` marker, with control
+/// characters and `;` escaped so each stays on its line and entry.
+pub fn results_to_grepable(results: &ScanResults) -> String {
+    let mut out = format!(
+        "# AngryEther {} scan initiated {} as: angryether (subnet {})\n",
+        env!("CARGO_PKG_VERSION"),
+        results.timestamp.to_rfc2822(),
+        results.subnet
+    );
+    for host in &results.hosts {
+        // nmap leaves the parentheses empty for hosts without a name
+        let hostname = host.hostname.as_deref().unwrap_or_default();
+        let _ = writeln!(out, "Host: {} ({})\tStatus: Up", host.ip, hostname);
+        if host.open_ports.is_empty() {
+            continue;
+        }
+
+        let ports: Vec<String> = host
+            .open_ports
+            .iter()
+            .map(|port| {
+                let service = port_service(port).unwrap_or("");
+                format!("{}/{}/{}//{}///", port.port, port.state, port.protocol, service)
+            })
+            .collect();
+        let _ = write!(out, "Host: {} ({})\tPorts: {}", host.ip, hostname, ports.join(", "));
+
+        let banners: Vec<String> = host
+            .open_ports
+            .iter()
+            .filter(|port| !port.banner.is_empty())
+            .map(|port| format!("{}/{}: {}", port.port, port.protocol, sanitize_banner(&port.banner).replace(';', "\\u{3b}")))
+            .collect();
+        if !banners.is_empty() {
+            let _ = write!(out, "\tBanners: {}", banners.join("; "));
+        }
+        out.push('\n');
+    }
+    let _ = writeln!(
+        out,
+        "# AngryEther done: {} IP addresses ({} hosts up) scanned",
+        results.total_hosts_scanned, results.active_hosts_found
+    );
+    out
+}
+
+pub fn write_grepable(results: &ScanResults, path: &str) -> Result<()> {
+    fs::write(path, results_to_grepable(results))?;
+    Ok(())
+}
+
+/// `text` without ANSI escape sequences (colours, cursor moves, terminal
+/// titles) or other control characters, except line breaks; tabs become spaces
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: up to BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\n' => out.push('\n'),
+            '\t' => out.push(' '),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Render results as a plain report for reading or attaching to a ticket:
+/// the scan's details, then each host with its open ports in a table, then
+/// any risks. No colours, and banners are stripped of escape sequences.
+pub fn results_to_text(results: &ScanResults) -> String {
+    let title = format!("AngryEther {} scan report", env!("CARGO_PKG_VERSION"));
+    let mut out = format!("{}\n{}\n\n", title, "=".repeat(title.len()));
+    let mut details = vec![
+        ("Started", results.timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+        ("Interface", results.interface.clone()),
+        ("Subnet", results.subnet.clone()),
+        ("Discovery", results.discovery_methods.join(", ")),
+        ("Hosts", format!("{} active of {} scanned", results.active_hosts_found, results.total_hosts_scanned)),
+        ("Open ports", results.total_open_ports.to_string()),
+        ("Duration", format!("{:.1}s", results.duration_ms as f64 / 1000.0)),
+        ("Seed", results.seed.to_string()),
+    ];
+    if let Some(shard) = &results.shard {
+        details.push(("Shard", shard.clone()));
+    }
+    match results.probe_budget {
+        Some(budget) => details.push(("Probes", format!("{} of a budget of {}", results.probes_sent, budget))),
+        None => details.push(("Probes", results.probes_sent.to_string())),
+    }
+    if results.omitted_open_ports > 0 {
+        details.push(("Omitted", format!("{} open ports over --max-results", results.omitted_open_ports)));
+    }
+    if results.partial {
+        let reason = if results.timed_out { "time limit reached" } else { "stopped early" };
+        details.push(("Status", format!("partial, {}", reason)));
+    }
+    for (name, value) in details {
+        let _ = writeln!(out, "{:<12}{}", format!("{}:", name), value);
+    }
+
+    for host in &results.hosts {
+        out.push('\n');
+        let mut heading = host.ip.clone();
+        if let Some(hostname) = &host.hostname {
+            let _ = write!(heading, " ({})", hostname);
+        }
+        if host.is_gateway {
+            heading.push_str(" [gateway]");
+        }
+        let _ = writeln!(out, "{}\n{}", heading, "-".repeat(heading.chars().count()));
+        let mut facts = vec![("Found by", host.discovery_method.clone())];
+        if let Some(mac) = &host.mac_address {
+            match &host.vendor {
+                Some(vendor) => facts.push(("MAC", format!("{} ({})", mac, vendor))),
+                None => facts.push(("MAC", mac.clone())),
+            }
+        }
+        if let Some(rtt) = host.rtt_ms {
+            facts.push(("RTT", format!("{:.2}ms", rtt)));
+        }
+        match (&host.os_guess, host.ttl) {
+            (Some(os), Some(ttl)) => facts.push(("OS guess", format!("{} (TTL {}, {} hops)", os, ttl, host.hops.unwrap_or_default()))),
+            (Some(os), None) => facts.push(("OS guess", os.clone())),
+            _ => {}
+        }
+        for (name, value) in facts {
+            let _ = writeln!(out, "  {:<10}{}", format!("{}:", name), value);
+        }
+        if host.open_ports.is_empty() {
+            out.push_str("  No open ports\n");
+            continue;
+        }
+
+        let mut ports: Vec<_> = host.open_ports.iter().collect();
+        ports.sort_by(|a, b| (a.port, &a.protocol).cmp(&(b.port, &b.protocol)));
+        let rows: Vec<[String; 3]> = ports
+            .iter()
+            .map(|port| {
+                let service = port_service(port).unwrap_or("");
+                [format!("{}/{}", port.port, port.protocol), port.state.clone(), service.to_string()]
+            })
+            .collect();
+        let mut widths = ["PORT".len(), "STATE".len(), "SERVICE".len()];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let indent = widths.iter().sum::<usize>() + 2 + 3 * 2;
+        let _ = writeln!(out, "\n  {:<w0$}  {:<w1$}  {:<w2$}  BANNER", "PORT", "STATE", "SERVICE", w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+        for (row, port) in rows.iter().zip(&ports) {
+            let line = format!("  {:<w0$}  {:<w1$}  {:<w2$}", row[0], row[1], row[2], w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+            // Long banners continue on lines of their own under the column
+            let banner = strip_ansi(&port.banner);
+            let mut banner_lines = banner.lines().map(str::trim).filter(|line| !line.is_empty());
+            match banner_lines.next() {
+                Some(first) => {
+                    let _ = writeln!(out, "{}  {}", line, first);
+                }
+                None => {
+                    let _ = writeln!(out, "{}", line.trim_end());
+                }
+            }
+            for rest in banner_lines {
+                let _ = writeln!(out, "{}{}", " ".repeat(indent), rest);
+            }
+        }
+    }
+
+    if !results.risks.is_empty() {
+        out.push_str("\nRisks\n-----\n");
+        for risk in &results.risks {
+            let _ = writeln!(
+                out,
+                "  {:<7}{} {}/{} {}: {}",
+                risk.severity.name().to_uppercase(),
+                risk.ip,
+                risk.port,
+                risk.protocol,
+                risk.service,
+                risk.reason
+            );
+        }
+    }
+    out
+}
+
+pub fn write_text_report(results: &ScanResults, path: &str) -> Result<()> {
+    fs::write(path, results_to_text(results))?;
+    Ok(())
+}
+
+/// The files a finished scan is written to, one optional path per format
+#[derive(Debug, Clone, Default)]
+pub struct OutputFiles {
+    pub json: Option<String>,
+    /// Write the JSON on one line rather than pretty-printed
+    pub compact_json: bool,
+    pub msgpack: Option<String>,
+    pub csv: Option<String>,
+    pub grepable: Option<String>,
+    pub text: Option<String>,
+    pub html: Option<String>,
+    /// Appended to rather than replaced; needs the `sqlite` feature
+    pub sqlite: Option<String>,
+}
+
+/// How writing one of the `OutputFiles` went
+#[derive(Debug)]
+pub struct Written {
+    /// The format, e.g. "HTML report"
+    pub what: &'static str,
+    pub path: String,
+    /// Anything worth adding on success, such as the run's row id in SQLite
+    pub result: Result<Option<String>>,
+}
+
+impl OutputFiles {
+    pub fn is_empty(&self) -> bool {
+        [&self.json, &self.msgpack, &self.csv, &self.grepable, &self.text, &self.html, &self.sqlite]
+            .iter()
+            .all(|path| path.is_none())
+    }
+
+    /// Write `results` to every file asked for, carrying on past any that fail
+    pub fn write(&self, results: &ScanResults) -> Vec<Written> {
+        let mut written = Vec::new();
+        let mut write = |what: &'static str, path: &Option<String>, result: &dyn Fn(&str) -> Result<Option<String>>| {
+            if let Some(path) = path {
+                written.push(Written { what, path: path.clone(), result: result(path) });
+            }
+        };
+        write("JSON", &self.json, &|path| write_json(results, path, self.compact_json).map(|_| None));
+        write("MessagePack", &self.msgpack, &|path| write_msgpack(results, path).map(|_| None));
+        write("CSV", &self.csv, &|path| write_csv(results, path).map(|_| None));
+        write("grepable output", &self.grepable, &|path| write_grepable(results, path).map(|_| None));
+        write("text report", &self.text, &|path| write_text_report(results, path).map(|_| None));
+        write("HTML report", &self.html, &|path| write_html_report(results, path).map(|_| None));
+        write("SQLite", &self.sqlite, &|path| write_sqlite(results, path));
+        written
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(results: &ScanResults, path: &str) -> Result<Option<String>> {
+    let scan_id = crate::sqlite::write_sqlite(results, path)?;
+    Ok(Some(format!("as scan {}", scan_id)))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(_results: &ScanResults, _path: &str) -> Result<Option<String>> {
+    anyhow::bail!("AngryEther was built without the sqlite feature")
+}
+
+/// Write discovered hosts one per line (or as `ip,mac,hostname` rows) for other tools to consume
+pub fn write_hosts_export(
+    path: &str,
+    hosts: &[IpAddr],
+    mac_addresses: &HashMap<IpAddr, String>,
+    hostnames: &HashMap<IpAddr, String>,
+    with_details: bool,
+    timestamp: DateTime<Utc>,
+    subnet: &str,
+) -> Result<()> {
+    let mut sorted = hosts.to_vec();
+    sorted.sort();
+
+    let mut content = format!("# AngryEther host export {} subnet {}\n", timestamp.to_rfc3339(), subnet);
+    if with_details {
+        content.push_str("# ip,mac,hostname\n");
+    }
+    for host in sorted {
+        if with_details {
+            let mac = mac_addresses.get(&host).map(String::as_str).unwrap_or("");
+            let hostname = hostnames.get(&host).map(String::as_str).unwrap_or("");
+            content.push_str(&format!("{},{},{}\n", host, mac, hostname));
+        } else {
+            content.push_str(&format!("{}\n", host));
+        }
+    }
+
+    fs::write(path, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portscan::BannerMode;
+    use crate::risk::find_risks;
+    use crate::OpenPort;
+    use chrono::Utc;
+    use std::net::Ipv4Addr;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_banner_dump() {
+        let dir = tempdir().unwrap();
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let raw = b"SSH-2.0-OpenSSH_9.6\r\n\x00\xff";
+
+        let path = write_banner_dump(dir.path(), ip, 22, raw).unwrap();
+        assert_eq!(path.file_name().unwrap(), "192.168.1.10_22.bin");
+        assert_eq!(fs::read(path).unwrap(), raw);
+
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(banner_dump_name(ip, 443), "2001_db8__1_443.bin");
+    }
+
+    fn hostile_banners() -> Vec<String> {
+        vec![
+            "\0\x07\x1b[31mred\x1b[0m\r\n\t\x7f".to_string(),
+            String::from_utf8_lossy(&[0xff, 0xfe, b'S', b'S', b'H', 0xc3, 0x28]).into_owned(),
+            "quote \" backslash \\ </script>".to_string(),
+        ]
+    }
+
+    fn results_with_banners(banners: &[String]) -> ScanResults {
+        ScanResults {
+            format_version: FORMAT_VERSION,
+            timestamp: Utc::now(),
+            interface: "eth0".to_string(),
+            subnet: "192.168.1.0/24".to_string(),
+            timeout_ms: 500,
+            total_hosts_scanned: 256,
+            active_hosts_found: 1,
+            discovery_methods: vec!["ICMP".to_string()],
+            hosts: vec![HostResult {
+                ip: "192.168.1.10".to_string(),
+                discovery_method: "ICMP".to_string(),
+                open_ports: banners
+                    .iter()
+                    .enumerate()
+                    .map(|(i, banner)| OpenPort { banner: banner.clone(), ..OpenPort::tcp(1000 + i as u16) })
+                    .collect(),
+                scan_duration_ms: 1200,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_json_survives_hostile_banners() {
+        let banners = hostile_banners();
+        let results = results_with_banners(&banners);
+
+        let json = results_to_json(&results, false).unwrap();
+        let parsed: ScanResults = serde_json::from_str(&json).unwrap();
+        let parsed_banners: Vec<String> = parsed.hosts[0].open_ports.iter().map(|p| p.banner.clone()).collect();
+        assert_eq!(parsed_banners, banners);
+    }
+
+    #[test]
+    fn test_compact_and_gzipped_json() {
+        let results = results_with_banners(&hostile_banners());
+        let compact = results_to_json(&results, true).unwrap();
+        assert!(!compact.contains('\n') && compact.len() < results_to_json(&results, false).unwrap().len());
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("scan.json.gz");
+        let path = path.to_str().unwrap();
+        write_json(&results, path, true).unwrap();
+        assert!(fs::read(path).unwrap().starts_with(&GZIP_MAGIC));
+        let banners = |results: &ScanResults| results.hosts[0].open_ports.iter().map(|p| p.banner.clone()).collect::<Vec<_>>();
+        assert_eq!(banners(&read_results(path).unwrap()), banners(&results));
+    }
+
+    #[test]
+    fn test_format_version() {
+        let mut results = results_with_banners(&[]);
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("scan.json");
+        let path = path.to_str().unwrap();
+
+        write_json(&results, path, false).unwrap();
+        assert_eq!(read_results(path).unwrap().format_version, FORMAT_VERSION);
+        results.format_version = FORMAT_VERSION + 1;
+        write_json(&results, path, false).unwrap();
+        assert!(read_results(path).is_err());
+
+        let schema: serde_json::Value = serde_json::from_str(&results_schema().unwrap()).unwrap();
+        assert!(schema["properties"]["format_version"].is_object());
+        assert!(schema["$defs"]["HostResult"]["properties"]["discovery_method"].is_object());
+    }
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        let banners = hostile_banners();
+        let mut results = results_with_banners(&banners);
+        results.shard = Some("4/2".to_string());
+        results.hosts[0].open_ports[0].banner_status = Some("success".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.msgpack");
+        let path = path.to_str().unwrap();
+
+        write_msgpack(&results, path).unwrap();
+        let parsed = read_results(path).unwrap();
+        assert_eq!(parsed.timestamp, results.timestamp);
+        assert_eq!(parsed.shard.as_deref(), Some("4/2"));
+        assert_eq!(parsed.hosts[0].open_ports[0].banner_status.as_deref(), Some("success"));
+        assert_eq!(parsed.hosts[0].open_ports[1].banner, banners[1]);
+        assert!(fs::read(path).unwrap().len() < results_to_json(&results, false).unwrap().len());
+
+        // The extension decides, so a newer MessagePack file is refused as a JSON one is
+        results.format_version = FORMAT_VERSION + 1;
+        let mpk = dir.path().join("scan.MPK");
+        write_msgpack(&results, mpk.to_str().unwrap()).unwrap();
+        assert!(read_results(mpk.to_str().unwrap()).is_err());
+        assert!(!is_msgpack_path("scan.json.gz"));
+    }
+
+    #[test]
+    fn test_jsonl_writer() {
+        let banners = hostile_banners();
+        let results = results_with_banners(&banners);
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("scan.jsonl");
+
+        let mut writer = JsonlWriter::create(path.to_str().unwrap()).unwrap();
+        writer.write_host(&results.hosts[0]).unwrap();
+        // Flushed per host, so the line is readable before the writer goes away
+        writer.write_host(&results.hosts[0]).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let parsed: HostResult = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(parsed.ip, "192.168.1.10");
+        assert_eq!(parsed.open_ports.len(), banners.len());
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let banners = vec!["SSH-2.0-OpenSSH".to_string(), "line one\nquote \" comma,".to_string()];
+        let mut results = results_with_banners(&banners);
+        let mut quiet_host = results.hosts[0].clone();
+        quiet_host.ip = "192.168.1.11".to_string();
+        quiet_host.open_ports.clear();
+        results.hosts.push(quiet_host);
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("scan.csv");
+
+        write_csv(&results, path.to_str().unwrap()).unwrap();
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        assert_eq!(reader.headers().unwrap(), vec!["ip", "hostname", "port", "protocol", "banner", "discovery_method"]);
+        let rows: Vec<csv::StringRecord> = reader.records().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1], vec!["192.168.1.10", "", "1001", "tcp", banners[1].as_str(), "ICMP"]);
+        assert_eq!(rows[2], vec!["192.168.1.11", "", "", "", "", "ICMP"]);
+    }
+
+    #[test]
+    fn test_grepable_output() {
+        let banners = vec!["SSH-2.0-OpenSSH_9.6".to_string(), "a;b\tc".to_string()];
+        let mut results = results_with_banners(&banners);
+        results.hosts[0].open_ports[0].port = 22;
+        results.hosts[0].open_ports[1].port = 53;
+        results.hosts[0].open_ports[1].protocol = "udp".to_string();
+        results.hosts[0].open_ports[1].state = "open|filtered".to_string();
+        let mut quiet_host = results.hosts[0].clone();
+        quiet_host.ip = "192.168.1.11".to_string();
+        quiet_host.hostname = Some("nas.lan".to_string());
+        quiet_host.open_ports.clear();
+        results.hosts.push(quiet_host);
+
+        let grepable = results_to_grepable(&results);
+        let lines: Vec<&str> = grepable.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("# AngryEther"));
+        assert_eq!(lines[1], "Host: 192.168.1.10 ()\tStatus: Up");
+        assert_eq!(
+            lines[2],
+            "Host: 192.168.1.10 ()\tPorts: 22/open/tcp//ssh///, 53/open|filtered/udp//domain///\t\
+             Banners: 22/tcp: SSH-2.0-OpenSSH_9.6; 53/udp: a\\u{3b}b\\tc"
+        );
+        assert_eq!(lines[3], "Host: 192.168.1.11 (nas.lan)\tStatus: Up");
+        assert!(lines[4].starts_with("# AngryEther done: 256 IP addresses (1 hosts up)"));
+    }
+
+    #[test]
+    fn test_text_report() {
+        let banners = vec!["\x1b[1;31mWELCOME\x1b[0m to acme\r\nbuild 42".to_string(), "SSH-2.0-OpenSSH_9.6".to_string(), String::new()];
+        let mut results = results_with_banners(&banners);
+        results.hosts[0].open_ports[0].port = 2323;
+        results.hosts[0].open_ports[1].port = 22;
+        results.hosts[0].open_ports[1].service = Some("ssh".to_string());
+        results.hosts[0].mac_address = Some("00:11:22:33:44:55".to_string());
+        results.partial = true;
+        results.timed_out = true;
+
+        let text = results_to_text(&results);
+        assert!(!text.contains('\x1b'), "{}", text);
+        assert!(text.contains("Status:     partial, time limit reached\n"), "{}", text);
+        assert!(text.contains("  MAC:      00:11:22:33:44:55\n"), "{}", text);
+        let table: Vec<&str> = text.lines().skip_while(|line| !line.starts_with("  PORT")).collect();
+        assert_eq!(
+            table,
+            [
+                "  PORT      STATE  SERVICE  BANNER",
+                "  22/tcp    open   ssh      SSH-2.0-OpenSSH_9.6",
+                "  1002/tcp  open",
+                "  2323/tcp  open            WELCOME to acme",
+                "                            build 42",
+            ]
+        );
+        assert_eq!(strip_ansi("\x1b]0;title\x07a\tb\x00c"), "a bc");
+    }
+
+    #[test]
+    fn test_write_hosts_export() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("hosts.txt");
+        let path = path.to_str().unwrap();
+        let hosts: Vec<IpAddr> = vec![Ipv4Addr::new(10, 0, 0, 20).into(), Ipv4Addr::new(10, 0, 0, 3).into()];
+        let macs = HashMap::from([(hosts[0], "aa:bb:cc:dd:ee:ff".to_string())]);
+        let names = HashMap::from([(hosts[0], "printer.lan".to_string())]);
+        let timestamp = Utc::now();
+
+        write_hosts_export(path, &hosts, &macs, &names, false, timestamp, "10.0.0.0/24").unwrap();
+        let content = fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines[0].starts_with("# AngryEther host export") && lines[0].ends_with("subnet 10.0.0.0/24"));
+        assert_eq!(&lines[1..], ["10.0.0.3", "10.0.0.20"]);
+
+        write_hosts_export(path, &hosts, &macs, &names, true, timestamp, "10.0.0.0/24").unwrap();
+        let content = fs::read_to_string(path).unwrap();
+        let rows: Vec<&str> = content.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(rows, ["10.0.0.3,,", "10.0.0.20,aa:bb:cc:dd:ee:ff,printer.lan"]);
+    }
+
+    #[test]
+    fn test_output_files_write() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| Some(dir.path().join(name).to_str().unwrap().to_string());
+        let results = results_with_banners(&["SSH-2.0-OpenSSH_9.6".to_string()]);
+        assert!(OutputFiles::default().is_empty());
+
+        let files = OutputFiles {
+            json: path("scan.json"),
+            compact_json: true,
+            csv: path("scan.csv"),
+            html: path("scan.html"),
+            // One failing write doesn't stop the rest
+            text: path("missing/scan.txt"),
+            ..Default::default()
+        };
+        let written = files.write(&results);
+        let outcomes: Vec<(&str, bool)> = written.iter().map(|written| (written.what, written.result.is_ok())).collect();
+        assert_eq!(outcomes, [("JSON", true), ("CSV", true), ("text report", false), ("HTML report", true)]);
+        let json = fs::read_to_string(files.json.as_ref().unwrap()).unwrap();
+        assert_eq!(json.lines().count(), 1);
+        assert_eq!(read_results(files.json.as_ref().unwrap()).unwrap().hosts.len(), 1);
+        assert!(fs::read_to_string(files.csv.unwrap()).unwrap().contains("SSH-2.0-OpenSSH_9.6"));
+    }
+
+    #[test]
+    fn test_merge_resumed() {
+        let mut results = results_with_banners(&["SSH-2.0-OpenSSH_9.6".to_string()]);
+        let mut resumed = results_with_banners(&[]);
+        resumed.hosts = vec![
+            // Already in this run, whose result wins
+            HostResult { ip: "192.168.1.10".to_string(), open_ports: vec![OpenPort::tcp(22), OpenPort::tcp(80)], ..Default::default() },
+            HostResult {
+                ip: "192.168.1.2".to_string(),
+                open_ports: vec![OpenPort::tcp(23), OpenPort { state: "filtered".to_string(), ..OpenPort::tcp(24) }],
+                ..Default::default()
+            },
+        ];
+        resumed.risks = find_risks(&resumed.hosts);
+        resumed.total_open_ports = 99;
+        resumed.probes_sent = 5;
+
+        merge_resumed(&mut results, resumed);
+        let ips: Vec<&str> = results.hosts.iter().map(|host| host.ip.as_str()).collect();
+        assert_eq!(ips, ["192.168.1.2", "192.168.1.10"]);
+        assert_eq!(results.hosts[1].open_ports[0].port, 1000);
+        assert_eq!((results.total_hosts_scanned, results.active_hosts_found), (257, 2));
+        // The filtered port isn't an open one
+        assert_eq!((results.total_open_ports, results.probes_sent), (2, 5));
+        // Only the resumed host's telnet, not the ports it lost to this run
+        let risks: Vec<(&str, u16)> = results.risks.iter().map(|risk| (risk.ip.as_str(), risk.port)).collect();
+        assert_eq!(risks, [("192.168.1.2", 23)]);
+    }
+
+    #[test]
+    fn test_merge_resumed_keeps_risks_of_cut_banners() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("resume.json");
+        let path = path.to_str().unwrap();
+        let mut resumed = results_with_banners(&[]);
+        resumed.hosts = vec![HostResult {
+            ip: "192.168.1.2".to_string(),
+            open_ports: vec![
+                OpenPort { banner: "SMBv1 (NT LM 0.12)".to_string(), ..OpenPort::tcp(445) },
+                OpenPort { banner: "HTTP/1.1 401 Unauthorized | Auth: Basic".to_string(), ..OpenPort::tcp(80) },
+            ],
+            ..Default::default()
+        }];
+        // As a --banner-mode none run saves them: risks from the whole banners, then the banners dropped
+        resumed.risks = find_risks(&resumed.hosts);
+        for port in &mut resumed.hosts[0].open_ports {
+            port.banner = BannerMode::None.apply(&port.banner);
+        }
+        write_json(&resumed, path, false).unwrap();
+
+        let mut results = results_with_banners(&[]);
+        merge_resumed(&mut results, read_results(path).unwrap());
+        let risks: Vec<(&str, u16)> = results.risks.iter().map(|risk| (risk.service.as_str(), risk.port)).collect();
+        assert_eq!(risks, [("smb", 445), ("http", 80)]);
+    }
+
+    #[test]
+    fn test_sanitize_banner() {
+        assert_eq!(sanitize_banner("ok\x00\x1b"), "ok\\u{0}\\u{1b}");
+    }
+}
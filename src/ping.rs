@@ -1,108 +1,689 @@
 use anyhow::Result;
+use futures::stream::{self, FuturesUnordered, StreamExt};
+use crate::budget::ProbeBudget;
+use crate::discovery::DiscoveryMethod;
+use crate::events::{emit, EventSender, ScanEvent};
+use crate::network::{interface_scope_id, scoped_socket_addr};
+use crate::rng::{ip_key, rng_for};
+use crate::socks::Socks5Proxy;
+use crate::timing::{Jitter, TimingProfile};
+use indicatif::ProgressBar;
+use log::{debug, info, warn};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
+use pnet::packet::Packet;
+use pnet::transport::{ipv4_packet_iter, transport_channel, TransportChannelType};
+use rand::rngs::StdRng;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr};
-use std::time::Duration;
-use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingSequence};
-use tokio::time::timeout;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingSequence, ICMP};
+use tokio::time::{sleep, timeout};
 use tokio::net::TcpSocket;
+use tokio_util::sync::CancellationToken;
+
+/// Which probe a live host answered.
+///
+/// There is no `Dead` variant: a host that answers neither probe is the
+/// `None` of `ping_host`'s `Option<PingReply>`, and `sweep` leaves it out,
+/// since it has no TTL or round trip for a `PingReply` to carry. `sweep`
+/// returns the addresses it was given, IPv4 or IPv6, with their replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingOutcome {
+    Icmp,
+    /// No echo reply, but a TCP connect to this port was accepted or
+    /// refused, which suggests ICMP is filtered on the way to the host
+    TcpFallback(u16),
+}
+
+impl PingOutcome {
+    pub fn method(&self) -> DiscoveryMethod {
+        match self {
+            PingOutcome::Icmp => DiscoveryMethod::Icmp,
+            PingOutcome::TcpFallback(_) => DiscoveryMethod::Tcp,
+        }
+    }
+}
+
+/// A host's answer to the ping sweep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingReply {
+    pub outcome: PingOutcome,
+    /// IP TTL of the reply, when the socket could see it
+    pub ttl: Option<u8>,
+    /// Echo round trip (the average with a ping count), or how long the
+    /// answering TCP connect took
+    pub rtt: Duration,
+    /// Echo statistics, when the scanner sends a fixed number of echoes
+    pub stats: Option<PingStats>,
+}
+
+/// Loss and round trips of a `with_ping_count` echo series, as `ping` reports them
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+pub struct PingStats {
+    pub sent: u32,
+    pub received: u32,
+    pub loss_percent: f64,
+    /// Round trips in milliseconds; `None` when no echo came back
+    pub min_rtt_ms: Option<f64>,
+    pub avg_rtt_ms: Option<f64>,
+    pub max_rtt_ms: Option<f64>,
+}
+
+impl PingStats {
+    pub fn from_rtts(sent: u32, rtts: &[Duration]) -> Self {
+        let millis: Vec<f64> = rtts.iter().map(|rtt| rtt.as_secs_f64() * 1000.0).collect();
+        let received = millis.len() as u32;
+        PingStats {
+            sent,
+            received,
+            loss_percent: if sent == 0 { 0.0 } else { f64::from(sent - received) * 100.0 / f64::from(sent) },
+            min_rtt_ms: millis.iter().copied().reduce(f64::min),
+            avg_rtt_ms: (received > 0).then(|| millis.iter().sum::<f64>() / f64::from(received)),
+            max_rtt_ms: millis.iter().copied().reduce(f64::max),
+        }
+    }
+
+    /// `5 sent, 4 received, 20% loss, rtt min/avg/max 0.12/0.20/0.31 ms`
+    pub fn summary(&self) -> String {
+        let counts = format!("{} sent, {} received, {:.0}% loss", self.sent, self.received, self.loss_percent);
+        match (self.min_rtt_ms, self.avg_rtt_ms, self.max_rtt_ms) {
+            (Some(min), Some(avg), Some(max)) => format!("{}, rtt min/avg/max {:.2}/{:.2}/{:.2} ms", counts, min, avg, max),
+            _ => counts,
+        }
+    }
+}
+
+/// Watches incoming TCP segments on a raw socket to learn the IP TTL of the
+/// SYN-ACKs and RSTs that answer TCP pings, which `connect()` never exposes.
+/// Needs root or `CAP_NET_RAW`, and only sees IPv4.
+struct TcpTtlSniffer {
+    ttls: Arc<Mutex<HashMap<Ipv4Addr, u8>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TcpTtlSniffer {
+    fn start() -> Result<Self> {
+        let (_, mut receiver) = transport_channel(
+            65536,
+            TransportChannelType::Layer3(IpNextHeaderProtocols::Tcp),
+        )?;
+        let ttls = Arc::new(Mutex::new(HashMap::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = std::thread::spawn({
+            let ttls = Arc::clone(&ttls);
+            let stop = Arc::clone(&stop);
+            move || {
+                let mut packets = ipv4_packet_iter(&mut receiver);
+                while !stop.load(Ordering::Relaxed) {
+                    let Ok(Some((packet, _))) = packets.next_with_timeout(Duration::from_millis(50)) else {
+                        continue;
+                    };
+                    let Some(tcp) = TcpPacket::new(packet.payload()) else {
+                        continue;
+                    };
+                    let flags = tcp.get_flags();
+                    let answers_connect = flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK
+                        || flags & TcpFlags::RST != 0;
+                    if answers_connect {
+                        ttls.lock().unwrap().entry(packet.get_source()).or_insert(packet.get_ttl());
+                    }
+                }
+            }
+        });
+
+        Ok(TcpTtlSniffer { ttls, stop, handle: Some(handle) })
+    }
+
+    fn ttl(&self, ip: IpAddr) -> Option<u8> {
+        match ip {
+            IpAddr::V4(ip) => self.ttls.lock().unwrap().get(&ip).copied(),
+            IpAddr::V6(_) => None,
+        }
+    }
+}
+
+impl Drop for TcpTtlSniffer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn reply_ttl(packet: &IcmpPacket) -> Option<u8> {
+    match packet {
+        IcmpPacket::V4(reply) => reply.get_ttl(),
+        // surge-ping doesn't surface the ICMPv6 hop limit
+        IcmpPacket::V6(_) => None,
+    }
+}
+
+/// Echoes after the first that a host gets within the ping timeout
+pub const DEFAULT_ICMP_RETRIES: u32 = 1;
+
+/// Echo payload bytes, as `ping` sends by default
+pub const DEFAULT_PING_SIZE: usize = 56;
+
+/// Ports the TCP fallback tries, in order, when a host ignores ICMP echo
+pub const DEFAULT_FALLBACK_PORTS: [u16; 7] = [80, 443, 22, 21, 23, 53, 25];
 
 pub struct PingScanner {
-    client: Client,
+    /// `None` for a TCP-only scanner
+    client: Option<Client>,
+    // Hosts without IPv6 support can still ping IPv4
+    client_v6: Option<Client>,
+    /// Interface index for TCP pings to link-local IPv6 addresses
+    scope_id: Option<u32>,
+    seed: u64,
+    budget: Arc<ProbeBudget>,
+    /// Hosts pinged at once during a sweep
+    concurrency: usize,
+    /// Advanced once per host a sweep finishes with
+    progress: ProgressBar,
+    fallback_ports: Vec<u16>,
+    /// SOCKS5 proxy the TCP pings go through
+    proxy: Option<Socks5Proxy>,
+    /// Echoes per host for statistics; `None` stops at the first reply
+    ping_count: Option<u32>,
+    /// Extra echoes sent within the ping timeout when the first goes unanswered
+    icmp_retries: u32,
+    /// Bytes of echo payload after the ICMP header
+    ping_size: usize,
+    jitter: Jitter,
+    /// Stops pings in flight and the rest of a sweep when cancelled
+    cancel: CancellationToken,
+    events: Option<EventSender>,
 }
 
 impl PingScanner {
+    /// Open the ICMP sockets. Unprivileged ICMP sockets are used where the
+    /// kernel allows them (`net.ipv4.ping_group_range` on Linux), otherwise
+    /// this needs root or `CAP_NET_RAW`. TCP pings need no privileges.
     pub fn new() -> Result<Self> {
         let client_v4 = Client::new(&Config::default())?;
-        Ok(PingScanner { client: client_v4 })
+        let client_v6 = Client::new(&Config::builder().kind(ICMP::V6).build())
+            .map_err(|e| warn!("ICMPv6 socket unavailable, IPv6 hosts get TCP pings only: {}", e))
+            .ok();
+        Ok(PingScanner {
+            client: Some(client_v4),
+            client_v6,
+            ..PingScanner::tcp_only()
+        })
+    }
+
+    /// Whether the sweep sends ICMP echoes, rather than TCP pings only
+    pub fn sends_icmp(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// A scanner that only sends TCP pings, for networks that drop ICMP or
+    /// hosts without ICMP sockets; it opens no sockets up front and needs no
+    /// privileges
+    pub fn tcp_only() -> Self {
+        PingScanner {
+            client: None,
+            client_v6: None,
+            scope_id: None,
+            seed: rand::random(),
+            budget: Arc::new(ProbeBudget::default()),
+            concurrency: TimingProfile::default().ping_concurrency,
+            progress: ProgressBar::hidden(),
+            fallback_ports: DEFAULT_FALLBACK_PORTS.to_vec(),
+            proxy: None,
+            ping_count: None,
+            icmp_retries: DEFAULT_ICMP_RETRIES,
+            ping_size: DEFAULT_PING_SIZE,
+            jitter: Jitter::default(),
+            cancel: CancellationToken::new(),
+            events: None,
+        }
+    }
+
+    /// Ports the TCP fallback tries, in order, when ICMP gets no answer
+    pub fn with_fallback_ports(mut self, ports: Vec<u16>) -> Self {
+        self.fallback_ports = ports;
+        self
+    }
+
+    /// Send the TCP pings through `proxy`; their TTLs, which would be the
+    /// proxy's, aren't read
+    pub fn with_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Send `count` echoes to every host and record loss and round trip
+    /// statistics, instead of stopping at the first reply
+    pub fn with_ping_count(mut self, count: u32) -> Self {
+        self.ping_count = Some(count.max(1));
+        self
+    }
+
+    /// Send up to `retries` more echoes, spaced evenly over the ping
+    /// timeout, when the first gets no reply
+    pub fn with_icmp_retries(mut self, retries: u32) -> Self {
+        self.icmp_retries = retries;
+        self
+    }
+
+    /// Carry `size` bytes of payload in each echo
+    pub fn with_ping_size(mut self, size: usize) -> Self {
+        self.ping_size = size;
+        self
+    }
+
+    /// Count every echo and connect against the scan's probe budget
+    pub fn with_budget(mut self, budget: Arc<ProbeBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Ping at most this many hosts at once during a sweep
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Pause a random time of up to `jitter` before each echo and TCP ping
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Stop pinging once `cancel` is cancelled
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Send a `HostDiscovered` event for each host a sweep finds alive
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Count each host a sweep finishes with on `progress`
+    pub fn with_progress(mut self, progress: ProgressBar) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Send ICMPv6 and TCP pings out of `interface`, which link-local
+    /// addresses need since they are only unique per link
+    pub fn with_ipv6_interface(mut self, interface: &str) -> Self {
+        self.scope_id = interface_scope_id(interface);
+        if self.client.is_none() {
+            return self;
+        }
+        let config = Config::builder().kind(ICMP::V6).interface(interface).build();
+        self.client_v6 = Client::new(&config)
+            .map_err(|e| warn!("cannot bind ICMPv6 to {}, using the default socket: {}", interface, e))
+            .ok()
+            .or(self.client_v6);
+        self
+    }
+
+    /// Derive ICMP identifiers from the run seed so a scan can be replayed
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Probe a host, returning the method that got an answer.
+    ///
+    /// Cancellation drops the ping in flight and returns `None`, as for a
+    /// host that never answered.
+    pub async fn ping_host(&self, ip: IpAddr, timeout_ms: u64) -> Option<PingReply> {
+        self.cancel.run_until_cancelled(self.probe_host(ip, timeout_ms)).await.flatten()
     }
 
-    pub async fn ping_host(&self, ip: Ipv4Addr, timeout_ms: u64) -> bool {
+    async fn probe_host(&self, ip: IpAddr, timeout_ms: u64) -> Option<PingReply> {
         // Try ICMP ping first
-        if self.icmp_ping(ip, timeout_ms).await {
-            return true;
+        let (icmp_reply, stats) = match (self.icmp_client(ip), self.ping_count) {
+            (Some(client), Some(count)) => {
+                let (reply, stats) = self.echo_series(client, ip, count, timeout_ms).await;
+                (reply, Some(stats))
+            }
+            (Some(client), None) => (self.echo(client, ip, timeout_ms).await, None),
+            (None, _) => (None, None),
+        };
+        if icmp_reply.is_some() {
+            return icmp_reply;
         }
         
         // If ICMP fails, try TCP connect to common ports
-        self.tcp_ping(ip, timeout_ms).await
+        if self.client.is_some() {
+            debug!("{}: no ICMP echo reply, falling back to TCP", ip);
+        }
+        let reply = self
+            .tcp_ping(ip, timeout_ms)
+            .await
+            .map(|(port, rtt)| PingReply { outcome: PingOutcome::TcpFallback(port), ttl: None, rtt, stats });
+        if reply.is_none() {
+            debug!("{}: no TCP answer either", ip);
+        }
+        reply
     }
 
-    async fn icmp_ping(&self, ip: Ipv4Addr, timeout_ms: u64) -> bool {
-        let payload = [0; 56];
-        
-        // Try multiple ICMP attempts for reliability
-        for _ in 0..2 {
-            let mut pinger = self
-                .client
-                .pinger(IpAddr::V4(ip), PingIdentifier(rand::random()))
-                .await;
-            
-            let ping_result = timeout(
-                Duration::from_millis(timeout_ms / 2),
-                pinger.ping(PingSequence(0), &payload),
-            ).await;
-
-            match ping_result {
-                Ok(Ok((IcmpPacket::V4(_), _))) => return true,
-                _ => continue,
-            }
+    /// The ICMP socket for `ip`'s family, if one is open
+    fn icmp_client(&self, ip: IpAddr) -> Option<&Client> {
+        match ip {
+            IpAddr::V4(_) => self.client.as_ref(),
+            IpAddr::V6(_) => self.client_v6.as_ref(),
         }
-        false
     }
 
-    async fn tcp_ping(&self, ip: Ipv4Addr, timeout_ms: u64) -> bool {
-        // Common ports to check (like nmap does)
-        let ports = [80, 443, 22, 21, 23, 53, 25];
-        
-        for &port in &ports {
-            let addr = format!("{}:{}", ip, port);
-            let connect_timeout = Duration::from_millis(timeout_ms / ports.len() as u64);
-            
-            if let Ok(socket) = TcpSocket::new_v4() {
-                if let Ok(addr) = addr.parse() {
-                    let connect_result = timeout(connect_timeout, socket.connect(addr)).await;
-                    match connect_result {
-                        Ok(Ok(_)) => return true,
-                        Ok(Err(_)) => continue, // Connection refused is still a live host
-                        Err(_) => continue,     // Timeout
+    fn identifier_rng(&self, ip: IpAddr) -> StdRng {
+        rng_for(self.seed, "ping-identifier", ip_key(ip))
+    }
+
+    /// Send up to `icmp_retries + 1` echoes spread evenly over `timeout_ms`
+    /// and take the first reply to any of them. Every echo waits until the
+    /// one overall deadline, so on a slow link a reply that arrives after the
+    /// next echo went out still counts.
+    async fn echo(&self, client: &Client, ip: IpAddr, timeout_ms: u64) -> Option<PingReply> {
+        let payload = vec![0; self.ping_size];
+        let identifier = PingIdentifier(self.identifier_rng(ip).gen());
+        let attempts = self.icmp_retries + 1;
+        let window = Duration::from_millis(timeout_ms);
+        let deadline = Instant::now() + window;
+
+        let mut echoes: FuturesUnordered<_> = (0..attempts)
+            .map(|attempt| {
+                let payload = &payload;
+                async move {
+                    sleep(window * attempt / attempts).await;
+                    if !self.budget.try_take() {
+                        return None;
+                    }
+                    self.jitter.wait("ping-jitter", ip, attempt.into()).await;
+                    let left = deadline.checked_duration_since(Instant::now())?;
+                    let mut pinger = client.pinger(ip, identifier).await;
+                    pinger.timeout(left);
+                    match pinger.ping(PingSequence(attempt as u16), payload).await {
+                        Ok(reply) => Some(reply),
+                        Err(e) => {
+                            debug!("{}: ICMP echo {} got no reply: {}", ip, attempt, e);
+                            None
+                        }
                     }
                 }
+            })
+            .collect();
+
+        while let Some(result) = echoes.next().await {
+            if let Some((packet, rtt)) = result {
+                let ttl = reply_ttl(&packet);
+                debug!("{}: ICMP echo reply in {:?}, ttl {:?}", ip, rtt, ttl);
+                return Some(PingReply { outcome: PingOutcome::Icmp, ttl, rtt, stats: None });
             }
         }
-        false
+        None
     }
 
-    pub async fn sweep(&self, ip_addresses: Vec<Ipv4Addr>, timeout_ms: u64) -> Vec<Ipv4Addr> {
-        use futures::stream::{self, StreamExt};
-        
-        // Limit concurrency to avoid overwhelming the network
-        let concurrent_limit = 50;
+    /// Send `count` echoes one after another, each waiting up to `timeout_ms`,
+    /// like `ping -c`. The reply carries the average round trip.
+    async fn echo_series(&self, client: &Client, ip: IpAddr, count: u32, timeout_ms: u64) -> (Option<PingReply>, PingStats) {
+        let payload = vec![0; self.ping_size];
+        let mut pinger = client.pinger(ip, PingIdentifier(self.identifier_rng(ip).gen())).await;
+        pinger.timeout(Duration::from_millis(timeout_ms));
+        let mut sent = 0;
+        let mut rtts = Vec::new();
+        let mut ttl = None;
+
+        for sequence in 0..count {
+            if !self.budget.try_take() {
+                break;
+            }
+            self.jitter.wait("ping-jitter", ip, sequence.into()).await;
+            sent += 1;
+            match timeout(Duration::from_millis(timeout_ms), pinger.ping(PingSequence(sequence as u16), &payload)).await {
+                Ok(Ok((packet, rtt))) => {
+                    debug!("{}: ICMP echo {} reply in {:?}", ip, sequence, rtt);
+                    ttl = ttl.or(reply_ttl(&packet));
+                    rtts.push(rtt);
+                }
+                Ok(Err(e)) => debug!("{}: ICMP echo {} failed: {}", ip, sequence, e),
+                Err(_) => debug!("{}: ICMP echo {} timed out", ip, sequence),
+            }
+        }
+
+        let stats = PingStats::from_rtts(sent, &rtts);
+        let reply = stats.avg_rtt_ms.map(|avg| PingReply {
+            outcome: PingOutcome::Icmp,
+            ttl,
+            rtt: Duration::from_secs_f64(avg / 1000.0),
+            stats: Some(stats),
+        });
+        (reply, stats)
+    }
+
+    /// The first fallback port that accepts or refuses, and how long the
+    /// answer took; a refusal still comes from a live host. Direct connects
+    /// share `timeout_ms` between the ports, but each one through a proxy
+    /// gets all of it, since it also waits on the SOCKS greeting, login and
+    /// CONNECT round trips to a proxy that may be far away.
+    async fn tcp_ping(&self, ip: IpAddr, timeout_ms: u64) -> Option<(u16, Duration)> {
+        let ports = &self.fallback_ports;
+
+        for &port in ports {
+            if !self.budget.try_take() {
+                return None;
+            }
+            self.jitter.wait("tcp-ping-jitter", ip, port.into()).await;
+            let addr = scoped_socket_addr(ip, port, self.scope_id);
+
+            let started = Instant::now();
+            let connect_result = match &self.proxy {
+                Some(proxy) => timeout(Duration::from_millis(timeout_ms.max(1)), proxy.connect(addr)).await,
+                None => {
+                    let socket = match ip {
+                        IpAddr::V4(_) => TcpSocket::new_v4(),
+                        IpAddr::V6(_) => TcpSocket::new_v6(),
+                    };
+                    let Ok(socket) = socket else {
+                        continue;
+                    };
+                    let connect_timeout = Duration::from_millis((timeout_ms / ports.len() as u64).max(1));
+                    timeout(connect_timeout, socket.connect(addr)).await
+                }
+            };
+            match connect_result {
+                Ok(Ok(_)) => {
+                    debug!("{}: TCP port {} accepted", ip, port);
+                    return Some((port, started.elapsed()));
+                }
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    debug!("{}: TCP port {} refused", ip, port);
+                    return Some((port, started.elapsed()));
+                }
+                Ok(Err(_)) => continue, // Unreachable
+                Err(_) => continue,     // Timeout
+            }
+        }
+        None
+    }
+
+    /// Ping every address concurrently, using ICMP or ICMPv6 to match its family.
+    ///
+    /// On cancellation the pings in flight are dropped, the hosts not yet
+    /// reached are skipped, and the hosts that already answered are returned.
+    pub async fn sweep<A>(&self, ip_addresses: Vec<A>, timeout_ms: u64) -> Vec<(A, PingReply)>
+    where
+        A: Into<IpAddr> + Copy,
+    {
+        // Without raw socket access TCP-only hosts simply get no TTL
+        let sniffer = match self.proxy {
+            Some(_) => None,
+            None => TcpTtlSniffer::start().map_err(|e| debug!("TCP TTL sniffer unavailable: {}", e)).ok(),
+        };
         
+        info!("ping sweep of {} hosts, {} at a time, {}ms timeout", ip_addresses.len(), self.concurrency, timeout_ms);
         let results: Vec<_> = stream::iter(ip_addresses)
             .map(|ip| async move {
-                if self.ping_host(ip, timeout_ms).await {
-                    Some(ip)
-                } else {
-                    None
+                let reply = self.ping_host(ip.into(), timeout_ms).await;
+                if let Some(reply) = &reply {
+                    emit(&self.events, ScanEvent::HostDiscovered { ip: ip.into(), method: reply.outcome.method(), mac: None });
                 }
+                self.progress.inc(1);
+                reply.map(|reply| (ip, reply))
             })
-            .buffer_unordered(concurrent_limit)
+            .buffer_unordered(self.concurrency)
             .collect()
             .await;
 
-        results.into_iter().filter_map(|result| result).collect()
+        let mut replies: Vec<(A, PingReply)> = results.into_iter().flatten().collect();
+        if let Some(sniffer) = sniffer {
+            // Give the sniffer thread a moment to drain the last segments
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            for (ip, reply) in replies.iter_mut().filter(|(_, reply)| reply.ttl.is_none()) {
+                reply.ttl = sniffer.ttl((*ip).into());
+            }
+        }
+        replies
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::Ipv6Addr;
     use std::str::FromStr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     #[tokio::test]
     async fn test_ping_localhost() {
         let scanner = PingScanner::new().unwrap();
         let localhost = Ipv4Addr::from_str("127.0.0.1").unwrap();
-        let result = scanner.ping_host(localhost, 1000).await;
-        assert!(result);
+        let result = scanner.ping_host(localhost.into(), 1000).await;
+        assert_eq!(result.map(|reply| reply.outcome), Some(PingOutcome::Icmp));
+    }
+
+    #[tokio::test]
+    async fn test_icmp_retries_and_size() {
+        let scanner = PingScanner::new().unwrap().with_icmp_retries(0).with_ping_size(1400);
+        let reply = scanner.ping_host(Ipv4Addr::LOCALHOST.into(), 1000).await;
+        assert_eq!(reply.map(|reply| reply.outcome), Some(PingOutcome::Icmp));
+
+        // TEST-NET-2 never answers, so every retry goes out within the timeout
+        let budget = Arc::new(ProbeBudget::default());
+        let scanner = PingScanner::new()
+            .unwrap()
+            .with_icmp_retries(3)
+            .with_fallback_ports(Vec::new())
+            .with_budget(Arc::clone(&budget));
+        let started = Instant::now();
+        assert!(scanner.ping_host(Ipv4Addr::new(198, 51, 100, 1).into(), 400).await.is_none());
+        assert_eq!(budget.sent(), 4);
+        assert!(started.elapsed() < Duration::from_millis(800));
+    }
+
+    #[tokio::test]
+    async fn test_ping_count_stats() {
+        let scanner = PingScanner::new().unwrap().with_ping_count(3);
+        let reply = scanner.ping_host(Ipv4Addr::LOCALHOST.into(), 1000).await.unwrap();
+        let stats = reply.stats.unwrap();
+        assert_eq!((stats.sent, stats.received, stats.loss_percent), (3, 3, 0.0));
+        assert!(stats.min_rtt_ms <= stats.avg_rtt_ms && stats.avg_rtt_ms <= stats.max_rtt_ms);
+
+        let millis = |ms| Duration::from_millis(ms);
+        let stats = PingStats::from_rtts(4, &[millis(1), millis(3), millis(2)]);
+        assert_eq!(stats.loss_percent, 25.0);
+        assert_eq!((stats.min_rtt_ms, stats.avg_rtt_ms, stats.max_rtt_ms), (Some(1.0), Some(2.0), Some(3.0)));
+        assert_eq!(stats.summary(), "4 sent, 3 received, 25% loss, rtt min/avg/max 1.00/2.00/3.00 ms");
+        assert_eq!(PingStats::from_rtts(2, &[]).summary(), "2 sent, 0 received, 100% loss");
+    }
+
+    #[tokio::test]
+    async fn test_tcp_only_ping() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+
+        let scanner = PingScanner::tcp_only().with_fallback_ports(vec![open, closed]);
+        let reply = scanner.ping_host(Ipv4Addr::LOCALHOST.into(), 1000).await.unwrap();
+        assert_eq!(reply.outcome, PingOutcome::TcpFallback(open));
+
+        // A refusal is an answer too
+        let scanner = PingScanner::tcp_only().with_fallback_ports(vec![closed, open]);
+        let reply = scanner.ping_host(Ipv4Addr::LOCALHOST.into(), 1000).await.unwrap();
+        assert_eq!(reply.outcome, PingOutcome::TcpFallback(closed));
+
+        // A listener whose accept queue is full drops new SYNs: no answer at all
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let full = socket.listen(0).unwrap();
+        let silent = full.local_addr().unwrap().port();
+        let mut backlog = Vec::new();
+        for _ in 0..2 {
+            if let Ok(Ok(stream)) = timeout(Duration::from_millis(200), tokio::net::TcpStream::connect(("127.0.0.1", silent))).await {
+                backlog.push(stream);
+            }
+        }
+        let scanner = PingScanner::tcp_only().with_fallback_ports(vec![silent]);
+        assert!(scanner.ping_host(Ipv4Addr::LOCALHOST.into(), 200).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_ping_through_slow_proxy() {
+        // A proxy slower to answer than the direct connects' share of the timeout
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut client, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut greeting = [0; 3];
+                    client.read_exact(&mut greeting).await.unwrap();
+                    sleep(Duration::from_millis(150)).await;
+                    client.write_all(&[5, 0]).await.unwrap();
+                    let mut request = [0; 10];
+                    client.read_exact(&mut request).await.unwrap();
+                    // Refused, which still marks the host alive
+                    client.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+                });
+            }
+        });
+
+        let proxy: Socks5Proxy = format!("socks5://{}", proxy_addr).parse().unwrap();
+        let scanner = PingScanner::tcp_only().with_fallback_ports(vec![22, 80, 443]).with_proxy(proxy);
+        let reply = scanner.ping_host(Ipv4Addr::new(192, 0, 2, 10).into(), 300).await.unwrap();
+        assert_eq!(reply.outcome, PingOutcome::TcpFallback(22));
+    }
+
+    #[tokio::test]
+    async fn test_ping_ipv6_localhost() {
+        let scanner = PingScanner::new().unwrap();
+        let localhost = Ipv6Addr::from_str("::1").unwrap();
+        let result = scanner.ping_host(localhost.into(), 1000).await;
+        assert_eq!(result.map(|reply| reply.outcome), Some(PingOutcome::Icmp));
+
+        let results = scanner.sweep(vec![localhost], 1000).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].0, results[0].1.outcome, results[0].1.ttl), (localhost, PingOutcome::Icmp, None));
+        assert!(results[0].1.rtt < Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_ttl_sniffer() {
+        // Raw sockets need root; nothing to check without them
+        let Ok(sniffer) = TcpTtlSniffer::start() else {
+            return;
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _stream = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(sniffer.ttl(IpAddr::V4(Ipv4Addr::LOCALHOST)), Some(64));
     }
 
     #[tokio::test]
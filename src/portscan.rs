@@ -1,327 +1,1805 @@
+use crate::budget::ProbeBudget;
+use crate::console::STATUS;
+use crate::data;
+use crate::events::{emit, EventSender, ScanEvent};
+use crate::network::scoped_socket_addr;
+use crate::probes::{CustomProbe, ProbeSet, ServiceProbe};
+use crate::services::service_name;
+use crate::socks::Socks5Proxy;
+use crate::timing::{HostRateLimit, Jitter, TimingProfile};
+use crate::tls::{inspect_certificate, TlsInfo};
+use crate::OpenPort;
 use anyhow::Result;
-use futures::future::join_all;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::net::{Ipv4Addr, SocketAddr};
+use futures::stream::{self, StreamExt};
+use indicatif::ProgressBar;
+use log::{debug, info};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Once};
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Interest};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
 use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
 use colored::*;
 
+/// How the banner read on an open port went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BannerStatus {
+    Success,
+    ReadTimeout,
+    ConnectionReset,
+    NoData,
+    /// Nothing was read: banners are off, the port's protocol isn't in
+    /// `--banner-protocols`, or the port isn't open
+    Skipped,
+}
+
+impl BannerStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BannerStatus::Success => "success",
+            BannerStatus::ReadTimeout => "read-timeout",
+            BannerStatus::ConnectionReset => "connection-reset",
+            BannerStatus::NoData => "no-data",
+            BannerStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// The built-in banner grabbers, chosen by port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BannerProtocol {
+    Http,
+    Tls,
+    Ftp,
+    Ssh,
+    Telnet,
+    Smtp,
+    Pop3,
+    Imap,
+    /// Ask a DNS server for its `version.bind`
+    Dns,
+    /// Parse the version from the server's greeting packet
+    Mysql,
+    /// Send a startup message and see how the server answers it
+    Postgresql,
+    /// `PING`, then `INFO server` for the version
+    Redis,
+    /// Offer only the SMBv1 dialect and see whether the server takes it
+    Smb,
+    /// Passively read whatever the service sends first
+    Generic,
+}
+
+impl BannerProtocol {
+    const ALL: [BannerProtocol; 14] = [
+        BannerProtocol::Http,
+        BannerProtocol::Tls,
+        BannerProtocol::Ftp,
+        BannerProtocol::Ssh,
+        BannerProtocol::Telnet,
+        BannerProtocol::Smtp,
+        BannerProtocol::Pop3,
+        BannerProtocol::Imap,
+        BannerProtocol::Dns,
+        BannerProtocol::Mysql,
+        BannerProtocol::Postgresql,
+        BannerProtocol::Redis,
+        BannerProtocol::Smb,
+        BannerProtocol::Generic,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BannerProtocol::Http => "http",
+            BannerProtocol::Tls => "tls",
+            BannerProtocol::Ftp => "ftp",
+            BannerProtocol::Ssh => "ssh",
+            BannerProtocol::Telnet => "telnet",
+            BannerProtocol::Smtp => "smtp",
+            BannerProtocol::Pop3 => "pop3",
+            BannerProtocol::Imap => "imap",
+            BannerProtocol::Dns => "dns",
+            BannerProtocol::Mysql => "mysql",
+            BannerProtocol::Postgresql => "postgresql",
+            BannerProtocol::Redis => "redis",
+            BannerProtocol::Smb => "smb",
+            BannerProtocol::Generic => "generic",
+        }
+    }
+
+    pub fn for_port(port: u16) -> Self {
+        match port {
+            // Common HTTP ports
+            80 | 8080 | 8000 | 8888 | 3000 | 5000 | 9000 | 8081 | 8082 | 8090 => BannerProtocol::Http,
+            // Common HTTPS ports, where a TLS handshake reads the certificate
+            443 | 8443 | 9443 | 4443 | 8444 => BannerProtocol::Tls,
+            // Standard service ports
+            21 => BannerProtocol::Ftp,
+            22 => BannerProtocol::Ssh,
+            23 => BannerProtocol::Telnet,
+            25 => BannerProtocol::Smtp,
+            // Additional common ports that might have banners
+            110 => BannerProtocol::Pop3,
+            143 => BannerProtocol::Imap,
+            53 => BannerProtocol::Dns,
+            // Databases
+            3306 => BannerProtocol::Mysql,
+            5432 => BannerProtocol::Postgresql,
+            6379 => BannerProtocol::Redis,
+            445 => BannerProtocol::Smb,
+            _ => BannerProtocol::Generic,
+        }
+    }
+}
+
+impl std::str::FromStr for BannerProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_ascii_lowercase();
+        let name = if name == "https" || name == "ssl" { "tls".to_string() } else { name };
+        BannerProtocol::ALL
+            .into_iter()
+            .find(|protocol| protocol.name() == name)
+            .ok_or_else(|| {
+                let known: Vec<&str> = BannerProtocol::ALL.iter().map(|protocol| protocol.name()).collect();
+                anyhow::anyhow!("unknown banner protocol '{}' (expected one of {})", s.trim(), known.join(", "))
+            })
+    }
+}
+
+/// Parse a comma-separated `--banner-protocols` list
+pub fn parse_banner_protocols(list: &str) -> Result<HashSet<BannerProtocol>> {
+    list.split(',')
+        .filter(|name| !name.trim().is_empty())
+        .map(|name| name.parse())
+        .collect()
+}
+
+/// A grabbed banner: the cleaned-up text plus the raw bytes it came from
+#[derive(Debug, Clone)]
+pub struct Banner {
+    pub text: String,
+    /// For TLS ports, the server certificate in DER form
+    pub raw: Vec<u8>,
+    pub status: BannerStatus,
+    pub tls: Option<TlsInfo>,
+    pub http: Option<HttpInfo>,
+    /// Service named by a matching `--probes` file entry
+    pub service: Option<String>,
+}
+
+impl Banner {
+    fn empty(status: BannerStatus) -> Self {
+        Banner { text: String::new(), raw: Vec::new(), status, tls: None, http: None, service: None }
+    }
+
+    /// The raw bytes as lowercase hex, for `--raw-banner`
+    pub fn raw_hex(&self) -> String {
+        self.raw.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// What a web server answered to `GET /`
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct HttpInfo {
+    /// Status code of the reply, e.g. 200 or 302
+    pub status: u16,
+    /// `Location` of a 3xx reply, as sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+}
+
+impl HttpInfo {
+    /// The status line and, for a redirect, the `Location` header of a
+    /// response's headers
+    fn parse(headers: &str) -> Option<Self> {
+        let mut lines = headers.lines();
+        let status: u16 = lines.next()?.strip_prefix("HTTP/")?.split_whitespace().nth(1)?.parse().ok()?;
+        let redirect = (300..400)
+            .contains(&status)
+            .then(|| {
+                lines.find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.trim().eq_ignore_ascii_case("location").then(|| value.trim().to_string())
+                })
+            })
+            .flatten()
+            .filter(|location| !location.is_empty());
+        Some(HttpInfo { status, redirect })
+    }
+}
+
+/// Longest console banner excerpt, in characters
+const BANNER_EXCERPT_CHARS: usize = 50;
+
+/// The first line of a banner cut to the console's width, as shown next to each open port
+pub fn banner_excerpt(text: &str) -> Option<String> {
+    text.lines().next().map(|line| line.chars().take(BANNER_EXCERPT_CHARS).collect())
+}
+
+/// How much of each banner the results keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BannerMode {
+    /// No banners at all
+    None,
+    /// The excerpt the console shows
+    Short,
+    #[default]
+    Full,
+}
+
+impl BannerMode {
+    /// `text` cut down to what this mode keeps
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            BannerMode::None => String::new(),
+            BannerMode::Short => banner_excerpt(text).unwrap_or_default(),
+            BannerMode::Full => text.to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for BannerMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(BannerMode::None),
+            "short" => Ok(BannerMode::Short),
+            "full" => Ok(BannerMode::Full),
+            other => Err(anyhow::anyhow!("unknown banner mode '{}' (expected none, short or full)", other)),
+        }
+    }
+}
+
+/// Transport a port was probed over
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+/// Which transports `scan_ports` covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanProtocol {
+    #[default]
+    Tcp,
+    Udp,
+    Both,
+}
+
+impl ScanProtocol {
+    pub fn includes(&self, protocol: Protocol) -> bool {
+        matches!(
+            (self, protocol),
+            (ScanProtocol::Both, _) | (ScanProtocol::Tcp, Protocol::Tcp) | (ScanProtocol::Udp, Protocol::Udp)
+        )
+    }
+}
+
+impl std::str::FromStr for ScanProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "tcp" => Ok(ScanProtocol::Tcp),
+            "udp" => Ok(ScanProtocol::Udp),
+            "both" => Ok(ScanProtocol::Both),
+            other => Err(anyhow::anyhow!("unknown scan protocol '{}' (expected tcp, udp or both)", other)),
+        }
+    }
+}
+
+/// What probing a port showed, in nmap's terms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    Open,
+    /// A UDP probe went unanswered: open, or a firewall dropped it
+    OpenFiltered,
+    /// The host refused: it is up, but nothing listens there
+    Closed,
+    /// A TCP connect got no answer or an ICMP unreachable, so a firewall is likely in the way
+    Filtered,
+}
+
+impl PortState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PortState::Open => "open",
+            PortState::OpenFiltered => "open|filtered",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+        }
+    }
+}
+
+/// UDP services worth probing when no UDP port list is given
+pub const DEFAULT_UDP_PORTS: &[u16] = &[53, 67, 69, 111, 123, 137, 161, 500, 514, 1900, 4500, 5353];
+
+/// DNS query for the TXT record `version.bind` in the CHAOS class, which
+/// BIND, Unbound, dnsmasq and others answer with their name and version
+const VERSION_BIND_QUERY: &[u8] = &[
+    0x56, 0x42, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    7, b'v', b'e', b'r', b's', b'i', b'o', b'n', 4, b'b', b'i', b'n', b'd', 0,
+    0x00, 0x10, 0x00, 0x03,
+];
+
+/// The `version.bind` text in a DNS reply; `None` when the server refused,
+/// had no answer or sent something that isn't a DNS response
+fn parse_version_bind(reply: &[u8]) -> Option<String> {
+    // Header: a response (QR set) with no error and at least one answer
+    if reply.len() < 12 || reply[2] & 0x80 == 0 || reply[3] & 0x0f != 0 || reply[6..8] == [0, 0] {
+        return None;
+    }
+    let skip_name = |mut at: usize| -> Option<usize> {
+        loop {
+            let length = *reply.get(at)? as usize;
+            match length {
+                0 => return Some(at + 1),
+                // Compression pointer: two bytes and the name ends
+                _ if length & 0xc0 == 0xc0 => return Some(at + 2),
+                _ => at += 1 + length,
+            }
+        }
+    };
+    // The question, then the first answer's name, type, class and TTL
+    let answer = skip_name(12)? + 4;
+    let rdata = skip_name(answer)? + 8;
+    let rdlength = u16::from_be_bytes([*reply.get(rdata)?, *reply.get(rdata + 1)?]) as usize;
+    let mut rdata = reply.get(rdata + 2..rdata + 2 + rdlength)?;
+
+    // TXT data is a run of length-prefixed strings
+    let mut text = String::new();
+    while let Some((&length, rest)) = rdata.split_first() {
+        let chunk = rest.get(..length as usize)?;
+        text.push_str(&String::from_utf8_lossy(chunk));
+        rdata = &rest[length as usize..];
+    }
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// The banner for a DNS reply: the server's version, or just "DNS service"
+/// when it won't say
+fn dns_banner_text(reply: &[u8]) -> String {
+    parse_version_bind(reply).unwrap_or_else(|| "DNS service".to_string())
+}
+
+pub const SNMP_PORT: u16 = 161;
+
+/// Community most devices ship with for read-only SNMP
+pub const DEFAULT_SNMP_COMMUNITY: &str = "public";
+
+/// sysDescr.0 (1.3.6.1.2.1.1.1.0), the device's own description: model,
+/// OS and firmware version on most switches and printers
+const OID_SYS_DESCR: &[u8] = &[0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00];
+
+/// A BER type-length-value, in the long length form once it passes 127 bytes
+fn ber(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let length = content.len();
+    match length {
+        0..=0x7f => encoded.push(length as u8),
+        0x80..=0xff => encoded.extend([0x81, length as u8]),
+        _ => encoded.extend([0x82, (length >> 8) as u8, length as u8]),
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+/// The first BER value in `data`: its tag, content and whatever follows it
+fn read_ber(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81 => (*rest.first()? as usize, rest.get(1..)?),
+        0x82 => (u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize, rest.get(2..)?),
+        _ => return None,
+    };
+    Some((tag, rest.get(..length)?, &rest[length..]))
+}
+
+/// Get-requests for sysDescr.0 as SNMPv1 and SNMPv2c, sent together so a
+/// device with either enabled answers; v3 needs credentials and isn't tried
+fn snmp_get_requests(community: &str) -> Vec<Vec<u8>> {
+    let varbinds = ber(0x30, &ber(0x30, &[ber(0x06, OID_SYS_DESCR), vec![0x05, 0x00]].concat()));
+    [(0u8, 0x1234_5678u32), (1, 0x1234_5679)]
+        .iter()
+        .map(|(version, request_id)| {
+            let pdu = ber(0xa0, &[ber(0x02, &request_id.to_be_bytes()), ber(0x02, &[0]), ber(0x02, &[0]), varbinds.clone()].concat());
+            ber(0x30, &[ber(0x02, &[*version]), ber(0x04, community.as_bytes()), pdu].concat())
+        })
+        .collect()
+}
+
+/// sysDescr from a v1 or v2c get-response; `None` for errors, missing
+/// objects and anything that isn't SNMP
+fn parse_snmp_sys_descr(reply: &[u8]) -> Option<String> {
+    let (0x30, message, _) = read_ber(reply)? else { return None };
+    let (0x02, [0 | 1], message) = read_ber(message)? else { return None };
+    let (0x04, _community, message) = read_ber(message)? else { return None };
+    let (0xa2, pdu, _) = read_ber(message)? else { return None };
+    let (0x02, _request_id, pdu) = read_ber(pdu)? else { return None };
+    let (0x02, error_status, pdu) = read_ber(pdu)? else { return None };
+    if error_status.iter().any(|&byte| byte != 0) {
+        return None;
+    }
+    let (0x02, _error_index, pdu) = read_ber(pdu)? else { return None };
+    let (0x30, varbinds, _) = read_ber(pdu)? else { return None };
+    let (0x30, varbind, _) = read_ber(varbinds)? else { return None };
+    let (0x06, _oid, varbind) = read_ber(varbind)? else { return None };
+    // An OCTET STRING; v2c answers noSuchObject (0x80) and friends instead
+    let (0x04, text, _) = read_ber(varbind)? else { return None };
+    let text = String::from_utf8_lossy(text).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// The banner for an SNMP reply: the device's sysDescr, or just "SNMP
+/// service" when it won't say
+fn snmp_banner_text(reply: &[u8]) -> String {
+    parse_snmp_sys_descr(reply).unwrap_or_else(|| "SNMP service".to_string())
+}
+
+/// A request that makes the service on `port` answer, since UDP services stay
+/// silent until they get something they understand
+fn udp_probe_payload(port: u16) -> &'static [u8] {
+    match port {
+        53 => VERSION_BIND_QUERY,
+        // mDNS: standard query for the root NS records
+        5353 => &[0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01],
+        // NTP: version 3 client request
+        123 => &[
+            0x1b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ],
+        // SSDP discovery
+        1900 => b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n\r\n",
+        _ => &[],
+    }
+}
+
+/// An open port found on a host, or a filtered one with `with_show_filtered`
+#[derive(Debug, Clone)]
+pub struct PortFinding {
+    pub port: u16,
+    pub protocol: Protocol,
+    pub state: PortState,
+    pub banner: Banner,
+    /// The banner text before `--banner-mode` cut it, for the risk checks only
+    pub full_text: String,
+    /// Probed only because a related service was open, not from the port list
+    pub correlated: bool,
+}
+
+impl PortFinding {
+    /// The finding as saved in the results, with the banner status and the
+    /// raw banner bytes only when asked for
+    pub fn into_open_port(self, record_status: bool, record_raw: bool) -> OpenPort {
+        OpenPort {
+            port: self.port,
+            protocol: self.protocol.name().to_string(),
+            state: self.state.name().to_string(),
+            banner_status: record_status.then(|| self.banner.status.name().to_string()),
+            raw_banner: (record_raw && !self.banner.raw.is_empty()).then(|| self.banner.raw_hex()),
+            banner: self.banner.text,
+            correlated: self.correlated,
+            tls: self.banner.tls,
+            http: self.banner.http,
+            service: self.banner.service.or_else(|| service_name(self.port, self.protocol).map(str::to_string)),
+        }
+    }
+}
+
+/// Services that usually travel together: when the first port is open the
+/// others are worth a look even if they weren't asked for
+const PORT_CORRELATIONS: &[(u16, &[u16])] = &[
+    (135, &[139, 445, 3389, 5985]), // Windows RPC
+    (139, &[135, 445]),
+    (445, &[135, 139, 3389]),
+    (3389, &[135, 445]),
+    (5985, &[5986]),                // WinRM
+    (88, &[389, 464, 636, 3268]),   // Kerberos, likely a domain controller
+    (389, &[88, 636, 3268]),
+    (80, &[443, 8080, 8443]),
+    (443, &[80, 8443]),
+    (111, &[2049]),                 // NFS
+    (2049, &[111]),
+    (3306, &[33060]),               // MySQL X protocol
+    (6379, &[26379]),               // Redis Sentinel
+    (9200, &[9300]),                // Elasticsearch transport
+];
+
+/// Ports correlated with the open ones that weren't already scanned
+pub fn correlated_ports(open: &[u16], scanned: &[u16]) -> Vec<u16> {
+    let scanned: HashSet<u16> = scanned.iter().copied().collect();
+    let extra: BTreeSet<u16> = PORT_CORRELATIONS
+        .iter()
+        .filter(|(port, _)| open.contains(port))
+        .flat_map(|(_, related)| related.iter().copied())
+        .filter(|port| !scanned.contains(port))
+        .collect();
+    extra.into_iter().collect()
+}
+
+/// How much of an HTTP response to read while looking for the page title
+const HTTP_RESPONSE_LIMIT: usize = 16 * 1024;
+
+/// Longest page title kept in a banner, in characters
+const MAX_TITLE_CHARS: usize = 80;
+
+/// Wait before the first connect retry; it doubles with each further retry
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// How long each knock waits for its connect; knock ports are normally filtered
+const KNOCK_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Pause between knocks, so the daemon sees them in order
+const KNOCK_DELAY: Duration = Duration::from_millis(100);
+
+/// What knocking did to the guarded port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KnockOutcome {
+    /// No guarded port to check
+    Sent,
+    Opened,
+    AlreadyOpen,
+    StillClosed,
+}
+
+/// How long banner grabbers wait for a reply unless `with_banner_timeout` says otherwise
+pub const DEFAULT_BANNER_TIMEOUT_MS: u64 = 1000;
+
+/// Most bytes a banner grabber reads unless `with_banner_bytes` says otherwise
+pub const DEFAULT_BANNER_BYTES: usize = 1024;
+
 pub struct PortScanner {
     timeout_duration: Duration,
+    /// Wait for a banner or probe reply once connected
+    banner_timeout: Duration,
+    /// Read size for banners and probe replies (HTTP has its own, larger limit)
+    banner_bytes: usize,
+    custom_probes: HashMap<u16, CustomProbe>,
+    probe_set: ProbeSet,
+    verbose: bool,
+    smart_ports: bool,
+    /// Built-in grabbers allowed to run; `None` allows all of them
+    banner_protocols: Option<HashSet<BannerProtocol>>,
+    budget: Arc<ProbeBudget>,
+    jitter: Jitter,
+    /// Shared by every host being scanned, each with its own bucket
+    host_rate: Arc<HostRateLimit>,
+    /// Drops the probes in flight and skips the rest when cancelled
+    cancel: CancellationToken,
+    /// Ports connected to in order before scanning a host
+    knock: Vec<u16>,
+    /// Port checked before and after knocking, to tell whether the knock worked
+    knock_guard: Option<u16>,
+    udp_ports: Vec<u16>,
+    udp_timeout: Duration,
+    /// Community string for the SNMP probe on UDP 161
+    snmp_community: String,
+    /// Interface index for connects to link-local IPv6 addresses
+    scope_id: Option<u32>,
+    /// Local port every TCP connect is made from
+    source_port: Option<u16>,
+    /// SOCKS5 proxy every TCP connect goes through
+    proxy: Option<Socks5Proxy>,
+    /// Read a banner from each open TCP port, rather than only connecting
+    grab_banners: bool,
+    /// How much of each banner the console, events and results get
+    banner_mode: BannerMode,
+    /// Extra connect attempts after a timeout before a port counts as closed
+    retries: u8,
+    /// Ports of one host probed at once
+    concurrency: usize,
+    /// Only announce hosts once a port turns out open
+    open_only: bool,
+    /// Report filtered TCP ports alongside the open ones
+    show_filtered: bool,
+    events: Option<EventSender>,
+    /// Advanced once per port probed
+    progress: ProgressBar,
 }
 
 impl PortScanner {
     pub fn new(timeout_ms: u64) -> Self {
         PortScanner {
             timeout_duration: Duration::from_millis(timeout_ms),
+            banner_timeout: Duration::from_millis(DEFAULT_BANNER_TIMEOUT_MS),
+            banner_bytes: DEFAULT_BANNER_BYTES,
+            custom_probes: HashMap::new(),
+            probe_set: ProbeSet::default(),
+            verbose: false,
+            smart_ports: false,
+            banner_protocols: None,
+            budget: Arc::new(ProbeBudget::default()),
+            jitter: Jitter::default(),
+            host_rate: Arc::default(),
+            cancel: CancellationToken::new(),
+            knock: Vec::new(),
+            knock_guard: None,
+            udp_ports: DEFAULT_UDP_PORTS.to_vec(),
+            udp_timeout: Duration::from_millis(2000),
+            snmp_community: DEFAULT_SNMP_COMMUNITY.to_string(),
+            scope_id: None,
+            source_port: None,
+            proxy: None,
+            grab_banners: true,
+            banner_mode: BannerMode::default(),
+            retries: 0,
+            concurrency: TimingProfile::default().port_concurrency,
+            open_only: false,
+            show_filtered: false,
+            events: None,
+            progress: ProgressBar::hidden(),
         }
     }
 
-    async fn check_port(&self, ip: Ipv4Addr, port: u16) -> Option<(u16, String)> {
-        let socket_addr = SocketAddr::from((ip, port));
-        
-        match timeout(self.timeout_duration, TcpStream::connect(socket_addr)).await {
-            Ok(Ok(mut stream)) => {
-                let banner = self.grab_banner(&mut stream, port).await;
-                Some((port, banner))
+    /// Ports and reply wait for UDP scans; UDP needs longer than a TCP connect
+    pub fn with_udp(mut self, ports: Vec<u16>, timeout_ms: u64) -> Self {
+        self.udp_ports = ports;
+        self.udp_timeout = Duration::from_millis(timeout_ms);
+        self
+    }
+
+    /// Community the SNMP probe asks with, instead of `public`
+    pub fn with_snmp_community(mut self, community: &str) -> Self {
+        self.snmp_community = community.to_string();
+        self
+    }
+
+    /// Wait up to `timeout_ms` for banners and probe replies; services that
+    /// speak unprompted get half of it
+    pub fn with_banner_timeout(mut self, timeout_ms: u64) -> Self {
+        self.banner_timeout = Duration::from_millis(timeout_ms);
+        self
+    }
+
+    /// Send `PortOpen` and `HostScanned` events to `events`
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Read up to `bytes` of each banner or probe reply
+    pub fn with_banner_bytes(mut self, bytes: usize) -> Self {
+        self.banner_bytes = bytes;
+        self
+    }
+
+    /// Count every connect against the scan's probe budget
+    pub fn with_budget(mut self, budget: Arc<ProbeBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Pause a random time of up to `jitter` before each connect and UDP probe
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Probe any one host at most `per_second` times a second, across all its
+    /// ports, retries and knocks
+    pub fn with_max_rate_per_host(mut self, per_second: u32) -> Self {
+        self.host_rate = Arc::new(HostRateLimit::new(per_second));
+        self
+    }
+
+    /// Stop scanning once `cancel` is cancelled
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Knock on `sequence` before scanning each host, and report whether that
+    /// opened `guard`
+    pub fn with_knock(mut self, sequence: Vec<u16>, guard: Option<u16>) -> Self {
+        self.knock = sequence;
+        self.knock_guard = guard;
+        self
+    }
+
+    /// Reach link-local IPv6 hosts through the interface with this index
+    pub fn with_scope_id(mut self, scope_id: Option<u32>) -> Self {
+        self.scope_id = scope_id;
+        self
+    }
+
+    /// Make every TCP connect from local port `source_port`, to test firewall
+    /// rules that trust traffic from ports such as 53 or 20
+    pub fn with_source_port(mut self, source_port: Option<u16>) -> Self {
+        self.source_port = source_port;
+        self
+    }
+
+    /// Make every TCP connect through a SOCKS5 proxy; banners are read over
+    /// the proxied stream as usual
+    pub fn with_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Only run these built-in banner grabbers; other open ports get no probe
+    pub fn with_banner_protocols(mut self, protocols: Option<HashSet<BannerProtocol>>) -> Self {
+        self.banner_protocols = protocols;
+        self
+    }
+
+    /// Retry connects that time out up to `retries` times, with exponential backoff
+    pub fn with_retries(mut self, retries: u8) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Probe at most this many ports of a host at once
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Count each probed port on `progress`; correlated follow-ups extend its length
+    pub fn with_progress(mut self, progress: ProgressBar) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Print a host's "is online" line only when it has an open port
+    pub fn with_open_only(mut self, open_only: bool) -> Self {
+        self.open_only = open_only;
+        self
+    }
+
+    /// Also report TCP ports that never answered, as filtered
+    pub fn with_show_filtered(mut self, show_filtered: bool) -> Self {
+        self.show_filtered = show_filtered;
+        self
+    }
+
+    /// Whether to read banners; without them a TCP port is done once it accepts
+    pub fn with_banners(mut self, grab_banners: bool) -> Self {
+        self.grab_banners = grab_banners;
+        self
+    }
+
+    /// Cut every banner's text to `mode` before it is printed, sent as an
+    /// event or recorded. The raw bytes are left alone for --raw-banner and
+    /// --banner-dump-dir, and risks are still read from the whole text.
+    pub fn with_banner_mode(mut self, mode: BannerMode) -> Self {
+        self.banner_mode = mode;
+        self
+    }
+
+    /// Follow up open ports with probes of their correlated ports
+    pub fn with_smart_ports(mut self, smart_ports: bool) -> Self {
+        self.smart_ports = smart_ports;
+        self
+    }
+
+    /// Also print how each banner grab went
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Use these probes instead of the built-in grabbers on their ports
+    pub fn with_custom_probes(mut self, probes: Vec<CustomProbe>) -> Self {
+        self.custom_probes = probes.into_iter().map(|probe| (probe.port, probe)).collect();
+        self
+    }
+
+    /// Try these service probes on their ports before the built-in grabbers
+    pub fn with_probe_set(mut self, probe_set: ProbeSet) -> Self {
+        self.probe_set = probe_set;
+        self
+    }
+
+    /// Whether the scan has been cancelled or has spent its probe budget, so
+    /// no further host should be started
+    pub(crate) fn stopped(&self) -> bool {
+        self.cancel.is_cancelled() || self.budget.exhausted()
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub(crate) fn open_only(&self) -> bool {
+        self.open_only
+    }
+
+    /// Probes `scan_ports` sends to one host for these TCP ports, before any
+    /// correlated follow-ups
+    pub fn probe_count(&self, ports: &[u16], protocol: ScanProtocol) -> usize {
+        let tcp = if protocol.includes(Protocol::Tcp) { ports.len() } else { 0 };
+        let udp = if protocol.includes(Protocol::Udp) { self.udp_ports.len() } else { 0 };
+        let guard_checks = if self.knock_guard.is_some() { 2 } else { 0 };
+        let knock = if self.knock.is_empty() { 0 } else { self.knock.len() + guard_checks };
+        tcp + udp + knock
+    }
+
+    /// Connect to `addr`, through the proxy or from the source port when one
+    /// is set. Connects to different ports can share the source port at once
+    /// thanks to `SO_REUSEADDR`; only a second connection to the same port
+    /// has to wait for the first to clear.
+    async fn connect(&self, addr: SocketAddr) -> std::io::Result<TcpStream> {
+        if let Some(proxy) = &self.proxy {
+            return proxy.connect(addr).await;
+        }
+        let Some(source_port) = self.source_port else {
+            return TcpStream::connect(addr).await;
+        };
+        let socket = bind_source_port(addr.is_ipv6(), source_port)?;
+        socket.connect(addr).await
+    }
+
+    /// A bare connect, with no banner read
+    async fn is_open(&self, ip: IpAddr, port: u16) -> bool {
+        if !self.budget.try_take() {
+            return false;
+        }
+        self.host_rate.wait(ip).await;
+        self.jitter.wait("port-jitter", ip, port.into()).await;
+        let socket_addr = scoped_socket_addr(ip, port, self.scope_id);
+        matches!(timeout(self.timeout_duration, self.connect(socket_addr)).await, Ok(Ok(_)))
+    }
+
+    /// Connect briefly to each knock port in order, then check the guarded
+    /// port against how it was before
+    async fn knock_host(&self, ip: IpAddr) -> KnockOutcome {
+        let was_open = match self.knock_guard {
+            Some(guard) => self.is_open(ip, guard).await,
+            None => false,
+        };
+        for (knock, &port) in self.knock.iter().enumerate() {
+            if !self.budget.try_take() {
+                break;
             }
-            _ => None,
+            self.host_rate.wait(ip).await;
+            // A sequence can knock the same port twice, so each knock draws its own delay
+            self.jitter.wait("knock-jitter", ip, knock as u64).await;
+            let socket_addr = scoped_socket_addr(ip, port, self.scope_id);
+            let accepted = matches!(timeout(KNOCK_TIMEOUT, self.connect(socket_addr)).await, Ok(Ok(_)));
+            debug!("{}: knocked ({})", socket_addr, if accepted { "accepted" } else { "no connection" });
+            tokio::time::sleep(KNOCK_DELAY).await;
+        }
+        match self.knock_guard {
+            None => KnockOutcome::Sent,
+            Some(_) if was_open => KnockOutcome::AlreadyOpen,
+            Some(guard) if self.is_open(ip, guard).await => KnockOutcome::Opened,
+            Some(_) => KnockOutcome::StillClosed,
         }
     }
 
-    async fn grab_banner(&self, stream: &mut TcpStream, port: u16) -> String {
-        match port {
-            // Common HTTP ports
-            80 | 8080 | 8000 | 8888 | 3000 | 5000 | 9000 | 8081 | 8082 | 8090 => {
-                self.grab_http_banner(stream, false).await
-            },
-            // Common HTTPS ports - use generic banner grabbing since TLS handshake is required
-            443 | 8443 | 9443 | 4443 | 8444 => {
-                self.grab_ssl_banner(stream).await
-            },
-            // Standard service ports
-            21 => self.grab_ftp_banner(stream).await,
-            22 => self.grab_ssh_banner(stream).await,
-            23 => self.grab_telnet_banner(stream).await,
-            25 => self.grab_smtp_banner(stream).await,
-            // Additional common ports that might have banners
-            110 => self.grab_pop3_banner(stream).await,
-            143 => self.grab_imap_banner(stream).await,
-            _ => self.grab_generic_banner(stream).await,
+    /// Send the port's UDP probe: a reply means open, an ICMP port unreachable
+    /// (seen as a refused receive) means closed, and silence is open|filtered
+    async fn check_udp_port(&self, ip: IpAddr, port: u16) -> Option<(u16, Banner, PortState)> {
+        if !self.budget.try_take() {
+            return None;
+        }
+        self.host_rate.wait(ip).await;
+        self.jitter.wait("udp-jitter", ip, port.into()).await;
+        let local: IpAddr = match ip {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0)).await.ok()?;
+        socket.connect(scoped_socket_addr(ip, port, self.scope_id)).await.ok()?;
+        if port == SNMP_PORT {
+            for request in snmp_get_requests(&self.snmp_community) {
+                socket.send(&request).await.ok()?;
+            }
+        } else {
+            socket.send(udp_probe_payload(port)).await.ok()?;
+        }
+
+        let mut buffer = vec![0; 2048];
+        match timeout(self.udp_timeout, recv_udp_reply(&socket, &mut buffer)).await {
+            Ok(Ok(bytes_read)) => {
+                buffer.truncate(bytes_read);
+                let text = match port {
+                    53 => dns_banner_text(&buffer),
+                    SNMP_PORT => snmp_banner_text(&buffer),
+                    _ => first_meaningful_line(&String::from_utf8_lossy(&buffer))
+                        .unwrap_or_else(|| format!("{}-byte reply", bytes_read)),
+                };
+                let banner = Banner { text, raw: buffer, status: BannerStatus::Success, tls: None, http: None, service: None };
+                Some((port, banner, PortState::Open))
+            }
+            Ok(Err(e)) => {
+                debug!("{}:{}/udp: closed ({})", ip, port, e);
+                Some((port, Banner::empty(BannerStatus::Skipped), PortState::Closed))
+            }
+            Err(_) => Some((port, Banner::empty(BannerStatus::ReadTimeout), PortState::OpenFiltered)),
+        }
+    }
+
+    /// Connect to the port: accepted means open, refused means closed, and
+    /// no answer after every retry means filtered. `None` only when the
+    /// probe budget ran out first.
+    async fn check_port(&self, ip: IpAddr, port: u16) -> Option<(u16, Banner, PortState)> {
+        let socket_addr = scoped_socket_addr(ip, port, self.scope_id);
+        let mut backoff = RETRY_BACKOFF;
+
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                debug!("{}: retrying connect (attempt {} of {}) after {:?}", socket_addr, attempt + 1, self.retries + 1, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            if !self.budget.try_take() {
+                return None;
+            }
+            self.host_rate.wait(ip).await;
+            self.jitter.wait("port-jitter", ip, u64::from(port) | u64::from(attempt) << 16).await;
+
+            match timeout(self.timeout_duration, self.connect(socket_addr)).await {
+                Ok(Ok(mut stream)) => {
+                    let banner = if self.grab_banners {
+                        self.grab_banner(&mut stream, socket_addr).await
+                    } else {
+                        Banner::empty(BannerStatus::Skipped)
+                    };
+                    debug!("{}: open, banner {}", socket_addr, banner.status.name());
+                    return Some((port, banner, PortState::Open));
+                }
+                // The source port couldn't be used this time, which says nothing about the port
+                Ok(Err(e)) if self.source_port.is_some() && is_source_port_error(&e) => {
+                    debug!("{}: cannot connect from source port: {}", socket_addr, e);
+                    return None;
+                }
+                // The host answered with a RST: the port is closed, retrying won't change that
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    return Some((port, Banner::empty(BannerStatus::Skipped), PortState::Closed));
+                }
+                // Timeouts and other failures may be packet loss or rate limiting
+                Ok(Err(e)) if self.retries > 0 => debug!("{}: connect failed: {}", socket_addr, e),
+                Err(_) if self.retries > 0 => debug!("{}: connect timed out", socket_addr),
+                _ => continue,
+            }
+        }
+        Some((port, Banner::empty(BannerStatus::Skipped), PortState::Filtered))
+    }
+
+    /// Whether a port in this state belongs in the results
+    fn reports(&self, state: PortState) -> bool {
+        match state {
+            PortState::Open | PortState::OpenFiltered => true,
+            PortState::Filtered => self.show_filtered,
+            PortState::Closed => false,
+        }
+    }
+
+    async fn grab_banner(&self, stream: &mut TcpStream, addr: SocketAddr) -> Banner {
+        let port = addr.port();
+        if let Some(probe) = self.custom_probes.get(&port) {
+            return self.run_custom_probe(stream, probe).await;
+        }
+
+        // Each service probe, and the built-in grabber after them, needs a fresh connection
+        let mut stream_used = false;
+        for probe in self.probe_set.for_port(port) {
+            if stream_used && !self.reconnect(stream, addr).await {
+                return Banner::empty(BannerStatus::ConnectionReset);
+            }
+            stream_used = true;
+            if let Some(banner) = self.run_service_probe(stream, probe).await {
+                return banner;
+            }
+        }
+        if stream_used && !self.reconnect(stream, addr).await {
+            return Banner::empty(BannerStatus::ConnectionReset);
+        }
+
+        let protocol = BannerProtocol::for_port(port);
+        if self.banner_protocols.as_ref().is_some_and(|allowed| !allowed.contains(&protocol)) {
+            return Banner::empty(BannerStatus::Skipped);
+        }
+
+        match protocol {
+            BannerProtocol::Http => self.grab_http_banner(stream).await,
+            BannerProtocol::Tls => self.grab_ssl_banner(stream).await,
+            BannerProtocol::Ftp => self.grab_ftp_banner(stream).await,
+            BannerProtocol::Ssh => self.grab_ssh_banner(stream).await,
+            BannerProtocol::Telnet => self.grab_telnet_banner(stream).await,
+            BannerProtocol::Smtp => self.grab_smtp_banner(stream).await,
+            BannerProtocol::Pop3 => self.grab_pop3_banner(stream).await,
+            BannerProtocol::Imap => self.grab_imap_banner(stream).await,
+            BannerProtocol::Dns => self.grab_dns_banner(stream).await,
+            BannerProtocol::Mysql => self.grab_mysql_banner(stream).await,
+            BannerProtocol::Postgresql => self.grab_postgresql_banner(stream).await,
+            BannerProtocol::Redis => self.grab_redis_banner(stream).await,
+            BannerProtocol::Smb => self.grab_smb_banner(stream).await,
+            BannerProtocol::Generic => self.grab_generic_banner(stream).await,
+        }
+    }
+
+    async fn run_custom_probe(&self, stream: &mut TcpStream, probe: &CustomProbe) -> Banner {
+        if !probe.payload.is_empty() && stream.write_all(&probe.payload).await.is_err() {
+            return Banner::empty(BannerStatus::ConnectionReset);
+        }
+
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        let response = String::from_utf8_lossy(&raw);
+        // Keep something readable when the reply doesn't match
+        let text = probe.extract(&response)
+            .or_else(|| first_meaningful_line(&response))
+            .unwrap_or_default();
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    /// The probe's banner if the reply matches its pattern
+    async fn run_service_probe(&self, stream: &mut TcpStream, probe: &ServiceProbe) -> Option<Banner> {
+        if !probe.payload.is_empty() && stream.write_all(&probe.payload).await.is_err() {
+            return None;
+        }
+
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        match probe.identify(&String::from_utf8_lossy(&raw)) {
+            Some(text) => Some(Banner { text, raw, status, tls: None, http: None, service: Some(probe.service.clone()) }),
+            None => {
+                if let Ok(addr) = stream.peer_addr() {
+                    debug!("{}: reply doesn't match the {} probe", addr, probe.service);
+                }
+                None
+            }
+        }
+    }
+
+    /// Replace `stream` with a new connection to `addr`; not the stream's
+    /// peer address, which is the proxy's when there is one
+    async fn reconnect(&self, stream: &mut TcpStream, addr: SocketAddr) -> bool {
+        if !self.budget.try_take() {
+            return false;
+        }
+        self.host_rate.wait(addr.ip()).await;
+        match timeout(self.timeout_duration, self.connect(addr)).await {
+            Ok(Ok(fresh)) => {
+                *stream = fresh;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    async fn grab_generic_banner(&self, stream: &mut TcpStream) -> Banner {
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout / 2).await;
+        let text = meaningful_text(&String::from_utf8_lossy(&raw)).unwrap_or_default();
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_ssl_banner(&self, stream: &mut TcpStream) -> Banner {
+        // Handshake to read the certificate; verification is off so
+        // self-signed and expired certificates still show up
+        match inspect_certificate(stream, self.banner_timeout).await {
+            Ok((info, der)) => Banner { text: info.summary(), raw: der, status: BannerStatus::Success, tls: Some(info), http: None, service: None },
+            Err(e) => {
+                let status = if e.is::<tokio::time::error::Elapsed>() {
+                    BannerStatus::ReadTimeout
+                } else {
+                    BannerStatus::NoData
+                };
+                Banner { text: format!("SSL/TLS service (handshake failed: {})", e), raw: Vec::new(), status, tls: None, http: None, service: None }
+            }
+        }
+    }
+
+    async fn grab_http_banner(&self, stream: &mut TcpStream) -> Banner {
+        let http_request = "GET / HTTP/1.0\r\nHost: localhost\r\n\r\n";
+        
+        if stream.write_all(http_request.as_bytes()).await.is_err() {
+            return Banner::empty(BannerStatus::ConnectionReset);
+        }
+
+        let (raw, status) = read_http_response(stream, HTTP_RESPONSE_LIMIT, self.banner_timeout).await;
+        if raw.is_empty() {
+            return Banner::empty(status);
+        }
+        let response = String::from_utf8_lossy(&raw);
+        
+        // Extract server header
+        let server = response.lines().find(|line| line.to_lowercase().starts_with("server:"));
+        // Fall back to the status line
+        let status_line = response.lines().next().filter(|line| line.starts_with("HTTP/"));
+
+        let mut text = match server.or(status_line) {
+            Some(line) => line.trim().to_string(),
+            None => "HTTP service detected".to_string(),
+        };
+        if let Some(title) = extract_html_title(&response) {
+            text = format!("{} | Title: {}", text, title);
+        }
+        // Only the headers; a page mentioning the header doesn't count
+        let headers = response.split("\r\n\r\n").next().unwrap_or_default();
+        if let Some(scheme) = headers.lines().find_map(basic_auth_challenge) {
+            text = format!("{} | Auth: {}", text, scheme);
+        }
+        let http = HttpInfo::parse(headers);
+        if let Some(location) = http.as_ref().and_then(|http| http.redirect.as_deref()) {
+            text = format!("{} | Redirect: {}", text, location);
+        }
+        Banner { text, raw, status, tls: None, http, service: None }
+    }
+
+    async fn grab_ftp_banner(&self, stream: &mut TcpStream) -> Banner {
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        let text = String::from_utf8_lossy(&raw).trim().replace(['\n', '\r'], " ");
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_telnet_banner(&self, stream: &mut TcpStream) -> Banner {
+        // Telnet often sends IAC sequences first, then a banner
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        if raw.is_empty() {
+            return Banner::empty(status);
+        }
+
+        // Filter out telnet control characters (IAC sequences)
+        let filtered: Vec<u8> = raw.iter()
+            .filter(|&&b| (32..=126).contains(&b) || b == b'\n' || b == b'\r')
+            .cloned()
+            .collect();
+        
+        let text = if !filtered.is_empty() {
+            String::from_utf8_lossy(&filtered)
+                .trim()
+                .replace(['\n', '\r'], " ")
+        } else {
+            "Telnet service".to_string()
+        };
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_ssh_banner(&self, stream: &mut TcpStream) -> Banner {
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        // Convert to string and find SSH version line
+        let data_str = String::from_utf8_lossy(&raw);
+        
+        // Look for SSH version and extract only the clean part
+        // Extract only printable characters up to first space after version
+        let text = data_str.lines()
+            .find(|line| line.starts_with("SSH-"))
+            .map(|ssh_line| ssh_line.chars().take_while(|c| c.is_ascii_graphic()).collect())
+            .unwrap_or_default();
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_smtp_banner(&self, stream: &mut TcpStream) -> Banner {
+        // Expect a "220" greeting, but keep whatever the server sent
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        let text = String::from_utf8_lossy(&raw).trim().replace(['\n', '\r'], " ");
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_pop3_banner(&self, stream: &mut TcpStream) -> Banner {
+        // Expect a "+OK" greeting, but keep whatever the server sent
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        let text = String::from_utf8_lossy(&raw).trim().replace(['\n', '\r'], " ");
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_imap_banner(&self, stream: &mut TcpStream) -> Banner {
+        // Expect a "* OK" greeting, but keep whatever the server sent
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        let text = String::from_utf8_lossy(&raw).trim().replace(['\n', '\r'], " ");
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_dns_banner(&self, stream: &mut TcpStream) -> Banner {
+        // DNS over TCP prefixes each message with its length
+        let mut query = (VERSION_BIND_QUERY.len() as u16).to_be_bytes().to_vec();
+        query.extend_from_slice(VERSION_BIND_QUERY);
+        if stream.write_all(&query).await.is_err() {
+            return Banner::empty(BannerStatus::ConnectionReset);
+        }
+
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        let text = if raw.len() > 2 { dns_banner_text(&raw[2..]) } else { String::new() };
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_mysql_banner(&self, stream: &mut TcpStream) -> Banner {
+        // The server speaks first with its greeting
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        if raw.is_empty() {
+            return Banner::empty(status);
+        }
+        let text = parse_mysql_greeting(&raw)
+            .or_else(|| first_meaningful_line(&String::from_utf8_lossy(&raw)))
+            .unwrap_or_default();
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_postgresql_banner(&self, stream: &mut TcpStream) -> Banner {
+        if stream.write_all(&postgresql_startup_message("angryether")).await.is_err() {
+            return Banner::empty(BannerStatus::ConnectionReset);
+        }
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        if raw.is_empty() {
+            return Banner::empty(status);
+        }
+        let text = parse_postgresql_reply(&raw)
+            .or_else(|| first_meaningful_line(&String::from_utf8_lossy(&raw)))
+            .unwrap_or_default();
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_redis_banner(&self, stream: &mut TcpStream) -> Banner {
+        if stream.write_all(b"PING\r\n").await.is_err() {
+            return Banner::empty(BannerStatus::ConnectionReset);
+        }
+        let (mut raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        if raw.is_empty() {
+            return Banner::empty(status);
+        }
+        let reply = String::from_utf8_lossy(&raw).trim().to_string();
+        let text = if reply.starts_with("+PONG") {
+            if stream.write_all(b"INFO server\r\n").await.is_err() {
+                return Banner { text: "Redis".to_string(), raw, status, tls: None, http: None, service: None };
+            }
+            let (info, _) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+            let version = redis_version(&String::from_utf8_lossy(&info));
+            raw.extend_from_slice(&info);
+            version.map_or_else(|| "Redis".to_string(), |version| format!("Redis {}", version))
+        } else if reply.starts_with("-NOAUTH") {
+            "Redis (authentication required)".to_string()
+        } else if let Some(error) = reply.strip_prefix('-') {
+            // e.g. protected mode refusing remote clients
+            format!("Redis: {}", error.lines().next().unwrap_or_default())
+        } else {
+            first_meaningful_line(&reply).unwrap_or_default()
+        };
+        Banner { text, raw, status, tls: None, http: None, service: None }
+    }
+
+    async fn grab_smb_banner(&self, stream: &mut TcpStream) -> Banner {
+        if stream.write_all(SMB1_NEGOTIATE).await.is_err() {
+            return Banner::empty(BannerStatus::ConnectionReset);
         }
+        let (raw, status) = read_response(stream, self.banner_bytes, self.banner_timeout).await;
+        // Servers with SMBv1 turned off often just drop the connection
+        let text = if raw.is_empty() && status == BannerStatus::ConnectionReset {
+            "SMB (SMBv1 refused)".to_string()
+        } else if raw.is_empty() {
+            return Banner::empty(status);
+        } else {
+            smb_dialect(&raw).to_string()
+        };
+        Banner { text, raw, status, tls: None, http: None, service: None }
     }
 
-    async fn grab_generic_banner(&self, stream: &mut TcpStream) -> String {
-        let mut buffer = [0; 1024];
-        
-        match timeout(Duration::from_millis(500), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                let data_str = String::from_utf8_lossy(&buffer[..bytes_read]);
-                
-                // Get first line that contains meaningful text
-                for line in data_str.lines() {
-                    let clean_line: String = line.chars()
-                        .filter(|c| c.is_ascii_graphic() || *c == ' ')
-                        .collect();
-                    
-                    if clean_line.trim().len() > 3 {  // Only return if meaningful content
-                        return clean_line.trim().to_string();
-                    }
+    /// Scan `ports` over TCP and/or the configured UDP ports, as `protocol` says.
+    ///
+    /// On cancellation the connects and banner reads in flight are dropped,
+    /// the remaining ports are skipped, and the open ports found so far are
+    /// returned.
+    pub async fn scan_ports(
+        &self,
+        ip: IpAddr,
+        ports: &[u16],
+        mac_address: Option<&str>,
+        protocol: ScanProtocol,
+    ) -> Vec<PortFinding> {
+        let mac_display = mac_address
+            .map(|mac| format!(" [MAC: {}]", mac))
+            .unwrap_or_default();
+        // Printed up front, or with open ports only just before the first one
+        let announce = Once::new();
+        let announce_host = || announce.call_once(|| info!(target: STATUS, "{} is online{}", ip.to_string().green(), mac_display.cyan()));
+        if !self.open_only {
+            announce_host();
+        }
+
+        if !self.knock.is_empty() {
+            if let Some(outcome) = self.cancel.run_until_cancelled(self.knock_host(ip)).await {
+                let sequence = self.knock.iter().map(u16::to_string).collect::<Vec<_>>().join(",");
+                let guard = self.knock_guard.unwrap_or_default();
+                let message = match outcome {
+                    KnockOutcome::Sent => format!("Knocked on {}", sequence),
+                    KnockOutcome::Opened => format!("Knock on {} opened port {}", sequence, guard),
+                    KnockOutcome::AlreadyOpen => format!("Knocked on {}, but port {} was already open", sequence, guard),
+                    KnockOutcome::StillClosed => format!("Knock on {} did not open port {}", sequence, guard),
+                };
+                // With --open-only, only a knock that worked is worth a line
+                if outcome == KnockOutcome::Opened {
+                    announce_host();
+                    info!(target: STATUS, "   {}", message.green());
+                } else if !self.open_only {
+                    info!(target: STATUS, "   {}", message.dimmed());
                 }
-                
-                String::new()
             }
-            _ => String::new(),
         }
-    }
 
-    async fn grab_ssl_banner(&self, stream: &mut TcpStream) -> String {
-        // For SSL/TLS ports, we can't do a simple HTTP request
-        // Instead, we'll attempt to detect if it's an SSL service
-        let mut buffer = [0; 512];
-        
-        // Try reading any initial data the server might send
-        match timeout(Duration::from_millis(500), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                let data = String::from_utf8_lossy(&buffer[..bytes_read]);
-                if !data.trim().is_empty() {
-                    return format!("SSL/TLS service - {}", data.trim().replace('\n', " ").replace('\r', " "));
+        let mut findings = Vec::new();
+
+        if protocol.includes(Protocol::Tcp) {
+            findings.extend(self.probe_ports(ip, ports, Protocol::Tcp, false, &announce_host).await);
+
+            if self.smart_ports {
+                let open: Vec<u16> = findings
+                    .iter()
+                    .filter(|finding| finding.state == PortState::Open)
+                    .map(|finding| finding.port)
+                    .collect();
+                let extra = correlated_ports(&open, ports);
+                if !extra.is_empty() {
+                    self.progress.inc_length(extra.len() as u64);
+                    findings.extend(self.probe_ports(ip, &extra, Protocol::Tcp, true, &announce_host).await);
                 }
             }
-            _ => {}
         }
-        
-        // If no initial banner, just indicate it's an SSL service
-        "SSL/TLS service".to_string()
+
+        if protocol.includes(Protocol::Udp) {
+            findings.extend(self.probe_ports(ip, &self.udp_ports, Protocol::Udp, false, &announce_host).await);
+        }
+
+        let open_ports = findings.iter().filter(|finding| finding.state != PortState::Filtered).count();
+        emit(&self.events, ScanEvent::HostScanned { ip, open_ports });
+        findings
     }
 
-    async fn grab_http_banner(&self, stream: &mut TcpStream, _is_https: bool) -> String {
-        let http_request = "GET / HTTP/1.0\r\nHost: localhost\r\n\r\n";
-        
-        if stream.write_all(http_request.as_bytes()).await.is_err() {
-            return String::new();
-        }
-
-        let mut buffer = [0; 2048];
-        match timeout(Duration::from_millis(1000), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                let response = String::from_utf8_lossy(&buffer[..bytes_read]);
-                
-                // Extract server header
-                for line in response.lines() {
-                    if line.to_lowercase().starts_with("server:") {
-                        return line.trim().to_string();
-                    }
-                }
-                
-                // Extract status line
-                if let Some(first_line) = response.lines().next() {
-                    if first_line.starts_with("HTTP/") {
-                        return first_line.trim().to_string();
-                    }
+    /// `announce_host` runs before the first port line is printed
+    async fn probe_ports(
+        &self,
+        ip: IpAddr,
+        ports: &[u16],
+        protocol: Protocol,
+        correlated: bool,
+        announce_host: &impl Fn(),
+    ) -> Vec<PortFinding> {
+        let scan_futures = ports.iter().map(|&port| async move {
+            let check = async {
+                match protocol {
+                    Protocol::Tcp => self.check_port(ip, port).await,
+                    Protocol::Udp => self.check_udp_port(ip, port).await,
                 }
-                
-                "HTTP service detected".to_string()
+            };
+            let result = self.cancel.run_until_cancelled(check).await.flatten();
+            self.progress.inc(1);
+            result
+        });
+
+        let results: Vec<_> = stream::iter(scan_futures).buffered(self.concurrency).collect().await;
+        let mut open_ports = Vec::new();
+
+        for (port, mut banner, state) in results.into_iter().flatten().filter(|(_, _, state)| self.reports(*state)) {
+            // Kept whole for risk detection; the console, events and results get the cut text
+            let full_text = banner.text.clone();
+            banner.text = self.banner_mode.apply(&full_text);
+            // The console gets a glimpse of whatever --banner-mode kept
+            let banner_display = match banner_excerpt(&banner.text) {
+                Some(excerpt) => format!(" [{}]", excerpt),
+                None => String::new(),
+            };
+            let status_display = if self.verbose {
+                format!(" ({})", banner.status.name())
+            } else {
+                String::new()
+            };
+            let correlated_display = if correlated { " (correlated)" } else { "" };
+            let port_display = match protocol {
+                Protocol::Tcp => port.to_string(),
+                Protocol::Udp => format!("{}/udp", port),
+            };
+            // A banner says more than the registered name, so the name only fills in for a silent port
+            let service_display = match service_name(port, protocol) {
+                Some(name) if banner.text.is_empty() => format!(" ({})", name),
+                _ => String::new(),
+            };
+            announce_host();
+            info!(
+                target: STATUS,
+                "   Port {}{} is {}{}{}{}",
+                port_display.cyan(),
+                service_display.dimmed(),
+                state.name(),
+                correlated_display.magenta(),
+                banner_display.yellow(),
+                status_display.dimmed()
+            );
+            if state != PortState::Filtered {
+                emit(&self.events, ScanEvent::PortOpen { ip, port, protocol, banner: banner.text.clone() });
             }
-            _ => String::new(),
+            open_ports.push(PortFinding { port, protocol, state, banner, full_text, correlated });
         }
+
+        open_ports
     }
+}
 
-    async fn grab_ftp_banner(&self, stream: &mut TcpStream) -> String {
-        let mut buffer = [0; 512];
-        
-        match timeout(Duration::from_millis(1000), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                let banner = String::from_utf8_lossy(&buffer[..bytes_read]);
-                if banner.starts_with("220") {
-                    return banner.trim().replace('\n', " ").replace('\r', " ");
-                }
-                banner.trim().replace('\n', " ").replace('\r', " ")
+/// Lines of printable text, stripped of control characters
+fn printable_lines(data: &str) -> impl Iterator<Item = String> + '_ {
+    data.lines().map(|line| {
+        line.chars()
+            .filter(|c| c.is_ascii_graphic() || *c == ' ')
+            .collect::<String>()
+            .trim()
+            .to_string()
+    })
+}
+
+/// "MySQL 8.0.36" or "MariaDB 10.11.6-MariaDB" from the greeting packet (a
+/// 4-byte header, protocol version 10, then the NUL-terminated server
+/// version), or the message of the error packet sent to refused clients
+fn parse_mysql_greeting(packet: &[u8]) -> Option<String> {
+    let payload = packet.get(4..)?;
+    match payload.first()? {
+        10 => {
+            let end = payload.iter().position(|&byte| byte == 0)?;
+            let version = std::str::from_utf8(&payload[1..end]).ok()?;
+            if version.contains("MariaDB") {
+                // MariaDB puts a fake "5.5.5-" in front for old clients
+                Some(format!("MariaDB {}", version.trim_start_matches("5.5.5-")))
+            } else {
+                Some(format!("MySQL {}", version))
             }
-            _ => String::new(),
         }
+        // Error packet: 0xff, a 2-byte code, then the message
+        0xff => {
+            let message = String::from_utf8_lossy(payload.get(3..)?);
+            Some(format!("MySQL: {}", message.trim()))
+        }
+        _ => None,
     }
+}
 
-    async fn grab_telnet_banner(&self, stream: &mut TcpStream) -> String {
-        let mut buffer = [0; 512];
-        
-        // Telnet often sends IAC sequences first, then a banner
-        match timeout(Duration::from_millis(1000), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                let raw_data = &buffer[..bytes_read];
-                
-                // Filter out telnet control characters (IAC sequences)
-                let filtered: Vec<u8> = raw_data.iter()
-                    .filter(|&&b| b >= 32 && b <= 126 || b == b'\n' || b == b'\r')
-                    .cloned()
-                    .collect();
-                
-                if !filtered.is_empty() {
-                    String::from_utf8_lossy(&filtered)
-                        .trim()
-                        .replace('\n', " ")
-                        .replace('\r', " ")
-                } else {
-                    "Telnet service".to_string()
-                }
+/// A protocol 3.0 startup message for `user`, which any PostgreSQL server answers
+fn postgresql_startup_message(user: &str) -> Vec<u8> {
+    let mut body = 196608u32.to_be_bytes().to_vec();
+    for value in ["user", user, "database", user] {
+        body.extend_from_slice(value.as_bytes());
+        body.push(0);
+    }
+    body.push(0);
+    let mut message = ((body.len() + 4) as u32).to_be_bytes().to_vec();
+    message.extend_from_slice(&body);
+    message
+}
+
+/// Make a banner of the server's answer to the startup message. Servers
+/// trusting us report `server_version` straight away; the rest ask for a
+/// password or send an error, which still tells PostgreSQL apart.
+fn parse_postgresql_reply(reply: &[u8]) -> Option<String> {
+    let mut rest = reply;
+    let mut banner = None;
+    // Each message is a type byte, a length that counts itself, then the body
+    while let [kind, a, b, c, d, ..] = *rest {
+        let length = u32::from_be_bytes([a, b, c, d]) as usize;
+        // A reply cut short by the read still counts for what arrived
+        let Some(body) = rest.get(5..1 + length) else {
+            break;
+        };
+        let fields: Vec<String> = body
+            .split(|&byte| byte == 0)
+            .map(|field| String::from_utf8_lossy(field).into_owned())
+            .collect();
+        match kind {
+            b'S' if fields.first().is_some_and(|name| name == "server_version") => {
+                return Some(format!("PostgreSQL {}", fields.get(1)?));
+            }
+            b'R' if body.get(..4).is_some_and(|code| code != [0, 0, 0, 0]) => {
+                return Some("PostgreSQL (authentication required)".to_string());
             }
-            _ => String::new(),
+            b'R' => banner = Some("PostgreSQL".to_string()),
+            // Error fields are a type letter followed by the text; M is the message
+            b'E' => {
+                let message = fields.iter().find_map(|field| field.strip_prefix('M'))?;
+                return Some(format!("PostgreSQL: {}", message));
+            }
+            _ => {}
         }
+        rest = &rest[1 + length..];
     }
+    banner
+}
 
-    async fn grab_ssh_banner(&self, stream: &mut TcpStream) -> String {
-        let mut buffer = [0; 256];
-        
-        match timeout(Duration::from_millis(1000), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                // Convert to string and find SSH version line
-                let data_str = String::from_utf8_lossy(&buffer[..bytes_read]);
-                
-                // Look for SSH version and extract only the clean part
-                if let Some(ssh_line) = data_str.lines().find(|line| line.starts_with("SSH-")) {
-                    // Extract only printable characters up to first space after version
-                    let clean_version: String = ssh_line.chars()
-                        .take_while(|c| c.is_ascii_graphic())
-                        .collect();
-                    
-                    if !clean_version.is_empty() {
-                        return clean_version;
-                    }
-                }
-                
-                String::new()
-            }
-            _ => String::new(),
+/// `redis_version` from an `INFO` reply
+fn redis_version(info: &str) -> Option<String> {
+    info.lines()
+        .find_map(|line| line.strip_prefix("redis_version:"))
+        .map(|version| version.trim().to_string())
+}
+
+/// An SMB1 Negotiate Protocol request over direct TCP (a 4-byte NetBIOS
+/// session header, the 32-byte SMB header, then the dialect list), offering
+/// only `NT LM 0.12`, the dialect every SMBv1 server speaks
+const SMB1_NEGOTIATE: &[u8] = &[
+    0x00, 0x00, 0x00, 0x2f,
+    0xff, b'S', b'M', b'B', 0x72, 0x00, 0x00, 0x00, 0x00, 0x18, 0x53, 0xc8,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xfe, 0xff, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x0c, 0x00, 0x02, b'N', b'T', b' ', b'L', b'M', b' ', b'0', b'.', b'1', b'2', 0x00,
+];
+
+/// What the reply to `SMB1_NEGOTIATE` says: an SMB1 reply choosing the
+/// dialect means SMBv1 is enabled, while an SMB2 reply or a refusal of the
+/// dialect means it isn't
+fn smb_dialect(reply: &[u8]) -> &'static str {
+    match reply.get(4..8) {
+        Some([0xff, b'S', b'M', b'B']) => {
+            let accepted = reply.get(8) == Some(&0x72)
+                && reply.get(9..13) == Some(&[0, 0, 0, 0])
+                && reply.get(36).is_some_and(|&words| words > 0)
+                && reply.get(37..39).is_some_and(|index| index != [0xff, 0xff]);
+            if accepted { "SMBv1 (NT LM 0.12)" } else { "SMB (SMBv1 refused)" }
         }
+        Some([0xfe, b'S', b'M', b'B']) => "SMB2+ (SMBv1 refused)",
+        _ => "SMB service",
     }
+}
 
-    async fn grab_smtp_banner(&self, stream: &mut TcpStream) -> String {
-        let mut buffer = [0; 512];
-        
-        match timeout(Duration::from_millis(1000), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                let banner = String::from_utf8_lossy(&buffer[..bytes_read]);
-                if banner.starts_with("220") {
-                    return banner.trim().replace('\n', " ").replace('\r', " ");
-                }
-                banner.trim().replace('\n', " ").replace('\r', " ")
+/// The `Basic realm="..."` of a `WWW-Authenticate: Basic` header line
+fn basic_auth_challenge(line: &str) -> Option<String> {
+    let (name, value) = line.split_once(':')?;
+    let value = value.trim();
+    (name.trim().eq_ignore_ascii_case("www-authenticate") && value.get(..5).is_some_and(|scheme| scheme.eq_ignore_ascii_case("basic")))
+        .then(|| value.to_string())
+}
+
+/// First line with meaningful printable text, stripped of control characters
+fn first_meaningful_line(data: &str) -> Option<String> {
+    printable_lines(data).find(|line| line.len() > 3) // Only return if meaningful content
+}
+
+/// All of the printable text, one line per non-blank line, if any line is meaningful
+fn meaningful_text(data: &str) -> Option<String> {
+    let lines: Vec<String> = printable_lines(data).filter(|line| !line.is_empty()).collect();
+    lines.iter().any(|line| line.len() > 3).then(|| lines.join("\n"))
+}
+
+/// Receive on a connected UDP socket, failing on an ICMP error.
+///
+/// A plain `recv` never wakes for the ICMP port unreachable the kernel
+/// queues as a socket error, so wait for error readiness as well.
+async fn recv_udp_reply(socket: &UdpSocket, buffer: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        let ready = socket.ready(Interest::READABLE | Interest::ERROR).await?;
+        if ready.is_error() {
+            if let Some(e) = socket.take_error()? {
+                return Err(e);
             }
-            _ => String::new(),
+        }
+        match socket.try_recv(buffer) {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            result => return result,
         }
     }
+}
 
-    async fn grab_pop3_banner(&self, stream: &mut TcpStream) -> String {
-        let mut buffer = [0; 512];
-        
-        match timeout(Duration::from_millis(1000), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                let banner = String::from_utf8_lossy(&buffer[..bytes_read]);
-                if banner.starts_with("+OK") {
-                    return banner.trim().replace('\n', " ").replace('\r', " ");
-                }
-                banner.trim().replace('\n', " ").replace('\r', " ")
-            }
-            _ => String::new(),
+/// Read whatever the peer sends within `wait`, up to `buffer_size` bytes
+async fn read_response(stream: &mut TcpStream, buffer_size: usize, wait: Duration) -> (Vec<u8>, BannerStatus) {
+    let mut buffer = vec![0; buffer_size];
+
+    match timeout(wait, stream.read(&mut buffer)).await {
+        // The peer closed the connection without sending anything
+        Ok(Ok(0)) => (Vec::new(), BannerStatus::NoData),
+        Ok(Ok(bytes_read)) => {
+            buffer.truncate(bytes_read);
+            (buffer, BannerStatus::Success)
         }
+        // Resets, aborts and any other read failure all mean the peer dropped us
+        Ok(Err(_)) => (Vec::new(), BannerStatus::ConnectionReset),
+        Err(_) => (Vec::new(), BannerStatus::ReadTimeout),
     }
+}
 
-    async fn grab_imap_banner(&self, stream: &mut TcpStream) -> String {
-        let mut buffer = [0; 512];
-        
-        match timeout(Duration::from_millis(1000), stream.read(&mut buffer)).await {
-            Ok(Ok(bytes_read)) if bytes_read > 0 => {
-                let banner = String::from_utf8_lossy(&buffer[..bytes_read]);
-                if banner.contains("* OK") {
-                    return banner.trim().replace('\n', " ").replace('\r', " ");
+/// Read an HTTP response until the page title is in, the peer closes,
+/// `limit` bytes have arrived or `wait` runs out
+async fn read_http_response(stream: &mut TcpStream, limit: usize, wait: Duration) -> (Vec<u8>, BannerStatus) {
+    let deadline = tokio::time::Instant::now() + wait;
+    let mut response = Vec::new();
+    let mut chunk = vec![0; 4096];
+
+    while response.len() < limit {
+        match tokio::time::timeout_at(deadline, stream.read(&mut chunk)).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(bytes_read)) => {
+                response.extend_from_slice(&chunk[..bytes_read]);
+                if response.to_ascii_lowercase().windows(8).any(|window| window == b"</title>") {
+                    break;
                 }
-                banner.trim().replace('\n', " ").replace('\r', " ")
             }
-            _ => String::new(),
+            // Keep whatever arrived before the reset or the deadline
+            Ok(Err(_)) if response.is_empty() => return (response, BannerStatus::ConnectionReset),
+            Err(_) if response.is_empty() => return (response, BannerStatus::ReadTimeout),
+            _ => break,
         }
     }
 
-    pub async fn scan_ports(&self, ip: Ipv4Addr, ports: &[u16], mac_address: Option<&str>) -> Vec<(u16, String)> {
-        let mac_display = mac_address
-            .map(|mac| format!(" [MAC: {}]", mac))
-            .unwrap_or_default();
-        println!("{} is online{}", ip.to_string().green(), mac_display.cyan());
+    response.truncate(limit);
+    let status = if response.is_empty() { BannerStatus::NoData } else { BannerStatus::Success };
+    (response, status)
+}
 
-        let scan_futures = ports.iter().map(|&port| async move {
-            self.check_port(ip, port).await
-        });
+/// The `<title>` of an HTML page with whitespace collapsed, capped at `MAX_TITLE_CHARS`
+fn extract_html_title(html: &str) -> Option<String> {
+    // ASCII lowercasing keeps byte offsets valid in the original text
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title>")?;
 
-        let results = join_all(scan_futures).await;
-        let mut open_ports = Vec::new();
+    let title = html[start..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        return None;
+    }
+    Some(title.chars().take(MAX_TITLE_CHARS).collect())
+}
 
-        for result in results {
-            if let Some((port, banner)) = result {
-                let banner_display = if banner.is_empty() {
-                    "".to_string()
-                } else {
-                    format!(" [{}]", banner.chars().take(50).collect::<String>())
-                };
-                println!("   Port {} is open{}", port.to_string().cyan(), banner_display.yellow());
-                open_ports.push((port, banner));
+/// Expand one port, `start-end` range, or a bare `-` for every port
+fn expand_port_token(token: &str) -> Result<Vec<u16>> {
+    if token.trim() == "-" {
+        return Ok((1..=u16::MAX).collect());
+    }
+    let parse = |s: &str| -> Result<u16> {
+        let s = s.trim();
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(anyhow::anyhow!("invalid port '{}'", s));
+        }
+        // Checked here rather than left to u16 parsing, for a clearer message
+        match s.parse::<u64>().ok().and_then(|port| u16::try_from(port).ok()) {
+            Some(0) => Err(anyhow::anyhow!("port 0 is not a valid port (expected 1-65535)")),
+            Some(port) => Ok(port),
+            None => Err(anyhow::anyhow!("port {} is out of range (expected 1-65535)", s)),
+        }
+    };
+    match token.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if start > end {
+                return Err(anyhow::anyhow!("invalid port range '{}' (start is after end)", token));
             }
+            Ok((start..=end).collect())
         }
+        None => Ok(vec![parse(token)?]),
+    }
+}
 
-        open_ports
+/// Ports and ranges in the order written; commas and whitespace both separate entries
+fn expand_port_list(list: &str) -> Result<Vec<u16>> {
+    let mut ports = Vec::new();
+    for token in list.split(|c: char| c == ',' || c.is_whitespace()).filter(|t| !t.is_empty()) {
+        ports.extend(expand_port_token(token)?);
     }
+    Ok(ports)
 }
 
-pub fn read_ports_from_file(file_path: &str) -> Result<Vec<u16>> {
-    // Try the provided path first
-    let path = std::path::Path::new(file_path);
-    
-    let file = if path.exists() {
-        File::open(file_path)?
+/// Parse a port list such as `22,80,8000-8100`, or `-` for all ports, into
+/// sorted ports without duplicates
+pub fn parse_port_spec(spec: &str) -> Result<Vec<u16>> {
+    let ports: BTreeSet<u16> = expand_port_list(spec)?.into_iter().collect();
+    if ports.is_empty() {
+        return Err(anyhow::anyhow!("no ports in '{}'", spec));
+    }
+    Ok(ports.into_iter().collect())
+}
+
+/// A TCP socket bound to `port` on the unspecified address, shareable with
+/// the other connects from it
+fn bind_source_port(ipv6: bool, port: u16) -> std::io::Result<TcpSocket> {
+    let (socket, local): (_, IpAddr) = if ipv6 {
+        (TcpSocket::new_v6()?, Ipv6Addr::UNSPECIFIED.into())
     } else {
-        // Try system-installed location as fallback
-        let system_path = find_system_ports_file(file_path)?;
-        File::open(system_path)?
+        (TcpSocket::new_v4()?, Ipv4Addr::UNSPECIFIED.into())
     };
-    
-    let reader = BufReader::new(file);
-    let content: String = reader.lines().collect::<Result<Vec<_>, _>>()?.join("");
-    
-    let ports: Result<Vec<u16>, _> = content
-        .split(',')
-        .map(|s| s.trim().parse::<u16>())
-        .collect();
-    
-    Ok(ports?)
-}
-
-fn find_system_ports_file(original_path: &str) -> Result<String> {
-    // Extract filename from original path
-    let filename = std::path::Path::new(original_path)
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("10000.txt");
-    
-    // Try system installation paths
-    let system_paths = [
-        format!("/usr/local/share/angryether/ports/{}", filename),
-        format!("/usr/share/angryether/ports/{}", filename),
-        format!("/opt/angryether/ports/{}", filename),
-    ];
-    
-    for path in &system_paths {
-        if std::path::Path::new(path).exists() {
-            println!("Using system ports file: {}", path);
-            return Ok(path.clone());
+    socket.set_reuseaddr(true)?;
+    socket.bind(SocketAddr::new(local, port))?;
+    Ok(socket)
+}
+
+/// Failures binding the source port, or of that port and address to
+/// this destination still being in use
+fn is_source_port_error(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(error.kind(), ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable | ErrorKind::PermissionDenied)
+}
+
+/// Check up front that connects can be made from `port`, so a scan doesn't
+/// quietly find nothing because every bind fails
+pub fn check_source_port(port: u16) -> Result<()> {
+    bind_source_port(false, port).map(drop).map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => {
+            anyhow::anyhow!("cannot bind source port {}: ports below 1024 need root or CAP_NET_BIND_SERVICE", port)
+        }
+        std::io::ErrorKind::AddrInUse => anyhow::anyhow!("cannot bind source port {}: a service is listening on it", port),
+        _ => anyhow::anyhow!("cannot bind source port {}: {}", port, e),
+    })
+}
+
+/// Parse a knock sequence such as `7000,8000,9000`, keeping its order and
+/// any repeated ports
+pub fn parse_knock_sequence(list: &str) -> Result<Vec<u16>> {
+    let ports = expand_port_list(list)?;
+    if ports.is_empty() {
+        return Err(anyhow::anyhow!("no ports in knock sequence '{}'", list));
+    }
+    Ok(ports)
+}
+
+/// Whether a `--ports` value reads as an inline port list rather than a file name
+pub fn is_port_spec(value: &str) -> bool {
+    value.chars().any(|c| c.is_ascii_digit() || c == '-')
+        && value.chars().all(|c| c.is_ascii_digit() || c == ',' || c == '-' || c.is_whitespace())
+}
+
+/// Parse a ports file in any mix of comma-, whitespace- or newline-separated
+/// ports and ranges, with `#` comments. Duplicates keep their first position.
+pub fn parse_ports(content: &str) -> Result<Vec<u16>> {
+    let mut seen = HashSet::new();
+    let mut ports = Vec::new();
+    let mut entry = 0;
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        for (offset, token) in port_tokens(line) {
+            entry += 1;
+            let token_ports = expand_port_token(token).map_err(|e| {
+                let column = line[..offset].chars().count() + 1;
+                anyhow::anyhow!("{} at entry {} (line {}, column {})", e, entry, number + 1, column)
+            })?;
+            ports.extend(token_ports.into_iter().filter(|port| seen.insert(*port)));
         }
     }
-    
-    // If no system file found, return error with helpful message
-    Err(anyhow::anyhow!(
-        "Ports file not found at '{}'. Tried system locations:\n{}",
-        original_path,
-        system_paths.join("\n")
-    ))
+    Ok(ports)
+}
+
+/// The non-empty entries of a ports file line with their byte offsets
+fn port_tokens(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split(|c: char| c == ',' || c.is_whitespace())
+        .scan(0, move |offset, token| {
+            let start = *offset;
+            let separator = line[start + token.len()..].chars().next().map_or(0, char::len_utf8);
+            *offset += token.len() + separator;
+            Some((start, token))
+        })
+        .filter(|(_, token)| !token.is_empty())
+}
+
+/// The default ports list, most common first, built into the binary so a
+/// scan works without the ports directory
+const BUILTIN_PORTS: &str = include_str!("../ports/10000.txt");
+
+/// Every port of the built-in list, most common first
+pub fn builtin_ports() -> Vec<u16> {
+    parse_ports(BUILTIN_PORTS).expect("built-in ports list parses")
+}
+
+/// The `count` most common TCP ports from the built-in list
+pub fn top_ports(count: usize) -> Result<Vec<u16>> {
+    let ports = builtin_ports();
+    if count == 0 || count > ports.len() {
+        return Err(anyhow::anyhow!("invalid top ports count {} (expected 1-{})", count, ports.len()));
+    }
+    Ok(ports[..count].to_vec())
+}
+
+pub fn read_ports_from_file(file_path: &str) -> Result<Vec<u16>> {
+    // Fall back to an installed copy of the same list when the path doesn't exist
+    let path = data::resolve_user_file(file_path, "ports")?;
+    if path != std::path::Path::new(file_path) {
+        info!(target: STATUS, "Using ports file: {}", path.display());
+    }
+    let ports = parse_ports(&fs::read_to_string(&path)?).map_err(|e| anyhow::anyhow!("{} in {}", e, path.display()))?;
+    if ports.is_empty() {
+        return Err(anyhow::anyhow!("no ports listed in {}", path.display()));
+    }
+    Ok(ports)
 }
 
 #[cfg(test)]
@@ -339,6 +1817,539 @@ mod tests {
         assert_eq!(ports, vec![80, 443, 22, 21]);
     }
 
+    #[test]
+    fn test_parse_ports_formats() {
+        assert_eq!(parse_ports("22\n80\r\n443\n").unwrap(), vec![22, 80, 443]);
+        assert_eq!(parse_ports("22 80\t443").unwrap(), vec![22, 80, 443]);
+        assert_eq!(parse_ports("20-23,80").unwrap(), vec![20, 21, 22, 23, 80]);
+        assert_eq!(
+            parse_ports("# web\n80, 443  # tls too\n\n8000-8002\n443\n").unwrap(),
+            vec![80, 443, 8000, 8001, 8002]
+        );
+        assert!(parse_ports("# nothing here\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_ports_errors() {
+        let error = |content: &str| parse_ports(content).unwrap_err().to_string();
+        assert_eq!(error("80\nhttp\n"), "invalid port 'http' at entry 2 (line 2, column 1)");
+        assert_eq!(error("22, 80 443,\n8080,  70000"), "port 70000 is out of range (expected 1-65535) at entry 5 (line 2, column 8)");
+        assert_eq!(error("0"), "port 0 is not a valid port (expected 1-65535) at entry 1 (line 1, column 1)");
+        assert!(error("99999999999999999999999").contains("out of range"));
+        assert!(parse_ports("1024-1").is_err());
+        assert!(parse_ports("80-").is_err());
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "80,https").unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        assert!(read_ports_from_file(path).unwrap_err().to_string().ends_with(&format!("column 4) in {}", path)));
+    }
+
+    #[test]
+    fn test_top_ports() {
+        assert_eq!(top_ports(3).unwrap(), vec![80, 23, 443]);
+        assert_eq!(top_ports(1000).unwrap().len(), 1000);
+        assert!(top_ports(0).is_err());
+        assert!(top_ports(builtin_ports().len() + 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_port_spec() {
+        assert_eq!(parse_port_spec("443,22,80-82,81").unwrap(), vec![22, 80, 81, 82, 443]);
+        assert_eq!(parse_port_spec("-").unwrap().len(), 65535);
+        assert!(parse_port_spec("100-50").unwrap_err().to_string().contains("start is after end"));
+        assert!(parse_port_spec("0,22").is_err());
+        assert!(parse_port_spec(",").is_err());
+
+        assert!(is_port_spec("22,80,8000-8100") && is_port_spec("443") && is_port_spec("-"));
+        assert!(!is_port_spec("ports/10000.txt") && !is_port_spec("top-100.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_read_response_status() {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            // Greet, close silently, then hold a connection open without talking
+            let (mut greeter, _) = listener.accept().await.unwrap();
+            greeter.write_all(b"220 ready\r\n").await.unwrap();
+            let (closer, _) = listener.accept().await.unwrap();
+            drop(closer);
+            let (silent, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop((greeter, silent));
+        });
+
+        let wait = Duration::from_millis(100);
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let (raw, status) = read_response(&mut stream, 512, wait).await;
+        assert_eq!((raw.as_slice(), status), (&b"220 ready\r\n"[..], BannerStatus::Success));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(read_response(&mut stream, 512, wait).await.1, BannerStatus::NoData);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(read_response(&mut stream, 512, wait).await.1, BannerStatus::ReadTimeout);
+
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_parse_banner_protocols() {
+        let protocols = parse_banner_protocols("http, SSH,https").unwrap();
+        assert_eq!(
+            protocols,
+            HashSet::from([BannerProtocol::Http, BannerProtocol::Ssh, BannerProtocol::Tls])
+        );
+        assert!(parse_banner_protocols("http,mssql").is_err());
+        assert_eq!(BannerProtocol::for_port(8443), BannerProtocol::Tls);
+        assert_eq!(BannerProtocol::for_port(3306), BannerProtocol::Mysql);
+        assert_eq!(BannerProtocol::for_port(1433), BannerProtocol::Generic);
+        assert_eq!(BannerProtocol::for_port(445), BannerProtocol::Smb);
+    }
+
+    #[test]
+    fn test_banner_mode() {
+        let banner = "HTTP/1.1 200 OK | Server: a-very-long-server-name-that-goes-on-and-on/1.0\nsecond line";
+        assert_eq!("FULL".parse::<BannerMode>().unwrap().apply(banner), banner);
+        assert_eq!(BannerMode::Short.apply(banner), "HTTP/1.1 200 OK | Server: a-very-long-server-name-");
+        assert_eq!(BannerMode::None.apply(banner), "");
+        assert_eq!(BannerMode::Short.apply(""), "");
+        assert!("brief".parse::<BannerMode>().is_err());
+    }
+
+    #[test]
+    fn test_smb_dialect() {
+        assert_eq!(SMB1_NEGOTIATE.len(), 4 + 0x2f);
+        // Negotiate response: status 0, 17 words, dialect 0 chosen
+        let mut reply = vec![0x00, 0x00, 0x00, 0x55, 0xff, b'S', b'M', b'B', 0x72, 0, 0, 0, 0];
+        reply.resize(36, 0);
+        reply.extend_from_slice(&[17, 0x00, 0x00]);
+        assert_eq!(smb_dialect(&reply), "SMBv1 (NT LM 0.12)");
+        // No dialect acceptable
+        reply[37..39].copy_from_slice(&[0xff, 0xff]);
+        assert_eq!(smb_dialect(&reply), "SMB (SMBv1 refused)");
+        assert_eq!(smb_dialect(&[0x00, 0x00, 0x00, 0x41, 0xfe, b'S', b'M', b'B', 0x40]), "SMB2+ (SMBv1 refused)");
+        assert_eq!(smb_dialect(b"garbage"), "SMB service");
+
+        assert_eq!(basic_auth_challenge("WWW-Authenticate: Basic realm=\"router\"").as_deref(), Some("Basic realm=\"router\""));
+        assert_eq!(basic_auth_challenge("www-authenticate: Digest realm=\"x\""), None);
+    }
+
+    #[test]
+    fn test_parse_version_bind() {
+        // A BIND reply: the question echoed, then a TXT answer named by a pointer to it
+        let mut reply = VERSION_BIND_QUERY.to_vec();
+        reply[2] = 0x85;
+        reply[7] = 1;
+        reply.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x10, 0x00, 0x03, 0, 0, 0, 0, 0x00, 0x0e]);
+        reply.extend_from_slice(&[0x06, b'B', b'I', b'N', b'D', b' ', b'9', 0x06, b'.', b'1', b'6', b'.', b'1', b' ']);
+        assert_eq!(parse_version_bind(&reply).as_deref(), Some("BIND 9.16.1"));
+
+        // REFUSED, a truncated answer and the query itself all fall back
+        let mut refused = VERSION_BIND_QUERY.to_vec();
+        refused[2] = 0x81;
+        refused[3] = 0x05;
+        assert_eq!(dns_banner_text(&refused), "DNS service");
+        assert_eq!(parse_version_bind(&reply[..reply.len() - 3]), None);
+        assert_eq!(parse_version_bind(VERSION_BIND_QUERY), None);
+        assert_eq!(BannerProtocol::for_port(53), BannerProtocol::Dns);
+    }
+
+    #[test]
+    fn test_snmp_sys_descr() {
+        let requests = snmp_get_requests(DEFAULT_SNMP_COMMUNITY);
+        assert_eq!(
+            requests[0],
+            vec![
+                0x30, 0x29, 0x02, 0x01, 0x00, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0, 0x1c, 0x02,
+                0x04, 0x12, 0x34, 0x56, 0x78, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30, 0x0c, 0x06,
+                0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+            ]
+        );
+        assert_eq!(requests[1][4], 1);
+        // A long community needs the long length form
+        let long = "c".repeat(200);
+        assert_eq!(read_ber(&snmp_get_requests(&long)[1]).map(|(tag, _, rest)| (tag, rest.len())), Some((0x30, 0)));
+
+        let response = |version: u8, error: u8, value: Vec<u8>| {
+            let varbinds = ber(0x30, &ber(0x30, &[ber(0x06, OID_SYS_DESCR), value].concat()));
+            let pdu = ber(0xa2, &[ber(0x02, &[0x01]), ber(0x02, &[error]), ber(0x02, &[0]), varbinds].concat());
+            ber(0x30, &[ber(0x02, &[version]), ber(0x04, b"public"), pdu].concat())
+        };
+        let description = b"HP ETHERNET MULTI-ENVIRONMENT,ROM none,JETDIRECT,JD153\r\n";
+        assert_eq!(
+            parse_snmp_sys_descr(&response(0, 0, ber(0x04, description))).as_deref(),
+            Some("HP ETHERNET MULTI-ENVIRONMENT,ROM none,JETDIRECT,JD153")
+        );
+        assert!(parse_snmp_sys_descr(&response(1, 0, ber(0x04, b"Linux edge 6.1.0"))).is_some());
+        // noSuchName, a v2c noSuchObject and a v3 message say nothing
+        assert_eq!(snmp_banner_text(&response(0, 2, vec![0x05, 0x00])), "SNMP service");
+        assert_eq!(parse_snmp_sys_descr(&response(1, 0, vec![0x80, 0x00])), None);
+        assert_eq!(parse_snmp_sys_descr(&response(3, 0, ber(0x04, description))), None);
+        assert_eq!(parse_snmp_sys_descr(&requests[0]), None);
+    }
+
+    #[test]
+    fn test_database_banners() {
+        let mut greeting = vec![0x4a, 0, 0, 0, 10];
+        greeting.extend_from_slice(b"8.0.36-0ubuntu0.22.04.1\0\x08\0\0\0");
+        assert_eq!(parse_mysql_greeting(&greeting).as_deref(), Some("MySQL 8.0.36-0ubuntu0.22.04.1"));
+        let mut greeting = vec![0x4a, 0, 0, 0, 10];
+        greeting.extend_from_slice(b"5.5.5-10.11.6-MariaDB\0");
+        assert_eq!(parse_mysql_greeting(&greeting).as_deref(), Some("MariaDB 10.11.6-MariaDB"));
+        let mut refused = vec![0x40, 0, 0, 0, 0xff, 0x6a, 0x04];
+        refused.extend_from_slice(b"Host '192.0.2.5' is not allowed to connect to this MySQL server");
+        assert_eq!(
+            parse_mysql_greeting(&refused).as_deref(),
+            Some("MySQL: Host '192.0.2.5' is not allowed to connect to this MySQL server")
+        );
+        assert_eq!(parse_mysql_greeting(b"SSH-2.0-OpenSSH"), None);
+
+        // Trust authentication: AuthenticationOk, then the server's parameters
+        let mut trusted = vec![b'R', 0, 0, 0, 8, 0, 0, 0, 0];
+        trusted.extend_from_slice(b"S\0\0\0\x19client_encoding\0UTF8\0");
+        trusted.extend_from_slice(b"S\0\0\0\x18server_version\x0016.2\0");
+        assert_eq!(parse_postgresql_reply(&trusted).as_deref(), Some("PostgreSQL 16.2"));
+        // MD5 password request
+        assert_eq!(
+            parse_postgresql_reply(&[b'R', 0, 0, 0, 12, 0, 0, 0, 5, 1, 2, 3, 4]).as_deref(),
+            Some("PostgreSQL (authentication required)")
+        );
+        let mut error = vec![b'E', 0, 0, 0, 0x2c];
+        error.extend_from_slice(b"SFATAL\0C28000\0Mno pg_hba.conf entry\0\0");
+        error[4] = (error.len() - 1) as u8;
+        assert_eq!(parse_postgresql_reply(&error).as_deref(), Some("PostgreSQL: no pg_hba.conf entry"));
+        let startup = postgresql_startup_message("angryether");
+        assert_eq!(startup[..4], (startup.len() as u32).to_be_bytes());
+
+        assert_eq!(redis_version("$1200\r\n# Server\r\nredis_version:7.2.4\r\nredis_git_sha1:0\r\n").as_deref(), Some("7.2.4"));
+    }
+
+    #[tokio::test]
+    async fn test_scan_ports_cancelled() {
+        // Accepts but never sends a banner, so the grab waits out its timeout
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let _held = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+
+        let cancel = CancellationToken::new();
+        let scanner = PortScanner::new(1000).with_banner_timeout(5000).with_cancellation(cancel.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+        let started = std::time::Instant::now();
+        let findings = scanner.scan_ports(Ipv4Addr::LOCALHOST.into(), &[port], None, ScanProtocol::Tcp).await;
+        assert!(findings.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_knock_opens_guarded_port() {
+        let first = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let guard = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let knock = vec![first.local_addr().unwrap().port(), second.local_addr().unwrap().port()];
+        let guard_addr = guard.local_addr().unwrap();
+        drop(guard);
+        // A tiny knock daemon: the guarded port opens once both knocks came in order
+        tokio::spawn(async move {
+            first.accept().await.unwrap();
+            second.accept().await.unwrap();
+            let guard = tokio::net::TcpListener::bind(guard_addr).await.unwrap();
+            loop {
+                let _ = guard.accept().await;
+            }
+        });
+
+        let scanner = PortScanner::new(500).with_knock(knock.clone(), Some(guard_addr.port()));
+        assert_eq!(scanner.knock_host(Ipv4Addr::LOCALHOST.into()).await, KnockOutcome::Opened);
+        assert_eq!(scanner.knock_host(Ipv4Addr::LOCALHOST.into()).await, KnockOutcome::AlreadyOpen);
+        assert_eq!(scanner.probe_count(&[22, 80], ScanProtocol::Tcp), 2 + knock.len() + 2);
+
+        assert_eq!(parse_knock_sequence("7000,8000 7000").unwrap(), vec![7000, 8000, 7000]);
+        assert!(parse_knock_sequence(",").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_source_port() {
+        let source_port = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let first = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ports = [first.local_addr().unwrap().port(), second.local_addr().unwrap().port()];
+        let peers = tokio::spawn(async move {
+            let (_, first_peer) = first.accept().await.unwrap();
+            let (_, second_peer) = second.accept().await.unwrap();
+            (first_peer.port(), second_peer.port())
+        });
+
+        check_source_port(source_port).unwrap();
+        // Both connects use the source port at the same time
+        let scanner = PortScanner::new(1000).with_banners(false).with_concurrency(2).with_source_port(Some(source_port));
+        let findings = scanner.scan_ports(Ipv4Addr::LOCALHOST.into(), &ports, None, ScanProtocol::Tcp).await;
+        assert_eq!(findings.len(), 2);
+        assert_eq!(peers.await.unwrap(), (source_port, source_port));
+
+        let listening = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let error = check_source_port(listening.local_addr().unwrap().port()).unwrap_err();
+        assert!(error.to_string().contains("a service is listening on it"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_redis_banner() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 64];
+            let _ = stream.read(&mut request).await;
+            stream.write_all(b"+PONG\r\n").await.unwrap();
+            let _ = stream.read(&mut request).await;
+            stream.write_all(b"$40\r\n# Server\r\nredis_version:7.2.4\r\n").await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let banner = PortScanner::new(1000).grab_redis_banner(&mut stream).await;
+        assert_eq!(banner.text, "Redis 7.2.4");
+    }
+
+    #[test]
+    fn test_correlated_ports() {
+        assert_eq!(correlated_ports(&[445], &[22, 445]), vec![135, 139, 3389]);
+        // Already-scanned ports and overlapping correlations aren't repeated
+        assert_eq!(correlated_ports(&[445, 3389], &[135, 445, 3389]), vec![139]);
+        assert!(correlated_ports(&[22], &[22]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_udp_port() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buffer = [0; 64];
+            let (_, peer) = server.recv_from(&mut buffer).await.unwrap();
+            server.send_to(b"pong from test service", peer).await.unwrap();
+        });
+        let scanner = PortScanner::new(1000).with_udp(vec![port], 500);
+
+        let (_, banner, state) = scanner.check_udp_port(Ipv4Addr::LOCALHOST.into(), port).await.unwrap();
+        assert_eq!((state, banner.text.as_str()), (PortState::Open, "pong from test service"));
+
+        // Nothing listens any more, so the kernel answers with port unreachable
+        let closed = UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let (_, _, state) = scanner.check_udp_port(Ipv4Addr::LOCALHOST.into(), closed).await.unwrap();
+        assert_eq!(state, PortState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_http_banner_title() {
+        assert_eq!(extract_html_title("<html><TITLE lang=\"en\">\n  Router   Admin\n</Title>").as_deref(), Some("Router Admin"));
+        assert_eq!(extract_html_title("<title>   </title>"), None);
+        assert_eq!(extract_html_title("<title>no end"), None);
+        assert_eq!(extract_html_title(&format!("<title>{}</title>", "é".repeat(200))).unwrap().chars().count(), MAX_TITLE_CHARS);
+
+        // Headers and body arrive in separate segments
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 512];
+            let _ = stream.read(&mut request).await;
+            stream.write_all(b"HTTP/1.0 200 OK\r\nServer: nginx\r\n\r\n").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            stream.write_all(b"<html><head><title>Router Admin</title></head>").await.unwrap();
+            // Hold the connection open: the title alone should end the read
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let banner = PortScanner::new(1000).grab_http_banner(&mut stream).await;
+        assert_eq!(banner.text, "Server: nginx | Title: Router Admin");
+        assert_eq!(banner.http, Some(HttpInfo { status: 200, redirect: None }));
+    }
+
+    #[test]
+    fn test_http_redirect() {
+        let headers = "HTTP/1.1 302 Found\r\nServer: lighttpd\r\nlocation: http://portal.example.net/login\r\n";
+        let info = HttpInfo::parse(headers).unwrap();
+        assert_eq!((info.status, info.redirect.as_deref()), (302, Some("http://portal.example.net/login")));
+        // Only a 3xx redirects, whatever headers come with it
+        assert_eq!(HttpInfo::parse("HTTP/1.0 200 OK\r\nLocation: /elsewhere").unwrap().redirect, None);
+        assert_eq!(HttpInfo::parse("SSH-2.0-OpenSSH_9.6"), None);
+    }
+
+    #[tokio::test]
+    async fn test_banner_mode_reaches_every_sink() {
+        let banner = "SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5 with a long comment";
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let _ = stream.write_all(format!("{}\r\n", banner).as_bytes()).await;
+            }
+        });
+
+        for (mode, expected) in [(BannerMode::None, ""), (BannerMode::Short, &banner[..BANNER_EXCERPT_CHARS])] {
+            let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+            let scanner = PortScanner::new(1000).with_banner_mode(mode).with_events(events);
+            let mut findings = scanner.scan_ports(Ipv4Addr::LOCALHOST.into(), &[port], None, ScanProtocol::Tcp).await;
+            let finding = findings.pop().unwrap();
+            assert_eq!(finding.full_text, banner);
+
+            let mut event_banners = Vec::new();
+            while let Ok(event) = received.try_recv() {
+                if let ScanEvent::PortOpen { banner, .. } = event {
+                    event_banners.push(banner);
+                }
+            }
+            assert_eq!(event_banners, [expected]);
+
+            let file = NamedTempFile::new().unwrap();
+            let mut writer = crate::output::JsonlWriter::create(file.path().to_str().unwrap()).unwrap();
+            let host = crate::HostResult {
+                ip: "127.0.0.1".to_string(),
+                discovery_method: "TCP".to_string(),
+                open_ports: vec![finding.into_open_port(false, true)],
+                ..Default::default()
+            };
+            writer.write_host(&host).unwrap();
+            let line: crate::HostResult = serde_json::from_str(&fs::read_to_string(file.path()).unwrap()).unwrap();
+            assert_eq!(line.open_ports[0].banner, expected);
+            // --banner-mode cuts the text, not the bytes --raw-banner asked for
+            let raw = Banner { raw: format!("{}\r\n", banner).into_bytes(), ..Banner::empty(BannerStatus::Success) };
+            assert_eq!(line.open_ports[0].raw_banner, Some(raw.raw_hex()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_port_without_banners() {
+        // A silent service would hold a banner grab until its read times out
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let scanner = PortScanner::new(1000).with_banners(false);
+
+        let started = std::time::Instant::now();
+        let (_, banner, state) = scanner.check_port(Ipv4Addr::LOCALHOST.into(), port).await.unwrap();
+        assert_eq!((state, banner.status, banner.text.as_str()), (PortState::Open, BannerStatus::Skipped, ""));
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_banner_timeout() {
+        // Accepts and never speaks, so the SSH grabber waits out its whole timeout
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _held = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let started = std::time::Instant::now();
+        let banner = PortScanner::new(1000).with_banner_timeout(100).grab_ssh_banner(&mut stream).await;
+        assert_eq!(banner.status, BannerStatus::ReadTimeout);
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_generic_banner_keeps_every_line() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(b"\x00\x01WELCOME to acme\r\n\r\nbuild 42\x07\r\n").await;
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let banner = PortScanner::new(1000).grab_generic_banner(&mut stream).await;
+        assert_eq!(banner.text, "WELCOME to acme\nbuild 42");
+        assert_eq!(&banner.raw_hex()[..8], "00015745");
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let banner = PortScanner::new(1000).with_banner_bytes(9).grab_generic_banner(&mut stream).await;
+        assert_eq!(banner.raw.len(), 9);
+        assert_eq!(banner.text, "WELCOME");
+    }
+
+    #[tokio::test]
+    async fn test_service_probes_before_builtin_grabbers() {
+        // Answers HELLO with a version line and anything else, or silence, with a greeting
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0; 64];
+                    let read = timeout(Duration::from_millis(100), stream.read(&mut request)).await;
+                    let reply: &[u8] = match read {
+                        Ok(Ok(n)) if request[..n].starts_with(b"HELLO") => b"ACME v2.3 ready\r\n",
+                        _ => b"GREETING\r\n",
+                    };
+                    let _ = stream.write_all(reply).await;
+                });
+            }
+        });
+
+        let probes = format!("{0} acme-ping q|PING| m|^PONG|\n{0} acme-ctl q|HELLO\\r\\n| m|^ACME v([\\d.]+)|\n", port);
+        let budget = Arc::new(ProbeBudget::new(None));
+        let scanner = PortScanner::new(1000)
+            .with_probe_set(probes.parse().unwrap())
+            .with_budget(Arc::clone(&budget));
+        let (_, banner, _) = scanner.check_port(Ipv4Addr::LOCALHOST.into(), port).await.unwrap();
+        assert_eq!(banner.text, "acme-ctl 2.3");
+        assert_eq!(banner.service.as_deref(), Some("acme-ctl"));
+        // The second probe needed its own connection
+        assert_eq!(budget.sent(), 2);
+
+        // When nothing matches, the built-in grabber still runs on a fresh connection
+        let scanner = PortScanner::new(1000).with_probe_set(format!("{} acme-ping q|PING| m|^PONG|", port).parse().unwrap());
+        let (_, banner, _) = scanner.check_port(Ipv4Addr::LOCALHOST.into(), port).await.unwrap();
+        assert_eq!((banner.text.as_str(), banner.service), ("GREETING", None));
+    }
+
+    #[tokio::test]
+    async fn test_check_port_retries() {
+        let budget = Arc::new(ProbeBudget::new(None));
+        let scanner = PortScanner::new(100).with_retries(2).with_budget(Arc::clone(&budget));
+
+        // Refused is final
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let (_, _, state) = scanner.check_port(Ipv4Addr::LOCALHOST.into(), closed).await.unwrap();
+        assert_eq!(state, PortState::Closed);
+        assert_eq!(budget.sent(), 1);
+
+        // A listener whose accept queue is full silently drops new SYNs, so
+        // every attempt times out and is retried
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut backlog = Vec::new();
+        for _ in 0..2 {
+            if let Ok(Ok(stream)) = timeout(Duration::from_millis(200), TcpStream::connect(addr)).await {
+                backlog.push(stream);
+            }
+        }
+        let sent = budget.sent();
+        let (_, _, state) = scanner.check_port(addr.ip(), addr.port()).await.unwrap();
+        assert_eq!(state, PortState::Filtered);
+        assert_eq!(budget.sent() - sent, 3);
+
+        // Filtered ports stay out of the results unless asked for
+        assert!(scanner.scan_ports(addr.ip(), &[addr.port(), closed], None, ScanProtocol::Tcp).await.is_empty());
+        let scanner = scanner.with_show_filtered(true);
+        let findings = scanner.scan_ports(addr.ip(), &[addr.port(), closed], None, ScanProtocol::Tcp).await;
+        assert_eq!(findings.iter().map(|finding| (finding.port, finding.state)).collect::<Vec<_>>(), vec![(addr.port(), PortState::Filtered)]);
+    }
+
     #[tokio::test]
     async fn test_port_scanner() {
         let scanner = PortScanner::new(1000);
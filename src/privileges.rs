@@ -0,0 +1,49 @@
+/// `CAP_NET_RAW`'s bit in the capability sets of `/proc/self/status`
+#[cfg(any(target_os = "linux", test))]
+const CAP_NET_RAW: u32 = 13;
+
+/// Whether this process may open the raw sockets that ICMP echo, ARP and the
+/// TCP TTL sniffer use: effective uid 0 or `CAP_NET_RAW` in the effective set.
+///
+/// Only Linux can be checked; elsewhere this assumes yes and lets the
+/// scanners' constructors report the failure.
+pub fn has_raw_socket_capability() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| status_allows_raw_sockets(&status))
+            .unwrap_or(true)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        true
+    }
+}
+
+/// Read the effective uid and capabilities from `/proc/<pid>/status` text
+#[cfg(any(target_os = "linux", test))]
+fn status_allows_raw_sockets(status: &str) -> Option<bool> {
+    let field = |name: &str| status.lines().find_map(|line| line.strip_prefix(name)).map(str::trim);
+    // Uid: real, effective, saved, filesystem
+    let euid: u32 = field("Uid:")?.split_whitespace().nth(1)?.parse().ok()?;
+    let effective_caps = u64::from_str_radix(field("CapEff:")?, 16).ok()?;
+    Some(euid == 0 || effective_caps & (1 << CAP_NET_RAW) != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_allows_raw_sockets() {
+        let status = |uid: &str, caps: &str| format!("Name:\tangryether\nUid:\t{}\nGid:\t1000\t1000\t1000\t1000\nCapEff:\t{}\n", uid, caps);
+        assert_eq!(status_allows_raw_sockets(&status("0\t0\t0\t0", "000001ffffffffff")), Some(true));
+        // setcap cap_net_raw+ep on the binary
+        assert_eq!(status_allows_raw_sockets(&status("1000\t1000\t1000\t1000", "0000000000002000")), Some(true));
+        assert_eq!(status_allows_raw_sockets(&status("1000\t1000\t1000\t1000", "0000000000000000")), Some(false));
+        // Privileges dropped after start: only the effective uid counts
+        assert_eq!(status_allows_raw_sockets(&status("0\t1000\t0\t1000", "0000000000000000")), Some(false));
+        assert_eq!(status_allows_raw_sockets("Name:\tangryether\n"), None);
+    }
+}
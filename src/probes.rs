@@ -0,0 +1,256 @@
+use anyhow::Result;
+use regex::Regex;
+use std::fs;
+use std::str::FromStr;
+
+/// A user-defined probe: send `payload` to `port` and pull the banner out of
+/// the reply with `pattern`
+#[derive(Debug, Clone)]
+pub struct CustomProbe {
+    pub port: u16,
+    pub payload: Vec<u8>,
+    pub pattern: Regex,
+}
+
+impl CustomProbe {
+    /// The first capture group of `pattern`, or the whole match if it has none
+    pub fn extract(&self, response: &str) -> Option<String> {
+        let captures = self.pattern.captures(response)?;
+        let found = captures.get(1).or_else(|| captures.get(0))?;
+        Some(found.as_str().trim().to_string())
+    }
+}
+
+impl FromStr for CustomProbe {
+    type Err = anyhow::Error;
+
+    /// Parse a `port:send-string:match-regex` spec. The regex may contain
+    /// colons; a colon in the send string must be written as `\x3a`.
+    fn from_str(spec: &str) -> Result<Self> {
+        let mut parts = spec.splitn(3, ':');
+        let (port, payload, pattern) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(port), Some(payload), Some(pattern)) => (port, payload, pattern),
+            _ => return Err(anyhow::anyhow!("invalid probe '{}': expected PORT:SEND:REGEX", spec)),
+        };
+
+        let port = port
+            .trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|port| *port != 0)
+            .ok_or_else(|| anyhow::anyhow!("invalid probe '{}': bad port '{}'", spec, port))?;
+        let payload = unescape(payload)?;
+        let pattern = Regex::new(pattern)
+            .map_err(|e| anyhow::anyhow!("invalid probe '{}': {}", spec, e))?;
+
+        Ok(CustomProbe { port, payload, pattern })
+    }
+}
+
+/// A service fingerprint from a `--probes` file: send `payload` to one of
+/// `ports`, and a reply matching `pattern` identifies `service`
+#[derive(Debug, Clone)]
+pub struct ServiceProbe {
+    pub ports: Vec<u16>,
+    pub service: String,
+    pub payload: Vec<u8>,
+    pub pattern: Regex,
+}
+
+impl ServiceProbe {
+    /// The banner for a matching reply: the service name, followed by the
+    /// pattern's first capture group (usually a version) if it has one
+    pub fn identify(&self, response: &str) -> Option<String> {
+        let captures = self.pattern.captures(response)?;
+        match captures.get(1).map(|detail| detail.as_str().trim()) {
+            Some(detail) if !detail.is_empty() => Some(format!("{} {}", self.service, detail)),
+            _ => Some(self.service.clone()),
+        }
+    }
+}
+
+/// Text between `marker|` and the closing `|`, and whatever follows it. With
+/// `last` the closing bar is the line's last one, so a regex may use `|`.
+fn delimited(rest: &str, marker: char, last: bool) -> Option<(&str, &str)> {
+    let body = rest.trim_start().strip_prefix(marker)?.strip_prefix('|')?;
+    let end = if last { body.rfind('|')? } else { body.find('|')? };
+    Some((&body[..end], &body[end + 1..]))
+}
+
+impl FromStr for ServiceProbe {
+    type Err = anyhow::Error;
+
+    /// Parse a `PORTS SERVICE q|PAYLOAD| m|REGEX|` line, e.g.
+    /// `7777,7778 acme-ctl q|HELLO\r\n| m|^ACME v([\d.]+)|`. A `|` in the
+    /// payload must be written as `\x7c`; the regex may contain them.
+    fn from_str(line: &str) -> Result<Self> {
+        let fields = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .and_then(|(ports, rest)| Some((ports, rest.trim_start().split_once(char::is_whitespace)?)));
+        let Some((ports, (service, rest))) = fields else {
+            return Err(anyhow::anyhow!("expected PORTS SERVICE q|PAYLOAD| m|REGEX|"));
+        };
+
+        let ports = ports
+            .split(',')
+            .map(|port| {
+                port.parse::<u16>()
+                    .ok()
+                    .filter(|port| *port != 0)
+                    .ok_or_else(|| anyhow::anyhow!("bad port '{}'", port))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (payload, rest) =
+            delimited(rest, 'q', false).ok_or_else(|| anyhow::anyhow!("expected the payload as q|...|"))?;
+        let (pattern, rest) =
+            delimited(rest, 'm', true).ok_or_else(|| anyhow::anyhow!("expected the match as m|...|"))?;
+        if !rest.trim().is_empty() {
+            return Err(anyhow::anyhow!("unexpected '{}' after the match", rest.trim()));
+        }
+
+        Ok(ServiceProbe {
+            ports,
+            service: service.to_string(),
+            payload: unescape(payload)?,
+            pattern: Regex::new(pattern)?,
+        })
+    }
+}
+
+/// The service probes loaded from a `--probes` file, tried in file order
+#[derive(Debug, Clone, Default)]
+pub struct ProbeSet {
+    probes: Vec<ServiceProbe>,
+}
+
+impl ProbeSet {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("cannot read '{}': {}", path, e))?;
+        content.parse().map_err(|e| anyhow::anyhow!("{}:{}", path, e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    /// The probes that apply to `port`, in file order
+    pub fn for_port(&self, port: u16) -> impl Iterator<Item = &ServiceProbe> {
+        self.probes.iter().filter(move |probe| probe.ports.contains(&port))
+    }
+}
+
+impl FromStr for ProbeSet {
+    type Err = anyhow::Error;
+
+    /// One probe per line; blank lines and lines starting with `#` are skipped
+    fn from_str(content: &str) -> Result<Self> {
+        let probes = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(number, line)| line.parse().map_err(|e| anyhow::anyhow!("{}: {}", number + 1, e)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ProbeSet { probes })
+    }
+}
+
+/// Expand `\r`, `\n`, `\t`, `\0`, `\\` and `\xHH` escapes into raw bytes
+pub fn unescape(s: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = Some(&hex)
+                    .filter(|hex| hex.len() == 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| anyhow::anyhow!("invalid \\x escape '\\x{}' in '{}'", hex, s))?;
+                bytes.push(byte);
+            }
+            Some(other) => return Err(anyhow::anyhow!("unknown escape '\\{}' in '{}'", other, s)),
+            None => return Err(anyhow::anyhow!("trailing backslash in '{}'", s)),
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_custom_probe() {
+        let probe: CustomProbe = r"7777:HELLO\r\n:VERSION (\d+)".parse().unwrap();
+        assert_eq!(probe.port, 7777);
+        assert_eq!(probe.payload, b"HELLO\r\n");
+        assert_eq!(probe.extract("WELCOME\nVERSION 42\n"), Some("42".to_string()));
+        assert_eq!(probe.extract("nothing here"), None);
+
+        // Colons after the second separator belong to the regex
+        let probe: CustomProbe = r"9000:\x3aPING:^(\w+:\d+)".parse().unwrap();
+        assert_eq!(probe.payload, b":PING");
+        assert_eq!(probe.extract("host:42"), Some("host:42".to_string()));
+
+        assert!("0:HELLO:.*".parse::<CustomProbe>().is_err());
+        assert!("7777:HELLO".parse::<CustomProbe>().is_err());
+        assert!("7777:HELLO:(".parse::<CustomProbe>().is_err());
+    }
+
+    #[test]
+    fn test_parse_probe_set() {
+        let set: ProbeSet = concat!(
+            "# vendor services\n",
+            "7777,7778 acme-ctl q|HELLO\\r\\n| m|^ACME v([\\d.]+)|\n",
+            "\n",
+            "7777   acme-legacy\tq||  m|^(?:OLD|LEGACY) ACME|\n",
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(set.len(), 2);
+
+        let probes: Vec<&ServiceProbe> = set.for_port(7777).collect();
+        assert_eq!(probes.len(), 2);
+        assert_eq!(probes[0].payload, b"HELLO\r\n");
+        assert_eq!(probes[0].identify("ACME v2.3 ready"), Some("acme-ctl 2.3".to_string()));
+        assert_eq!(probes[0].identify("SSH-2.0-OpenSSH"), None);
+        // The match's last bar closes it, so alternation works
+        assert!(probes[1].payload.is_empty());
+        assert_eq!(probes[1].identify("LEGACY ACME"), Some("acme-legacy".to_string()));
+        assert_eq!(set.for_port(7778).count(), 1);
+        assert_eq!(set.for_port(22).count(), 0);
+
+        let error = "7777 acme q|HI|\n".parse::<ProbeSet>().unwrap_err().to_string();
+        assert_eq!(error, "1: expected the match as m|...|");
+        let error = "# ok\n0 acme q|| m|x|\n".parse::<ProbeSet>().unwrap_err().to_string();
+        assert_eq!(error, "2: bad port '0'");
+        assert!("7777 acme q|| m|(|".parse::<ProbeSet>().is_err());
+        assert!("7777 acme q|| m|x| extra".parse::<ProbeSet>().is_err());
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape(r"a\tb\\c\0\xff").unwrap(), b"a\tb\\c\0\xff");
+        assert!(unescape(r"bad\q").is_err());
+        assert!(unescape(r"bad\x4").is_err());
+        assert!(unescape("bad\\").is_err());
+    }
+}
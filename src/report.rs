@@ -0,0 +1,286 @@
+use crate::services::port_service;
+use crate::{HostResult, OpenPort, ScanResults};
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::net::IpAddr;
+
+/// How many ports the summary chart shows
+const TOP_SERVICES: usize = 10;
+
+const STYLE: &str = r#"
+body { font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; margin: 2em; color: #222; }
+h1 { margin-bottom: 0.2em; }
+.meta { color: #666; margin-bottom: 1.5em; }
+.summary { display: flex; gap: 1em; margin-bottom: 1.5em; }
+.card { border: 1px solid #ddd; border-radius: 6px; padding: 0.8em 1.2em; }
+.card b { display: block; font-size: 1.6em; }
+.bar-row { display: flex; align-items: center; margin: 0.2em 0; }
+.bar-label { width: 6em; font-family: monospace; }
+.bar { background: #c0392b; color: #fff; padding: 0.1em 0.4em; font-size: 0.85em; min-width: 1.5em; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 1.5em; }
+th, td { border-bottom: 1px solid #eee; padding: 0.35em 0.6em; text-align: left; vertical-align: top; }
+th { cursor: pointer; background: #f6f6f6; user-select: none; }
+th.sorted-asc::after { content: " \25B2"; }
+th.sorted-desc::after { content: " \25BC"; }
+td.banner { font-family: monospace; white-space: pre-wrap; word-break: break-all; }
+.port { display: inline-block; background: #e3f1e6; color: #1e6b34; border-radius: 3px; padding: 0 0.3em; margin: 0.1em 0; font-family: monospace; }
+.port.risky { background: #c0392b; color: #fff; }
+tr.risky td { background: #fbeaea; }
+#filter { padding: 0.4em; width: 20em; margin-bottom: 1em; }
+details { margin: 0.4em 0; }
+summary { cursor: pointer; font-family: monospace; }
+"#;
+
+const SCRIPT: &str = r#"
+document.querySelectorAll("table.sortable th").forEach(function (th, column) {
+  th.addEventListener("click", function () {
+    var table = th.closest("table");
+    var body = table.tBodies[0];
+    var ascending = !th.classList.contains("sorted-asc");
+    table.querySelectorAll("th").forEach(function (h) { h.classList.remove("sorted-asc", "sorted-desc"); });
+    th.classList.add(ascending ? "sorted-asc" : "sorted-desc");
+    var rows = Array.prototype.slice.call(body.rows);
+    rows.sort(function (a, b) {
+      var x = a.cells[column].dataset.sort || a.cells[column].textContent;
+      var y = b.cells[column].dataset.sort || b.cells[column].textContent;
+      var nx = parseFloat(x), ny = parseFloat(y);
+      var order = (!isNaN(nx) && !isNaN(ny)) ? nx - ny : x.localeCompare(y);
+      return ascending ? order : -order;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+document.getElementById("filter").addEventListener("input", function (event) {
+  var needle = event.target.value.toLowerCase();
+  document.querySelectorAll("[data-filter]").forEach(function (element) {
+    element.style.display = element.dataset.filter.indexOf(needle) === -1 ? "none" : "";
+  });
+});
+"#;
+
+/// Escape text for use in HTML element content and quoted attributes
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Everything the filter box should match for a host: its addresses, ports and banners
+fn filter_text(host: &HostResult) -> String {
+    let mut text = format!(
+        "{} {} {} {}",
+        host.ip,
+        host.hostname.as_deref().unwrap_or_default(),
+        host.mac_address.as_deref().unwrap_or_default(),
+        host.vendor.as_deref().unwrap_or_default()
+    );
+    for port in &host.open_ports {
+        let _ = write!(text, " {} {} {}", port.port, port_service(port).unwrap_or_default(), port.banner);
+    }
+    escape_html(&text.to_lowercase())
+}
+
+/// Ports the risk checks flagged, as (ip, port, protocol), so the report
+/// highlights exactly what the risk summary lists
+fn risky_ports(results: &ScanResults) -> HashSet<(&str, u16, &str)> {
+    results
+        .risks
+        .iter()
+        .map(|risk| (risk.ip.as_str(), risk.port, risk.protocol.as_str()))
+        .collect()
+}
+
+/// Most common open ports across all hosts, busiest first
+fn top_services(hosts: &[HostResult]) -> Vec<(u16, usize)> {
+    let mut counts: HashMap<u16, usize> = HashMap::new();
+    for port in hosts.iter().flat_map(|host| &host.open_ports) {
+        *counts.entry(port.port).or_default() += 1;
+    }
+    let mut counts: Vec<(u16, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(TOP_SERVICES);
+    counts
+}
+
+/// Render the scan as one self-contained HTML page with inline CSS and JS
+pub fn render_html_report(results: &ScanResults) -> String {
+    let mut html = String::new();
+    let open_ports: usize = results.hosts.iter().map(|host| host.open_ports.len()).sum();
+    let risky = risky_ports(results);
+    let is_risky = |host: &HostResult, port: &OpenPort| risky.contains(&(host.ip.as_str(), port.port, port.protocol.as_str()));
+
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(html, "<title>AngryEther report: {}</title>", escape_html(&results.subnet));
+    let _ = writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE);
+    let _ = writeln!(html, "<h1>AngryEther scan of {}</h1>", escape_html(&results.subnet));
+    let _ = writeln!(
+        html,
+        "<div class=\"meta\">{} &middot; interface {} &middot; seed {}</div>",
+        results.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+        escape_html(&results.interface),
+        results.seed
+    );
+
+    html.push_str("<div class=\"summary\">\n");
+    for (label, value) in [
+        ("Hosts scanned", results.total_hosts_scanned),
+        ("Active hosts", results.active_hosts_found),
+        ("Open ports", open_ports),
+    ] {
+        let _ = writeln!(html, "<div class=\"card\"><b>{}</b>{}</div>", value, label);
+    }
+    html.push_str("</div>\n");
+
+    let services = top_services(&results.hosts);
+    if let Some(&(_, busiest)) = services.first() {
+        html.push_str("<h2>Top services</h2>\n");
+        for (port, count) in &services {
+            let _ = writeln!(
+                html,
+                "<div class=\"bar-row\"><span class=\"bar-label\">{}</span><span class=\"bar\" style=\"width: {}%\">{}</span></div>",
+                port,
+                count * 60 / busiest,
+                count
+            );
+        }
+    }
+
+    html.push_str("<h2>Hosts</h2>\n<input id=\"filter\" type=\"search\" placeholder=\"Filter by IP, hostname, MAC, vendor, port, service or banner\">\n");
+    html.push_str("<table class=\"sortable\">\n<thead><tr><th>IP</th><th>Hostname</th><th>MAC</th><th>Vendor</th><th>Discovery</th><th>Open ports</th><th>Scan time (ms)</th></tr></thead>\n<tbody>\n");
+    for host in &results.hosts {
+        let ip_sort = match host.ip.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => u128::from(u32::from(ip)),
+            Ok(IpAddr::V6(ip)) => u128::from(ip),
+            Err(_) => 0,
+        };
+        let ports: Vec<String> = host
+            .open_ports
+            .iter()
+            .map(|port| {
+                let class = if is_risky(host, port) { "port risky" } else { "port" };
+                format!("<span class=\"{}\">{}</span>", class, port.port)
+            })
+            .collect();
+        let _ = writeln!(
+            html,
+            "<tr data-filter=\"{}\"><td data-sort=\"{}\"><a href=\"#host-{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td data-sort=\"{}\">{}</td><td>{}</td></tr>",
+            filter_text(host),
+            ip_sort,
+            escape_html(&host.ip),
+            escape_html(&host.ip),
+            escape_html(host.hostname.as_deref().unwrap_or("-")),
+            escape_html(host.mac_address.as_deref().unwrap_or("-")),
+            escape_html(host.vendor.as_deref().unwrap_or("-")),
+            escape_html(&host.discovery_method),
+            host.open_ports.len(),
+            ports.join(" "),
+            host.scan_duration_ms
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+
+    html.push_str("<h2>Port details</h2>\n");
+    for host in &results.hosts {
+        let _ = writeln!(
+            html,
+            "<details id=\"host-{}\" data-filter=\"{}\"><summary>{} ({} open)</summary>",
+            escape_html(&host.ip),
+            filter_text(host),
+            escape_html(&host.ip),
+            host.open_ports.len()
+        );
+        html.push_str("<table>\n<thead><tr><th>Port</th><th>Service</th><th>Banner</th></tr></thead>\n<tbody>\n");
+        for port in &host.open_ports {
+            let _ = writeln!(
+                html,
+                "<tr{}><td>{}/{}</td><td>{}</td><td class=\"banner\">{}</td></tr>",
+                if is_risky(host, port) { " class=\"risky\"" } else { "" },
+                port.port,
+                escape_html(&port.protocol),
+                escape_html(port_service(port).unwrap_or("")),
+                escape_html(&port.banner)
+            );
+        }
+        html.push_str("</tbody>\n</table>\n</details>\n");
+    }
+
+    let _ = writeln!(html, "<script>{}</script>\n</body>\n</html>", SCRIPT);
+    html
+}
+
+pub fn write_html_report(results: &ScanResults, path: &str) -> Result<()> {
+    fs::write(path, render_html_report(results))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risk::find_risks;
+    use crate::FORMAT_VERSION;
+    use chrono::Utc;
+
+    fn host(ip: &str, ports: &[(u16, &str)]) -> HostResult {
+        HostResult {
+            ip: ip.to_string(),
+            discovery_method: "ICMP".to_string(),
+            open_ports: ports
+                .iter()
+                .map(|&(port, banner)| OpenPort { banner: banner.to_string(), ..OpenPort::tcp(port) })
+                .collect(),
+            scan_duration_ms: 10,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_html_report_escapes_banners() {
+        let mut tagged = host("192.0.2.11", &[(80, "nginx & friends"), (23, "")]);
+        tagged.mac_address = Some("00:11:22:33:44:55".to_string());
+        tagged.vendor = Some("Acme <Networks>".to_string());
+        let hosts = vec![host("192.0.2.10", &[(80, "<script>alert('x')</script>"), (22, "SSH-2.0-OpenSSH")]), tagged];
+        let risks = find_risks(&hosts);
+        let results = ScanResults {
+            format_version: FORMAT_VERSION,
+            timestamp: Utc::now(),
+            interface: "eth0".to_string(),
+            subnet: "192.0.2.0/24".to_string(),
+            timeout_ms: 500,
+            total_hosts_scanned: 256,
+            active_hosts_found: 2,
+            discovery_methods: vec!["ICMP".to_string()],
+            hosts,
+            seed: 7,
+            risks,
+            ..Default::default()
+        };
+
+        let html = render_html_report(&results);
+        assert!(!html.contains("<script>alert"));
+        assert!(html.contains("&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"));
+        assert!(html.contains("nginx &amp; friends"));
+        // Exactly one script element: the report's own
+        assert_eq!(html.matches("<script>").count(), 1);
+        assert_eq!(top_services(&results.hosts), vec![(80, 2), (22, 1), (23, 1)]);
+        // Telnet is flagged both in the host table and in the port details
+        assert!(html.contains("<span class=\"port risky\">23</span>"));
+        assert!(html.contains("<tr class=\"risky\"><td>23/tcp</td><td>telnet</td>"));
+        assert!(html.contains("<span class=\"port\">80</span>"));
+        assert!(html.contains("<td>00:11:22:33:44:55</td><td>Acme &lt;Networks&gt;</td>"));
+
+        // Only what the risk summary lists is highlighted
+        let unflagged = ScanResults { risks: Vec::new(), ..results };
+        assert!(!render_html_report(&unflagged).contains("risky\">"));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{HostResult, OpenPort};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How urgently a risky service wants looking at; the most severe sorts first
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Credentials or whole sessions exposed, or a protocol with known wormable flaws
+    High,
+    /// Credentials exposed, but usually on a service that is meant to be reachable
+    Medium,
+    /// Not a weakness in itself, but worth knowing about
+    Low,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::High => "high",
+            Severity::Medium => "medium",
+            Severity::Low => "low",
+        }
+    }
+}
+
+/// An open port running a plaintext or legacy service
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct RiskFinding {
+    pub ip: String,
+    pub port: u16,
+    pub protocol: String,
+    pub service: String,
+    pub severity: Severity,
+    pub reason: String,
+}
+
+/// Plaintext services recognised by their port or IANA name, whatever they answered
+const PLAINTEXT_SERVICES: &[(u16, &str, Severity, &str)] = &[
+    (23, "telnet", Severity::High, "Telnet sends logins and whole sessions in plaintext"),
+    (512, "exec", Severity::High, "rexec sends passwords in plaintext"),
+    (513, "login", Severity::High, "rlogin trusts client host names and sends sessions in plaintext"),
+    (514, "shell", Severity::High, "rsh trusts client host names and sends sessions in plaintext"),
+    (21, "ftp", Severity::Medium, "FTP sends logins and files in plaintext"),
+];
+
+/// Words in a redirect target that give a captive portal away
+const CAPTIVE_PORTAL_HINTS: &[&str] = &["captive", "portal", "hotspot", "splash", "guest", "wifi", "login"];
+
+/// The host part of an absolute `http(s)://` URL, without port or credentials
+fn url_host(url: &str) -> Option<&str> {
+    let rest = url.strip_prefix("http://").or_else(|| url.strip_prefix("https://"))?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next(),
+        None => host.split(':').next(),
+    }
+}
+
+/// Why the web server on `port` looks like a captive portal: it answers
+/// 511, or sends `GET /` off to a login page on another host, as guest
+/// networks do before they let anyone out
+fn captive_portal(ip: &str, port: &OpenPort) -> Option<String> {
+    let http = port.http.as_ref()?;
+    if http.status == 511 {
+        return Some("answers 511 Network Authentication Required, as captive portals do".to_string());
+    }
+    let redirect = http.redirect.as_deref()?;
+    let host = url_host(redirect).filter(|host| !host.eq_ignore_ascii_case(ip))?;
+    let lower = redirect.to_ascii_lowercase();
+    CAPTIVE_PORTAL_HINTS
+        .iter()
+        .any(|hint| lower.contains(hint))
+        .then(|| format!("redirects to {} on {}, which looks like a captive portal", redirect, host))
+}
+
+/// The risk one open port carries, if any. TCP only: 514/udp is syslog, not rsh.
+fn port_risk(ip: &str, port: &OpenPort) -> Option<(&'static str, Severity, String)> {
+    if port.protocol != "tcp" {
+        return None;
+    }
+    if port.banner.starts_with("SMBv1") {
+        return Some(("smb", Severity::High, "SMBv1 is enabled; it has no encryption and was the way in for WannaCry and NotPetya".to_string()));
+    }
+    // The HTTP grabber notes a Basic challenge; over TLS the password is at least encrypted
+    if port.tls.is_none() && port.banner.contains("| Auth: Basic") {
+        return Some(("http", Severity::Medium, "HTTP Basic authentication sends passwords in plaintext".to_string()));
+    }
+    PLAINTEXT_SERVICES
+        .iter()
+        .find(|(number, name, _, _)| port.port == *number || port.service.as_deref() == Some(*name))
+        .map(|(_, name, severity, reason)| (*name, *severity, reason.to_string()))
+        .or_else(|| captive_portal(ip, port).map(|reason| ("captive-portal", Severity::Low, reason)))
+}
+
+/// Risky services on one host, in port order
+fn host_risks(host: &HostResult) -> Vec<RiskFinding> {
+    host.open_ports
+        .iter()
+        .filter(|port| port.state == "open")
+        .filter_map(|port| {
+            let (service, severity, reason) = port_risk(&host.ip, port)?;
+            Some(RiskFinding {
+                ip: host.ip.clone(),
+                port: port.port,
+                protocol: port.protocol.clone(),
+                service: service.to_string(),
+                severity,
+                reason,
+            })
+        })
+        .collect()
+}
+
+/// Risky services on `hosts`, most severe first and otherwise in host order
+pub fn find_risks(hosts: &[HostResult]) -> Vec<RiskFinding> {
+    let mut risks: Vec<RiskFinding> = hosts.iter().flat_map(host_risks).collect();
+    risks.sort_by_key(|risk| risk.severity);
+    risks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portscan::HttpInfo;
+
+    fn open_port(port: u16, protocol: &str, banner: &str, service: Option<&str>) -> OpenPort {
+        OpenPort {
+            protocol: protocol.to_string(),
+            banner: banner.to_string(),
+            service: service.map(str::to_string),
+            ..OpenPort::tcp(port)
+        }
+    }
+
+    #[test]
+    fn test_host_risks() {
+        let host = HostResult {
+            ip: "192.0.2.10".to_string(),
+            discovery_method: "ICMP".to_string(),
+            open_ports: vec![
+                open_port(21, "tcp", "220 ProFTPD Server", Some("ftp")),
+                open_port(22, "tcp", "SSH-2.0-OpenSSH_9.6", Some("ssh")),
+                open_port(80, "tcp", "Server: lighttpd | Auth: Basic realm=\"router\"", Some("http")),
+                open_port(445, "tcp", "SMBv1 (NT LM 0.12)", Some("microsoft-ds")),
+                open_port(514, "udp", "", Some("syslog")),
+                // Telnet moved off its port is still telnet
+                open_port(2323, "tcp", "", Some("telnet")),
+            ],
+            ..Default::default()
+        };
+        let risks = host_risks(&host);
+        assert_eq!(
+            risks.iter().map(|risk| (risk.port, risk.severity)).collect::<Vec<_>>(),
+            vec![(21, Severity::Medium), (80, Severity::Medium), (445, Severity::High), (2323, Severity::High)]
+        );
+        assert_eq!(risks[3].service, "telnet");
+        assert_eq!(serde_json::to_string(&Severity::High).unwrap(), "\"high\"");
+    }
+
+    #[test]
+    fn test_captive_portal() {
+        let redirect = |status, location: Option<&str>| {
+            let mut port = open_port(80, "tcp", "", Some("http"));
+            port.http = Some(HttpInfo { status, redirect: location.map(str::to_string) });
+            port
+        };
+        let portal = redirect(302, Some("http://guest.example.net:8080/portal/login?orig=/"));
+        let (service, severity, reason) = port_risk("10.0.0.1", &portal).unwrap();
+        assert_eq!((service, severity), ("captive-portal", Severity::Low));
+        assert!(reason.contains("on guest.example.net"));
+        assert!(port_risk("10.0.0.1", &redirect(511, None)).is_some());
+
+        // A router's own login page, a relative redirect and a plain HTTPS upgrade aren't portals
+        assert!(port_risk("10.0.0.1", &redirect(302, Some("http://10.0.0.1/login.html"))).is_none());
+        assert!(port_risk("10.0.0.1", &redirect(302, Some("/login"))).is_none());
+        assert!(port_risk("10.0.0.1", &redirect(301, Some("https://www.example.com/"))).is_none());
+        assert_eq!(url_host("https://user:pw@[2001:db8::1]:8443/"), Some("2001:db8::1"));
+    }
+}
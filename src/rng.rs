@@ -0,0 +1,69 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::net::IpAddr;
+
+/// A fresh seed for runs that didn't get `--seed`
+pub fn generate_seed() -> u64 {
+    rand::random()
+}
+
+/// SplitMix64 finalizer, used to spread seeds and keys across all bits
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// An RNG for one randomized component (`stream`) and item (`key`), e.g. the
+/// ping identifiers for one host.
+///
+/// Everything random in a scan draws from here, so the run seed alone
+/// reproduces it, independent of task scheduling.
+pub fn rng_for(seed: u64, stream: &str, key: u64) -> StdRng {
+    let stream_hash = stream.bytes().fold(0u64, |hash, byte| mix(hash ^ byte as u64));
+    StdRng::seed_from_u64(mix(mix(seed ^ stream_hash) ^ key))
+}
+
+/// A `rng_for` key for `ip`; IPv6 addresses fold their halves together
+pub fn ip_key(ip: IpAddr) -> u64 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip) as u64,
+        IpAddr::V6(ip) => u128::from(ip) as u64 ^ (u128::from(ip) >> 64) as u64,
+    }
+}
+
+/// Shuffle `items` in an order fixed by the run seed, e.g. hosts for `--randomize`
+pub fn shuffle_seeded<T>(items: &mut [T], seed: u64, stream: &str) {
+    items.shuffle(&mut rng_for(seed, stream, 0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_rng_for_is_reproducible() {
+        let draw = |seed, stream, key| rng_for(seed, stream, key).gen::<u64>();
+        assert_eq!(draw(42, "ping", 7), draw(42, "ping", 7));
+        assert_ne!(draw(42, "ping", 7), draw(43, "ping", 7));
+        assert_ne!(draw(42, "ping", 7), draw(42, "ping", 8));
+        assert_ne!(draw(42, "ping", 7), draw(42, "ports", 7));
+    }
+
+    #[test]
+    fn test_shuffle_seeded() {
+        let shuffled = |seed| {
+            let mut items: Vec<u32> = (0..100).collect();
+            shuffle_seeded(&mut items, seed, "hosts");
+            items
+        };
+        assert_eq!(shuffled(42), shuffled(42));
+        assert_ne!(shuffled(42), shuffled(43));
+        let mut sorted = shuffled(42);
+        sorted.sort();
+        assert_eq!(sorted, (0..100).collect::<Vec<u32>>());
+    }
+}
@@ -0,0 +1,265 @@
+use crate::discovery::Discovered;
+use crate::portscan::{PortFinding, PortScanner, PortState, ScanProtocol};
+use crate::risk::{find_risks, RiskFinding};
+use crate::trace::{TimingTrace, PHASE_TRACK};
+use crate::{HostResult, OpenPort};
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Instant;
+
+/// What `HostScans::run` gathered
+#[derive(Debug, Default)]
+pub struct ScannedHosts {
+    /// By IP address, so the output is the same every run
+    pub hosts: Vec<HostResult>,
+    /// Most severe first, read from the whole banners whatever `--banner-mode` kept
+    pub risks: Vec<RiskFinding>,
+    /// Hosts not scanned, or cut short, because the scan was cancelled or
+    /// the probe budget ran out
+    pub skipped: usize,
+    /// Open ports recorded, not counting `--show-filtered`'s filtered ones
+    pub recorded_open_ports: usize,
+    /// Open ports over `with_max_results`, counted but not recorded
+    pub omitted_open_ports: usize,
+}
+
+/// The port scans of a scan's active hosts, run `with_concurrency` at a time
+/// and turned into `HostResult`s by `run`
+pub struct HostScans<'a> {
+    scanner: &'a PortScanner,
+    found: &'a Discovered,
+    trace: &'a TimingTrace,
+    /// None when hosts only get results from discovery
+    ports: Option<(&'a [u16], ScanProtocol)>,
+    concurrency: usize,
+    max_results: Option<usize>,
+    record_status: bool,
+    record_raw: bool,
+    hostnames: Option<&'a HashMap<IpAddr, String>>,
+    vendors: Option<&'a HashMap<String, String>>,
+    gateway: Option<Ipv4Addr>,
+}
+
+impl<'a> HostScans<'a> {
+    pub fn new(scanner: &'a PortScanner, found: &'a Discovered, trace: &'a TimingTrace) -> Self {
+        HostScans {
+            scanner,
+            found,
+            trace,
+            ports: None,
+            concurrency: 1,
+            max_results: None,
+            record_status: false,
+            record_raw: false,
+            hostnames: None,
+            vendors: None,
+            gateway: None,
+        }
+    }
+
+    /// Port scan each host; without this, hosts get results from discovery alone
+    pub fn with_ports(mut self, ports: &'a [u16], protocol: ScanProtocol) -> Self {
+        self.ports = Some((ports, protocol));
+        self
+    }
+
+    pub fn with_concurrency(mut self, hosts: usize) -> Self {
+        self.concurrency = hosts.max(1);
+        self
+    }
+
+    /// Record at most `max` open ports across all hosts. Filtered ports
+    /// don't count and are always kept. The cap is first come, first
+    /// served: hosts are handed to `run`'s callback as they finish, so
+    /// which hosts fill it depends on which finish first.
+    pub fn with_max_results(mut self, max: Option<usize>) -> Self {
+        self.max_results = max;
+        self
+    }
+
+    /// Save each port's banner status and its raw banner bytes in the results
+    pub fn with_banner_details(mut self, status: bool, raw: bool) -> Self {
+        self.record_status = status;
+        self.record_raw = raw;
+        self
+    }
+
+    /// Names the targets were given as, by address
+    pub fn with_hostnames(mut self, hostnames: &'a HashMap<IpAddr, String>) -> Self {
+        self.hostnames = Some(hostnames);
+        self
+    }
+
+    /// Vendors by MAC address, e.g. from `oui::lookup_vendors`
+    pub fn with_vendors(mut self, vendors: &'a HashMap<String, String>) -> Self {
+        self.vendors = Some(vendors);
+        self
+    }
+
+    pub fn with_gateway(mut self, gateway: Option<Ipv4Addr>) -> Self {
+        self.gateway = gateway;
+        self
+    }
+
+    /// Scan `hosts`, handing each result to `each` as it is done along with
+    /// its findings, whose banners still hold the raw bytes
+    pub async fn run(self, hosts: &[IpAddr], mut each: impl FnMut(IpAddr, &HostResult, &[PortFinding])) -> ScannedHosts {
+        let this = &self;
+        let mut scanned = ScannedHosts::default();
+        let mut results = Vec::new();
+
+        self.trace.begin("port scan", "phase", PHASE_TRACK);
+        let mut scans = stream::iter(hosts.iter().enumerate())
+            .map(|(index, &host)| async move { (host, this.scan_host(index, host).await) })
+            .buffer_unordered(self.concurrency);
+        while let Some((host, scan)) = scans.next().await {
+            let Some((mut findings, scan_duration_ms)) = scan else {
+                scanned.skipped += 1;
+                continue;
+            };
+            // Ports finish in any order too; sorted so runs diff cleanly and a
+            // capped host keeps its lowest ports
+            findings.sort_by_key(|finding| (finding.port, finding.protocol.name()));
+
+            // Safety valve against hosts that claim every port is open
+            let mut open_ports = open_count(&findings);
+            if let Some(max) = self.max_results {
+                let room = max.saturating_sub(scanned.recorded_open_ports);
+                if open_ports > room {
+                    scanned.omitted_open_ports += open_ports - room;
+                    let mut kept = 0;
+                    findings.retain(|finding| {
+                        finding.state == PortState::Filtered || {
+                            kept += 1;
+                            kept <= room
+                        }
+                    });
+                    open_ports = room;
+                }
+            }
+            scanned.recorded_open_ports += open_ports;
+
+            let result = self.host_result(host, &findings, scan_duration_ms);
+            if self.scanner.open_only() && result.open_ports.is_empty() {
+                continue;
+            }
+            // Risks read the whole banner, whatever --banner-mode keeps
+            let mut unabridged = result.clone();
+            for (port, finding) in unabridged.open_ports.iter_mut().zip(&findings) {
+                port.banner = finding.full_text.clone();
+            }
+            let risks = find_risks(std::slice::from_ref(&unabridged));
+            each(host, &result, &findings);
+            results.push((host, result, risks));
+        }
+        self.trace.end("port scan", "phase", PHASE_TRACK);
+
+        results.sort_by_key(|(ip, _, _)| *ip);
+        for (_, result, risks) in results {
+            scanned.hosts.push(result);
+            scanned.risks.extend(risks);
+        }
+        scanned.risks.sort_by_key(|risk| risk.severity);
+        scanned
+    }
+
+    /// The host's findings and how long its scan took, or `None` when it was
+    /// skipped or cut short, so a `--resume` scans it again
+    async fn scan_host(&self, index: usize, host: IpAddr) -> Option<(Vec<PortFinding>, u64)> {
+        let Some((ports, protocol)) = self.ports else {
+            return Some((Vec::new(), 0));
+        };
+        // Checked as each host's turn comes; scans under way watch the token themselves
+        if self.scanner.stopped() {
+            return None;
+        }
+        let mac_address = self.found.mac_addresses.get(&host);
+        let host_track = index as u64 + 1;
+        let host_span = format!("scan {}", host);
+        self.trace.name_track(host_track, &host.to_string());
+        self.trace.begin(&host_span, "host", host_track);
+        let host_start = Instant::now();
+        let findings = self.scanner.scan_ports(host, ports, mac_address.map(String::as_str), protocol).await;
+        let scan_duration_ms = host_start.elapsed().as_millis() as u64;
+        self.trace.end(&host_span, "host", host_track);
+        if self.scanner.is_cancelled() {
+            return None;
+        }
+        Some((findings, scan_duration_ms))
+    }
+
+    fn host_result(&self, host: IpAddr, findings: &[PortFinding], scan_duration_ms: u64) -> HostResult {
+        let open_ports: Vec<OpenPort> = findings
+            .iter()
+            .cloned()
+            .map(|finding| finding.into_open_port(self.record_status, self.record_raw))
+            .collect();
+        let mac_address = self.found.mac_addresses.get(&host);
+        HostResult {
+            hostname: self.hostnames.and_then(|names| names.get(&host)).cloned(),
+            vendor: self.vendors.zip(mac_address).and_then(|(vendors, mac)| vendors.get(mac)).cloned(),
+            is_gateway: self.gateway.is_some_and(|gateway| host == IpAddr::V4(gateway)),
+            ..self.found.host_result(host, open_ports, scan_duration_ms)
+        }
+    }
+}
+
+/// Findings that count as open ports, i.e. all but `--show-filtered`'s filtered ones
+fn open_count(findings: &[PortFinding]) -> usize {
+    findings.iter().filter(|finding| finding.state != PortState::Filtered).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::DiscoveryMethod;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_host_scans() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ports = [listener.local_addr().unwrap().port(), second.local_addr().unwrap().port()];
+        for listener in [listener, second] {
+            tokio::spawn(async move {
+                loop {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let _ = stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").await;
+                }
+            });
+        }
+        let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut found = Discovered::default();
+        found.methods.entry(host).or_default().insert(DiscoveryMethod::Tcp);
+        found.mac_addresses.insert(host, "00:11:22:33:44:55".to_string());
+        let hostnames = HashMap::from([(host, "localhost".to_string())]);
+        let vendors = HashMap::from([("00:11:22:33:44:55".to_string(), "Example Corp".to_string())]);
+        let scanner = PortScanner::new(1000);
+        let trace = TimingTrace::new(false);
+
+        let mut seen = Vec::new();
+        let scanned = HostScans::new(&scanner, &found, &trace)
+            .with_ports(&ports, ScanProtocol::Tcp)
+            .with_max_results(Some(1))
+            .with_hostnames(&hostnames)
+            .with_vendors(&vendors)
+            .with_gateway(Some(Ipv4Addr::LOCALHOST))
+            .run(&[host], |ip, result, findings| seen.push((ip, result.ip.clone(), findings.len())))
+            .await;
+        assert_eq!(seen, [(host, "127.0.0.1".to_string(), 1)]);
+        let result = &scanned.hosts[0];
+        assert_eq!(result.hostname.as_deref(), Some("localhost"));
+        assert_eq!(result.vendor.as_deref(), Some("Example Corp"));
+        assert!(result.is_gateway);
+        // The cap keeps the lowest port and counts the other
+        assert_eq!(result.open_ports.len(), 1);
+        assert_eq!(result.open_ports[0].port, *ports.iter().min().unwrap());
+        assert_eq!((scanned.recorded_open_ports, scanned.omitted_open_ports, scanned.skipped), (1, 1, 0));
+
+        // Without ports, hosts get results from discovery alone
+        let scanned = HostScans::new(&scanner, &found, &trace).run(&[host], |_, _, _| {}).await;
+        assert_eq!(scanned.hosts[0].discovery_method, "TCP");
+        assert!(scanned.hosts[0].open_ports.is_empty());
+    }
+}
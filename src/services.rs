@@ -0,0 +1,71 @@
+use crate::portscan::Protocol;
+use crate::OpenPort;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// The embedded IANA table, so names don't depend on the host's /etc/services
+const SERVICES: &str = include_str!("../ports/services.txt");
+
+fn parse_services(text: &str) -> HashMap<(u16, Protocol), &str> {
+    let mut table = HashMap::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let Some((name, port_protocol)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let Some((port, protocol)) = port_protocol.trim().split_once('/') else {
+            continue;
+        };
+        let protocol = match protocol {
+            "tcp" => Protocol::Tcp,
+            "udp" => Protocol::Udp,
+            _ => continue,
+        };
+        if let Ok(port) = port.parse() {
+            // The first name listed for a port is IANA's primary one
+            table.entry((port, protocol)).or_insert(name);
+        }
+    }
+    table
+}
+
+/// IANA's registered service name for a port, e.g. "http-alt" for 8080/tcp
+pub fn service_name(port: u16, protocol: Protocol) -> Option<&'static str> {
+    static TABLE: OnceLock<HashMap<(u16, Protocol), &'static str>> = OnceLock::new();
+    TABLE.get_or_init(|| parse_services(SERVICES)).get(&(port, protocol)).copied()
+}
+
+/// An open port's service: what a probe identified, else its registered name
+pub fn port_service(port: &OpenPort) -> Option<&str> {
+    let protocol = if port.protocol == "udp" { Protocol::Udp } else { Protocol::Tcp };
+    port.service.as_deref().or_else(|| service_name(port.port, protocol))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_name() {
+        assert_eq!(service_name(8080, Protocol::Tcp), Some("http-alt"));
+        assert_eq!(service_name(22, Protocol::Tcp), Some("ssh"));
+        assert_eq!(service_name(53, Protocol::Udp), Some("domain"));
+        assert_eq!(service_name(3389, Protocol::Tcp), Some("ms-wbt-server"));
+        assert_eq!(service_name(65000, Protocol::Tcp), None);
+
+        let table = parse_services("# comment\nfoo 1/tcp\nbar 1/tcp\nbaz 2/sctp\nbroken\n");
+        assert_eq!(table.get(&(1, Protocol::Tcp)), Some(&"foo"));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_port_service() {
+        let mut port = OpenPort::tcp(8080);
+        assert_eq!(port_service(&port), Some("http-alt"));
+        port.service = Some("http".to_string());
+        assert_eq!(port_service(&port), Some("http"));
+        port.protocol = "udp".to_string();
+        port.port = 161;
+        port.service = None;
+        assert_eq!(port_service(&port), Some("snmp"));
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::Result;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// A SOCKS5 proxy (RFC 1928) that TCP connects go through, from
+/// `socks5://[user:password@]host:port`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    /// `host:port`, resolved on each connect
+    address: String,
+    /// Username and password for RFC 1929 authentication
+    credentials: Option<(String, String)>,
+}
+
+impl FromStr for Socks5Proxy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let rest = s
+            .strip_prefix("socks5://")
+            .ok_or_else(|| anyhow::anyhow!("unsupported proxy '{}' (expected socks5://host:port)", s))?;
+        let (credentials, address) = match rest.rsplit_once('@') {
+            Some((userinfo, address)) => {
+                let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                anyhow::ensure!(user.len() <= 255 && password.len() <= 255, "proxy username and password are limited to 255 bytes");
+                (Some((user.to_string(), password.to_string())), address)
+            }
+            None => (None, rest),
+        };
+        let address = address.trim_end_matches('/');
+        let port = address.rsplit_once(':').map(|(_, port)| port).unwrap_or("");
+        anyhow::ensure!(port.parse::<u16>().is_ok(), "proxy '{}' needs a port, e.g. socks5://127.0.0.1:1080", s);
+        Ok(Socks5Proxy { address: address.to_string(), credentials })
+    }
+}
+
+impl Socks5Proxy {
+    /// `host:port` of the proxy itself
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Connect to the proxy and agree on authentication
+    async fn open(&self) -> std::io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        let methods: &[u8] = if self.credentials.is_some() { &[NO_AUTH, USERNAME_PASSWORD] } else { &[NO_AUTH] };
+        let mut greeting = vec![VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).await?;
+
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await?;
+        match choice {
+            [VERSION, NO_AUTH] => {}
+            [VERSION, USERNAME_PASSWORD] => {
+                let Some((user, password)) = &self.credentials else {
+                    return Err(Error::other("proxy asked for a username and password"));
+                };
+                let mut request = vec![1, user.len() as u8];
+                request.extend_from_slice(user.as_bytes());
+                request.push(password.len() as u8);
+                request.extend_from_slice(password.as_bytes());
+                stream.write_all(&request).await?;
+                let mut reply = [0; 2];
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0 {
+                    return Err(Error::other("proxy rejected the username and password"));
+                }
+            }
+            [VERSION, NO_ACCEPTABLE_METHOD] => return Err(Error::other("proxy accepts none of our authentication methods")),
+            _ => return Err(Error::other("not a SOCKS5 proxy")),
+        }
+        Ok(stream)
+    }
+
+    /// Check up front that the proxy is reachable and lets us in, so a scan
+    /// doesn't report every port filtered because the proxy is down
+    pub async fn check(&self) -> Result<()> {
+        self.open().await.map(drop).map_err(|e| anyhow::anyhow!("SOCKS5 proxy {}: {}", self.address, e))
+    }
+
+    /// A TCP connection to `target` through the proxy, ready for the
+    /// target's own protocol. A refusal from the target comes back as
+    /// `ConnectionRefused`, just like a direct connect.
+    pub async fn connect(&self, target: SocketAddr) -> std::io::Result<TcpStream> {
+        let mut stream = self.open().await?;
+        let mut request = vec![VERSION, CONNECT, 0];
+        match target {
+            SocketAddr::V4(addr) => {
+                request.push(ATYP_IPV4);
+                request.extend_from_slice(&addr.ip().octets());
+            }
+            SocketAddr::V6(addr) => {
+                request.push(ATYP_IPV6);
+                request.extend_from_slice(&addr.ip().octets());
+            }
+        }
+        request.extend_from_slice(&target.port().to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        match reply[1] {
+            0x00 => {}
+            0x05 => return Err(Error::from(ErrorKind::ConnectionRefused)),
+            0x02 => return Err(Error::other("proxy ruleset forbids the connection")),
+            0x03 => return Err(Error::other("proxy: network unreachable")),
+            0x04 => return Err(Error::other("proxy: host unreachable")),
+            0x06 => return Err(Error::other("proxy: TTL expired")),
+            code => return Err(Error::other(format!("proxy failed the connect (reply {:#04x})", code))),
+        }
+        // The address the proxy bound, which nothing here needs
+        let bound = match reply[3] {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => stream.read_u8().await? as usize,
+            _ => return Err(Error::other("proxy sent an unknown address type")),
+        };
+        let mut skip = vec![0; bound + 2];
+        stream.read_exact(&mut skip).await?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_proxy() {
+        let proxy: Socks5Proxy = "socks5://127.0.0.1:1080".parse().unwrap();
+        assert_eq!((proxy.address(), proxy.credentials.clone()), ("127.0.0.1:1080", None));
+        let proxy: Socks5Proxy = "socks5://scan:s3cret@[2001:db8::1]:9050/".parse().unwrap();
+        assert_eq!(proxy.address(), "[2001:db8::1]:9050");
+        assert_eq!(proxy.credentials, Some(("scan".to_string(), "s3cret".to_string())));
+        assert!("http://127.0.0.1:8080".parse::<Socks5Proxy>().is_err());
+        assert!("socks5://127.0.0.1".parse::<Socks5Proxy>().is_err());
+    }
+
+    /// A proxy that demands a password, refuses connects to port 1 and
+    /// answers every other connect itself with a banner
+    async fn fake_proxy() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut client, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut header = [0; 2];
+                    client.read_exact(&mut header).await.unwrap();
+                    let mut methods = vec![0; header[1] as usize];
+                    client.read_exact(&mut methods).await.unwrap();
+                    if !methods.contains(&USERNAME_PASSWORD) {
+                        client.write_all(&[VERSION, NO_ACCEPTABLE_METHOD]).await.unwrap();
+                        return;
+                    }
+                    client.write_all(&[VERSION, USERNAME_PASSWORD]).await.unwrap();
+                    let mut auth = [0; 11];
+                    client.read_exact(&mut auth).await.unwrap();
+                    assert_eq!(&auth, b"\x01\x04scan\x04pass");
+                    client.write_all(&[1, 0]).await.unwrap();
+
+                    let mut request = [0; 10];
+                    client.read_exact(&mut request).await.unwrap();
+                    assert_eq!(request[..8], [VERSION, CONNECT, 0, ATYP_IPV4, 192, 0, 2, 10]);
+                    let code = if request[8..] == [0, 1] { 0x05 } else { 0x00 };
+                    client.write_all(&[VERSION, code, 0, ATYP_DOMAIN, 5, b'p', b'r', b'o', b'x', b'y', 0, 0]).await.unwrap();
+                    if code == 0 {
+                        client.write_all(b"SSH-2.0-behind-proxy\r\n").await.unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_proxy() {
+        let proxy: Socks5Proxy = format!("socks5://scan:pass@{}", fake_proxy().await).parse().unwrap();
+        proxy.check().await.unwrap();
+
+        let mut stream = proxy.connect("192.0.2.10:22".parse().unwrap()).await.unwrap();
+        let mut banner = String::new();
+        stream.read_to_string(&mut banner).await.unwrap();
+        assert_eq!(banner, "SSH-2.0-behind-proxy\r\n");
+
+        let refused = proxy.connect("192.0.2.10:1".parse().unwrap()).await.unwrap_err();
+        assert_eq!(refused.kind(), ErrorKind::ConnectionRefused);
+
+        let unauthenticated: Socks5Proxy = format!("socks5://{}", proxy.address()).parse().unwrap();
+        assert!(unauthenticated.check().await.is_err());
+    }
+}
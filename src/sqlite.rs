@@ -0,0 +1,265 @@
+use crate::ScanResults;
+use anyhow::Result;
+use rusqlite::{params, Connection};
+
+/// One row per run in `scans`, its hosts in `hosts` and their open ports in
+/// `ports`, mirroring `ScanResults`, `HostResult` and `OpenPort`
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    interface TEXT NOT NULL,
+    subnet TEXT NOT NULL,
+    timeout_ms INTEGER NOT NULL,
+    total_hosts_scanned INTEGER NOT NULL,
+    active_hosts_found INTEGER NOT NULL,
+    discovery_methods TEXT NOT NULL,
+    omitted_open_ports INTEGER NOT NULL,
+    shard TEXT,
+    seed TEXT NOT NULL,
+    probes_sent INTEGER NOT NULL,
+    probe_budget INTEGER,
+    partial INTEGER NOT NULL,
+    timed_out INTEGER NOT NULL DEFAULT 0,
+    duration_ms INTEGER NOT NULL,
+    total_open_ports INTEGER NOT NULL,
+    total_ports_per_host INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS hosts (
+    id INTEGER PRIMARY KEY,
+    scan_id INTEGER NOT NULL REFERENCES scans(id),
+    ip TEXT NOT NULL,
+    hostname TEXT,
+    mac_address TEXT,
+    vendor TEXT,
+    is_gateway INTEGER NOT NULL DEFAULT 0,
+    discovery_method TEXT NOT NULL,
+    scan_duration_ms INTEGER NOT NULL,
+    rtt_ms REAL,
+    ttl INTEGER,
+    hops INTEGER,
+    os_guess TEXT,
+    tcp_ping_port INTEGER
+);
+CREATE TABLE IF NOT EXISTS ports (
+    id INTEGER PRIMARY KEY,
+    host_id INTEGER NOT NULL REFERENCES hosts(id),
+    port INTEGER NOT NULL,
+    protocol TEXT NOT NULL,
+    state TEXT NOT NULL,
+    banner TEXT NOT NULL,
+    banner_status TEXT,
+    correlated INTEGER NOT NULL,
+    service TEXT,
+    tls_subject_cn TEXT,
+    tls_issuer TEXT,
+    tls_not_after TEXT,
+    tls_expired INTEGER
+);
+CREATE INDEX IF NOT EXISTS hosts_scan_id ON hosts(scan_id);
+CREATE INDEX IF NOT EXISTS ports_host_id ON ports(host_id);
+";
+
+/// Columns added since the first schema, as (table, column, definition), so
+/// databases written by older versions get them before the next run lands
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("scans", "timed_out", "INTEGER NOT NULL DEFAULT 0"),
+    ("hosts", "hostname", "TEXT"),
+    ("hosts", "vendor", "TEXT"),
+    ("hosts", "is_gateway", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// Create the tables, or bring an older database's up to date
+fn migrate(conn: &Connection) -> Result<()> {
+    conn.execute_batch(SCHEMA)?;
+    for (table, column, definition) in ADDED_COLUMNS {
+        let present: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+            params![table, column],
+            |row| row.get(0),
+        )?;
+        if !present {
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+        }
+    }
+    Ok(())
+}
+
+/// Append the run to the SQLite database at `path`, creating the tables on
+/// first use. Everything goes in one transaction, so a crash never leaves a
+/// half-written run. Returns the new row's id in `scans`.
+pub fn write_sqlite(results: &ScanResults, path: &str) -> Result<i64> {
+    let mut conn = Connection::open(path)?;
+    migrate(&conn)?;
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        "INSERT INTO scans (timestamp, interface, subnet, timeout_ms, total_hosts_scanned, active_hosts_found,
+             discovery_methods, omitted_open_ports, shard, seed, probes_sent, probe_budget, partial, timed_out,
+             duration_ms, total_open_ports, total_ports_per_host)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+        params![
+            results.timestamp.to_rfc3339(),
+            results.interface,
+            results.subnet,
+            results.timeout_ms,
+            results.total_hosts_scanned,
+            results.active_hosts_found,
+            results.discovery_methods.join(","),
+            results.omitted_open_ports,
+            results.shard,
+            // Seeds use all 64 bits, more than an SQLite integer holds
+            results.seed.to_string(),
+            results.probes_sent,
+            results.probe_budget,
+            results.partial,
+            results.timed_out,
+            results.duration_ms,
+            results.total_open_ports,
+            results.total_ports_per_host,
+        ],
+    )?;
+    let scan_id = tx.last_insert_rowid();
+
+    for host in &results.hosts {
+        tx.execute(
+            "INSERT INTO hosts (scan_id, ip, hostname, mac_address, vendor, is_gateway, discovery_method,
+                 scan_duration_ms, rtt_ms, ttl, hops, os_guess, tcp_ping_port)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                scan_id,
+                host.ip,
+                host.hostname,
+                host.mac_address,
+                host.vendor,
+                host.is_gateway,
+                host.discovery_method,
+                host.scan_duration_ms,
+                host.rtt_ms,
+                host.ttl,
+                host.hops,
+                host.os_guess,
+                host.tcp_ping_port,
+            ],
+        )?;
+        let host_id = tx.last_insert_rowid();
+
+        for port in &host.open_ports {
+            let tls = port.tls.as_ref();
+            tx.execute(
+                "INSERT INTO ports (host_id, port, protocol, state, banner, banner_status, correlated, service,
+                     tls_subject_cn, tls_issuer, tls_not_after, tls_expired)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    host_id,
+                    port.port,
+                    port.protocol,
+                    port.state,
+                    port.banner,
+                    port.banner_status,
+                    port.correlated,
+                    port.service,
+                    tls.and_then(|tls| tls.subject_cn.as_deref()),
+                    tls.map(|tls| tls.issuer.as_str()),
+                    tls.map(|tls| tls.not_after.to_rfc3339()),
+                    tls.map(|tls| tls.expired),
+                ],
+            )?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(scan_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HostResult, OpenPort, FORMAT_VERSION};
+    use chrono::Utc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_sqlite_appends_runs() {
+        let results = ScanResults {
+            format_version: FORMAT_VERSION,
+            timestamp: Utc::now(),
+            interface: "eth0".to_string(),
+            subnet: "192.0.2.0/24".to_string(),
+            timeout_ms: 500,
+            total_hosts_scanned: 256,
+            active_hosts_found: 1,
+            discovery_methods: vec!["ICMP".to_string(), "TCP".to_string()],
+            hosts: vec![HostResult {
+                ip: "192.0.2.10".to_string(),
+                hostname: Some("router.lan".to_string()),
+                mac_address: Some("00:11:22:33:44:55".to_string()),
+                vendor: Some("Acme Networks".to_string()),
+                discovery_method: "ICMP".to_string(),
+                open_ports: vec![OpenPort {
+                    banner: "SSH-2.0-OpenSSH_9.6".to_string(),
+                    service: Some("ssh".to_string()),
+                    ..OpenPort::tcp(22)
+                }],
+                scan_duration_ms: 10,
+                rtt_ms: Some(0.4),
+                ttl: Some(64),
+                hops: Some(0),
+                os_guess: Some("Linux/Unix".to_string()),
+                is_gateway: true,
+                ..Default::default()
+            }],
+            seed: u64::MAX,
+            probes_sent: 3,
+            partial: true,
+            timed_out: true,
+            ..Default::default()
+        };
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("scans.db");
+        let path = path.to_str().unwrap();
+
+        let first = write_sqlite(&results, path).unwrap();
+        let second = write_sqlite(&results, path).unwrap();
+        assert_ne!(first, second);
+
+        let conn = Connection::open(path).unwrap();
+        let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!(count("SELECT COUNT(*) FROM scans"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM hosts"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM ports WHERE port = 22 AND service = 'ssh'"), 2);
+        let seed: String = conn.query_row("SELECT seed FROM scans WHERE id = ?1", [second], |row| row.get(0)).unwrap();
+        assert_eq!(seed, u64::MAX.to_string());
+        assert_eq!(count("SELECT COUNT(*) FROM scans WHERE partial AND timed_out"), 2);
+        let (hostname, vendor, is_gateway): (String, String, bool) = conn
+            .query_row("SELECT hostname, vendor, is_gateway FROM hosts WHERE scan_id = ?1", [second], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!((hostname.as_str(), vendor.as_str(), is_gateway), ("router.lan", "Acme Networks", true));
+    }
+
+    #[test]
+    fn test_write_sqlite_adds_new_columns_to_old_databases() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("old.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE hosts (id INTEGER PRIMARY KEY, scan_id INTEGER NOT NULL, ip TEXT NOT NULL);
+             CREATE TABLE scans (id INTEGER PRIMARY KEY, partial INTEGER NOT NULL);",
+        )
+        .unwrap();
+
+        migrate(&conn).unwrap();
+        // Running it again finds nothing left to add
+        migrate(&conn).unwrap();
+        let columns: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('hosts') WHERE name IN ('hostname', 'vendor', 'is_gateway')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(columns, 3);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::events::ScanEvent;
+use crate::services::service_name;
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tokio::net::{lookup_host, UdpSocket};
+
+/// `local0`, the facility left for site use
+const FACILITY: u8 = 16;
+const SEVERITY_NOTICE: u8 = 5;
+const SEVERITY_INFO: u8 = 6;
+
+/// SD-ID for the structured data, under the enterprise number RFC 5612
+/// reserves for documentation since the project has none of its own
+const SD_ID: &str = "angryether@32473";
+
+/// Longest banner excerpt in a message, in characters, so a message stays
+/// well inside one UDP datagram
+const MAX_BANNER_CHARS: usize = 200;
+
+/// Sends scan events to a syslog collector as RFC 5424 messages over UDP
+pub struct SyslogSink {
+    socket: UdpSocket,
+    hostname: String,
+    scan_started: DateTime<Utc>,
+}
+
+impl SyslogSink {
+    /// Resolve `target` (`HOST:PORT`) and connect a UDP socket to it
+    pub async fn connect(target: &str, scan_started: DateTime<Utc>) -> Result<Self> {
+        let addr = lookup_host(target)
+            .await
+            .with_context(|| format!("cannot resolve {} (expected HOST:PORT)", target))?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("{} resolved to no addresses", target))?;
+        let local: IpAddr = match addr {
+            std::net::SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            std::net::SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0)).await?;
+        socket.connect(addr).await?;
+        Ok(SyslogSink { socket, hostname: local_hostname(), scan_started })
+    }
+
+    /// Send the message for `event`, if it gets one
+    pub async fn send(&self, event: &ScanEvent) -> Result<()> {
+        if let Some(message) = format_message(event, &self.hostname, self.scan_started, Utc::now()) {
+            self.socket.send(message.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// This machine's name for the HOSTNAME field, or the nil value `-` where it can't be read
+fn local_hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_graphic()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// An SD-PARAM value with `"`, `\` and `]` escaped as RFC 5424 requires
+fn escape_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+/// The RFC 5424 message for an open port, a host's finished port scan or the
+/// end of the scan; discovery events get none. `scan_started` ties every
+/// message of one run together.
+pub fn format_message(event: &ScanEvent, hostname: &str, scan_started: DateTime<Utc>, now: DateTime<Utc>) -> Option<String> {
+    let mut params = vec![("scan", scan_started.to_rfc3339_opts(SecondsFormat::Secs, true))];
+    let (msgid, severity, text) = match event {
+        ScanEvent::PortOpen { ip, port, protocol, banner } => {
+            let service = service_name(*port, *protocol);
+            params.push(("ip", ip.to_string()));
+            params.push(("port", port.to_string()));
+            params.push(("protocol", protocol.name().to_string()));
+            if let Some(service) = service {
+                params.push(("service", service.to_string()));
+            }
+            let mut text = format!("{} {}/{} open", ip, port, protocol.name());
+            if let Some(service) = service {
+                text.push_str(&format!(" ({})", service));
+            }
+            // The first line only; a syslog message is one line
+            if let Some(line) = banner.lines().map(str::trim).find(|line| !line.is_empty()) {
+                text.push_str(&format!(": {}", line.chars().take(MAX_BANNER_CHARS).collect::<String>()));
+            }
+            ("port_open", SEVERITY_NOTICE, text)
+        }
+        ScanEvent::HostScanned { ip, open_ports } => {
+            params.push(("ip", ip.to_string()));
+            params.push(("open_ports", open_ports.to_string()));
+            ("host_scanned", SEVERITY_INFO, format!("{} scanned, {} open ports", ip, open_ports))
+        }
+        ScanEvent::ScanComplete { hosts_scanned, active_hosts, open_ports, duration_ms } => {
+            params.push(("hosts_scanned", hosts_scanned.to_string()));
+            params.push(("active_hosts", active_hosts.to_string()));
+            params.push(("open_ports", open_ports.to_string()));
+            let text = format!(
+                "scan complete: {} active of {} hosts, {} open ports in {}ms",
+                active_hosts, hosts_scanned, open_ports, duration_ms
+            );
+            ("scan_complete", SEVERITY_INFO, text)
+        }
+        ScanEvent::HostDiscovered { .. } => return None,
+    };
+    let structured: String = params.iter().map(|(name, value)| format!(" {}=\"{}\"", name, escape_param(value))).collect();
+    Some(format!(
+        "<{}>1 {} {} angryether {} {} [{}{}] {}",
+        FACILITY * 8 + severity,
+        now.to_rfc3339_opts(SecondsFormat::Millis, true),
+        hostname,
+        std::process::id(),
+        msgid,
+        SD_ID,
+        structured,
+        text
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portscan::Protocol;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_format_message() {
+        let started = Utc.with_ymd_and_hms(2026, 10, 14, 9, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2026, 10, 14, 9, 0, 5).unwrap();
+        let event = ScanEvent::PortOpen {
+            ip: Ipv4Addr::new(192, 0, 2, 10).into(),
+            port: 22,
+            protocol: Protocol::Tcp,
+            banner: "SSH-2.0-OpenSSH_9.6\r\n".to_string(),
+        };
+        assert_eq!(
+            format_message(&event, "scanner", started, now).unwrap(),
+            format!(
+                "<133>1 2026-10-14T09:00:05.000Z scanner angryether {} port_open [angryether@32473 scan=\"2026-10-14T09:00:00Z\" \
+                 ip=\"192.0.2.10\" port=\"22\" protocol=\"tcp\" service=\"ssh\"] 192.0.2.10 22/tcp open (ssh): SSH-2.0-OpenSSH_9.6",
+                std::process::id()
+            )
+        );
+
+        let event = ScanEvent::HostDiscovered { ip: Ipv4Addr::new(192, 0, 2, 10).into(), method: crate::discovery::DiscoveryMethod::Arp, mac: None };
+        assert_eq!(format_message(&event, "scanner", started, now), None);
+        assert_eq!(escape_param(r#"a"b\c]"#), r#"a\"b\\c\]"#);
+    }
+
+    #[tokio::test]
+    async fn test_syslog_sink_sends_datagrams() {
+        let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sink = SyslogSink::connect(&collector.local_addr().unwrap().to_string(), Utc::now()).await.unwrap();
+        sink.send(&ScanEvent::HostScanned { ip: Ipv4Addr::new(192, 0, 2, 10).into(), open_ports: 3 }).await.unwrap();
+
+        let mut buffer = [0; 1024];
+        let length = collector.recv(&mut buffer).await.unwrap();
+        let message = String::from_utf8_lossy(&buffer[..length]);
+        assert!(message.starts_with("<134>1 "), "{}", message);
+        assert!(message.ends_with("192.0.2.10 scanned, 3 open ports"), "{}", message);
+
+        assert!(SyslogSink::connect("no-port-here", Utc::now()).await.is_err());
+    }
+}
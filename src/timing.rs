@@ -0,0 +1,216 @@
+use crate::rng::{ip_key, rng_for};
+use anyhow::Result;
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// An nmap-style `-T` preset for timeouts, concurrency and ARP pacing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingProfile {
+    pub level: u8,
+    pub name: &'static str,
+    pub ping_timeout_ms: u64,
+    pub connect_timeout_ms: u64,
+    /// Hosts pinged at once
+    pub ping_concurrency: usize,
+    /// Ports of one host probed at once
+    pub port_concurrency: usize,
+    /// Hosts port scanned at once
+    pub host_concurrency: usize,
+    /// Pause after each ARP request
+    pub arp_burst_delay: Duration,
+    /// Most random delay before each probe; zero leaves probes unjittered
+    pub jitter: Duration,
+}
+
+const fn profile(
+    level: u8,
+    name: &'static str,
+    ping_timeout_ms: u64,
+    connect_timeout_ms: u64,
+    ping_concurrency: usize,
+    port_concurrency: usize,
+    arp_burst_delay: Duration,
+) -> TimingProfile {
+    TimingProfile {
+        level,
+        name,
+        ping_timeout_ms,
+        connect_timeout_ms,
+        ping_concurrency,
+        port_concurrency,
+        host_concurrency: 1,
+        arp_burst_delay,
+        jitter: Duration::ZERO,
+    }
+}
+
+impl TimingProfile {
+    /// How many hosts the profile port scans at once
+    const fn scanning(self, host_concurrency: usize) -> TimingProfile {
+        TimingProfile { host_concurrency, ..self }
+    }
+
+    /// The stealthy profiles also jitter every probe
+    const fn jittered(self, jitter: Duration) -> TimingProfile {
+        TimingProfile { jitter, ..self }
+    }
+}
+
+/// T0 through T5; keep the `--timing` long help in step with these values
+pub const PROFILES: [TimingProfile; 6] = [
+    profile(0, "paranoid", 5000, 5000, 1, 1, Duration::from_millis(100)).scanning(1).jittered(Duration::from_millis(500)),
+    profile(1, "sneaky", 3000, 3000, 5, 10, Duration::from_millis(10)).scanning(2).jittered(Duration::from_millis(100)),
+    profile(2, "polite", 1000, 2000, 20, 100, Duration::from_millis(1)).scanning(5),
+    profile(3, "normal", 500, 1000, 50, 1000, Duration::from_micros(100)).scanning(10),
+    profile(4, "aggressive", 250, 500, 200, 2000, Duration::from_micros(20)).scanning(20),
+    profile(5, "insane", 100, 250, 500, 5000, Duration::ZERO).scanning(50),
+];
+
+impl Default for TimingProfile {
+    fn default() -> Self {
+        PROFILES[3]
+    }
+}
+
+impl FromStr for TimingProfile {
+    type Err = anyhow::Error;
+
+    /// A level `0`-`5` (optionally `T3` style) or a profile name
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        let level = s.strip_prefix('t').unwrap_or(&s);
+        PROFILES
+            .iter()
+            .find(|profile| profile.name == s || profile.level.to_string() == level)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("unknown timing profile '{}' (expected 0-5 or paranoid, sneaky, polite, normal, aggressive, insane)", s))
+    }
+}
+
+/// A random pause of up to `max` before each probe (`--jitter`), so probe
+/// timing has no rhythm to fingerprint. Each probe's delay comes from the run
+/// seed, so a replayed scan pauses the same way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Jitter {
+    max: Duration,
+    seed: u64,
+}
+
+impl Jitter {
+    pub fn new(max: Duration, seed: u64) -> Self {
+        Jitter { max, seed }
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// The delay for probe number `probe` of `stream` (e.g. a port) to `ip`
+    pub fn delay(&self, stream: &str, ip: IpAddr, probe: u64) -> Duration {
+        if self.max.is_zero() {
+            return Duration::ZERO;
+        }
+        let micros = self.max.as_micros() as u64;
+        Duration::from_micros(rng_for(self.seed, stream, ip_key(ip) ^ (probe << 32)).gen_range(0..=micros))
+    }
+
+    pub async fn wait(&self, stream: &str, ip: IpAddr, probe: u64) {
+        let delay = self.delay(stream, ip, probe);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// At most `per_second` probes to any one host (`--max-rate-per-host`): a
+/// token bucket per IP that holds a single token, so probes to a host are
+/// evenly spaced however many of its ports are in flight, while every other
+/// host keeps its own pace. The default has no limit.
+#[derive(Debug, Default)]
+pub struct HostRateLimit {
+    /// Zero when unlimited
+    interval: Duration,
+    /// When each host's bucket next holds a token
+    next_token: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl HostRateLimit {
+    pub fn new(per_second: u32) -> Self {
+        HostRateLimit { interval: Duration::from_secs(1) / per_second.max(1), next_token: Mutex::default() }
+    }
+
+    /// How long from `now` a probe to `ip` waits for its token, taking it
+    fn reserve(&self, ip: IpAddr, now: Instant) -> Duration {
+        if self.interval.is_zero() {
+            return Duration::ZERO;
+        }
+        let mut next_token = self.next_token.lock().unwrap();
+        let slot = next_token.get(&ip).map_or(now, |next| (*next).max(now));
+        next_token.insert(ip, slot + self.interval);
+        slot - now
+    }
+
+    pub async fn wait(&self, ip: IpAddr) {
+        let delay = self.reserve(ip, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing_profiles() {
+        assert_eq!("3".parse::<TimingProfile>().unwrap(), TimingProfile::default());
+        assert_eq!("T5".parse::<TimingProfile>().unwrap().name, "insane");
+        assert_eq!("Paranoid".parse::<TimingProfile>().unwrap().port_concurrency, 1);
+        // T0 port scans one host at a time
+        assert_eq!("0".parse::<TimingProfile>().unwrap().host_concurrency, 1);
+        assert_eq!(TimingProfile::default().host_concurrency, 10);
+        assert!("6".parse::<TimingProfile>().is_err());
+
+        // Faster profiles never wait longer or probe less in parallel
+        for pair in PROFILES.windows(2) {
+            assert!(pair[1].ping_timeout_ms <= pair[0].ping_timeout_ms);
+            assert!(pair[1].port_concurrency >= pair[0].port_concurrency);
+            assert!(pair[1].host_concurrency >= pair[0].host_concurrency);
+            assert!(pair[1].arp_burst_delay <= pair[0].arp_burst_delay);
+            assert!(pair[1].jitter <= pair[0].jitter);
+        }
+    }
+
+    #[test]
+    fn test_jitter() {
+        let ip = IpAddr::from([192, 0, 2, 10]);
+        assert_eq!(Jitter::default().delay("port", ip, 80), Duration::ZERO);
+
+        let jitter = Jitter::new(Duration::from_millis(50), 42);
+        let delays: Vec<Duration> = (0..100).map(|port| jitter.delay("port", ip, port)).collect();
+        assert!(delays.iter().all(|delay| *delay <= Duration::from_millis(50)));
+        // Per probe, not per host, yet the same for the same seed
+        assert!(delays.windows(2).any(|pair| pair[0] != pair[1]));
+        assert_eq!(delays[7], Jitter::new(Duration::from_millis(50), 42).delay("port", ip, 7));
+    }
+
+    #[test]
+    fn test_host_rate_limit() {
+        let (first, second) = (IpAddr::from([192, 0, 2, 10]), IpAddr::from([192, 0, 2, 11]));
+        let now = Instant::now();
+        assert_eq!(HostRateLimit::default().reserve(first, now), Duration::ZERO);
+
+        let limit = HostRateLimit::new(4);
+        let delays: Vec<Duration> = (0..3).map(|_| limit.reserve(first, now)).collect();
+        assert_eq!(delays, [Duration::ZERO, Duration::from_millis(250), Duration::from_millis(500)]);
+        // Another host has its own bucket, and an idle host's token is only one
+        assert_eq!(limit.reserve(second, now), Duration::ZERO);
+        assert_eq!(limit.reserve(second, now + Duration::from_secs(5)), Duration::ZERO);
+        assert_eq!(limit.reserve(second, now + Duration::from_secs(5)), Duration::from_millis(250));
+    }
+}
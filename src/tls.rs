@@ -0,0 +1,184 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, Error, SignatureScheme};
+use tokio_rustls::TlsConnector;
+use x509_parser::prelude::*;
+
+/// Certificates expiring within this many days count as expiring soon
+pub const EXPIRY_WARNING_DAYS: i64 = 30;
+
+/// What a server's leaf certificate says about itself
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct TlsInfo {
+    pub subject_cn: Option<String>,
+    pub issuer: String,
+    pub not_after: DateTime<Utc>,
+    /// Whole days from the scan until `not_after`, negative once expired
+    pub days_remaining: i64,
+    pub expired: bool,
+}
+
+impl TlsInfo {
+    pub fn expires_soon(&self) -> bool {
+        !self.expired && self.days_remaining <= EXPIRY_WARNING_DAYS
+    }
+
+    /// One line for the banner, expiry first so it survives truncation
+    pub fn summary(&self) -> String {
+        let subject = self.subject_cn.as_deref().unwrap_or("(no CN)");
+        let date = self.not_after.format("%Y-%m-%d");
+        let expiry = if self.expired {
+            format!("EXPIRED {}", date)
+        } else if self.expires_soon() {
+            format!("expires {} (in {} days)", date, self.days_remaining)
+        } else {
+            format!("expires {}", date)
+        };
+        format!("TLS CN={}, {}, issuer: {}", subject, expiry, self.issuer)
+    }
+}
+
+/// Accepts every certificate: the scan reads self-signed and expired
+/// certificates rather than trusting them. Handshake signatures are still
+/// checked so the session is a real one.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn connector() -> &'static TlsConnector {
+    static CONNECTOR: OnceLock<TlsConnector> = OnceLock::new();
+    CONNECTOR.get_or_init(|| {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default TLS versions")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    })
+}
+
+/// Run a TLS handshake on `stream` and read the server's leaf certificate,
+/// returning what it says and its DER bytes
+pub async fn inspect_certificate(stream: &mut TcpStream, wait: Duration) -> Result<(TlsInfo, Vec<u8>)> {
+    let server_name = ServerName::IpAddress(stream.peer_addr()?.ip().into());
+    let session = timeout(wait, connector().connect(server_name, stream)).await??;
+    let der = session
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|chain| chain.first())
+        .ok_or_else(|| anyhow::anyhow!("server sent no certificate"))?
+        .to_vec();
+    Ok((tls_info(&der, Utc::now())?, der))
+}
+
+fn tls_info(der: &[u8], now: DateTime<Utc>) -> Result<TlsInfo> {
+    let (_, certificate) =
+        parse_x509_certificate(der).map_err(|e| anyhow::anyhow!("unreadable certificate: {}", e))?;
+    let subject_cn = certificate
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map(String::from);
+    let not_after = DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| anyhow::anyhow!("certificate expiry out of range"))?;
+
+    Ok(TlsInfo {
+        subject_cn,
+        issuer: certificate.issuer().to_string(),
+        not_after,
+        days_remaining: (not_after - now).num_days(),
+        expired: not_after < now,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, DnType, KeyPair};
+    use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    #[tokio::test]
+    async fn test_inspect_self_signed_certificate() {
+        let mut params = CertificateParams::new(vec!["printer.local".to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "printer.local");
+        params.not_after = rcgen::date_time_ymd(2020, 6, 1);
+        let key = KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key).unwrap();
+
+        let config = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.der().clone()], PrivateKeyDer::Pkcs8(key.serialize_der().into()))
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(stream).await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let (info, der) = inspect_certificate(&mut stream, Duration::from_secs(2)).await.unwrap();
+        assert_eq!(der, certificate.der().to_vec());
+        assert_eq!(info.subject_cn.as_deref(), Some("printer.local"));
+        assert_eq!(info.issuer, "CN=printer.local");
+        assert!(info.expired && info.days_remaining < 0);
+        assert!(info.summary().starts_with("TLS CN=printer.local, EXPIRED 2020-06-01"));
+
+        let earlier = DateTime::parse_from_rfc3339("2020-05-20T00:00:00Z").unwrap().with_timezone(&Utc);
+        let info = tls_info(&der, earlier).unwrap();
+        assert!(!info.expired && info.expires_soon());
+        assert_eq!(info.days_remaining, 12);
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Track id for the top-level scan phases; per-host spans use their own ids
+pub const PHASE_TRACK: u64 = 0;
+
+#[derive(Serialize, Debug, Clone)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    /// Microseconds since the trace started
+    ts: u64,
+    pid: u32,
+    tid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>,
+}
+
+/// Collects begin/end events in the Chrome trace format (`chrome://tracing`,
+/// Perfetto). Recording is a no-op unless the trace is enabled.
+pub struct TimingTrace {
+    enabled: bool,
+    origin: Instant,
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+impl TimingTrace {
+    pub fn new(enabled: bool) -> Self {
+        TimingTrace {
+            enabled,
+            origin: Instant::now(),
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn record(&self, name: &str, cat: &'static str, ph: &'static str, tid: u64, args: Option<serde_json::Value>) {
+        if !self.enabled {
+            return;
+        }
+        let event = TraceEvent {
+            name: name.to_string(),
+            cat,
+            ph,
+            ts: self.origin.elapsed().as_micros() as u64,
+            pid: std::process::id(),
+            tid,
+            args,
+        };
+        self.events.lock().unwrap().push(event);
+    }
+
+    /// Label a track in the viewer, e.g. with the host it belongs to
+    pub fn name_track(&self, tid: u64, name: &str) {
+        self.record("thread_name", "__metadata", "M", tid, Some(json!({ "name": name })));
+    }
+
+    pub fn begin(&self, name: &str, cat: &'static str, tid: u64) {
+        self.record(name, cat, "B", tid, None);
+    }
+
+    pub fn end(&self, name: &str, cat: &'static str, tid: u64) {
+        self.record(name, cat, "E", tid, None);
+    }
+
+    fn to_json(&self) -> Result<String> {
+        let events = self.events.lock().unwrap();
+        Ok(serde_json::to_string(&json!({
+            "traceEvents": *events,
+            "displayTimeUnit": "ms",
+        }))?)
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_events() {
+        let trace = TimingTrace::new(true);
+        trace.name_track(1, "192.0.2.10");
+        trace.begin("discovery", "phase", PHASE_TRACK);
+        trace.end("discovery", "phase", PHASE_TRACK);
+
+        let parsed: serde_json::Value = serde_json::from_str(&trace.to_json().unwrap()).unwrap();
+        let events = parsed["traceEvents"].as_array().unwrap();
+        let phases: Vec<&str> = events.iter().map(|e| e["ph"].as_str().unwrap()).collect();
+        assert_eq!(phases, ["M", "B", "E"]);
+        assert_eq!(events[0]["args"]["name"], "192.0.2.10");
+        assert!(events[1]["ts"].as_u64() <= events[2]["ts"].as_u64());
+        assert!(events[1].get("args").is_none());
+
+        let disabled = TimingTrace::new(false);
+        disabled.begin("discovery", "phase", PHASE_TRACK);
+        assert!(disabled.events.lock().unwrap().is_empty());
+    }
+}